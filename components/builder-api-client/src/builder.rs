@@ -7,9 +7,12 @@ use crate::{error::{Error,
                                  PackageIdent,
                                  PackageTarget},
                        ChannelIdent},
-            hab_http::ApiClient,
+            hab_http::{ApiClient,
+                       ApiClientOptions},
             response::{err_from_response,
                        ResponseExt},
+            throttle::{Throttle,
+                       ThrottledReader},
             BoxedClient,
             BuildOnUpload,
             BuilderAPIProvider,
@@ -20,15 +23,21 @@ use crate::{error::{Error,
             ReverseDependencies,
             SchedulerResponse};
 use broadcast::BroadcastWriter;
-use reqwest::{header::CONTENT_LENGTH,
+use reqwest::{header::{CONTENT_LENGTH,
+                       CONTENT_RANGE,
+                       ETAG,
+                       IF_NONE_MATCH,
+                       RANGE},
               Body,
               IntoUrl,
               RequestBuilder,
               StatusCode};
 use std::{fs::{self,
-               File},
+               File,
+               OpenOptions},
           io::{self,
-               Read},
+               Read,
+               Write},
           path::{Path,
                  PathBuf},
           string::ToString};
@@ -113,7 +122,9 @@ pub struct OriginChannelIdent {
     pub name: String,
 }
 
-pub struct BuilderAPIClient(ApiClient);
+/// A client for the Builder API. The throttle, if there is one, holds back its package downloads,
+/// and the flag is whether it's `revalidating` them.
+pub struct BuilderAPIClient(ApiClient, Option<Throttle>, bool);
 
 impl BuilderAPIClient {
     pub fn new<U>(endpoint: U,
@@ -122,18 +133,53 @@ impl BuilderAPIClient {
                   fs_root_path: Option<&Path>)
                   -> Result<Self>
         where U: IntoUrl
+    {
+        Self::with_options(endpoint,
+                           product,
+                           version,
+                           fs_root_path,
+                           &ApiClientOptions::default())
+    }
+
+    /// Like `new`, but with `options` overriding the defaults of the underlying HTTP client.
+    pub fn with_options<U>(endpoint: U,
+                           product: &str,
+                           version: &str,
+                           fs_root_path: Option<&Path>,
+                           options: &ApiClientOptions)
+                           -> Result<Self>
+        where U: IntoUrl
     {
         let mut endpoint = endpoint.into_url().map_err(Error::ReqwestError)?;
         if !endpoint.cannot_be_a_base() && endpoint.path() == "/" {
             endpoint.set_path(DEFAULT_API_PATH);
         }
-        let client = BuilderAPIClient(
-            ApiClient::new(endpoint, product, version, fs_root_path)
-                .map_err(Error::HabitatHttpClient)?,
-        );
+        let client =
+            BuilderAPIClient(ApiClient::with_options(endpoint,
+                                                     product,
+                                                     version,
+                                                     fs_root_path,
+                                                     options).map_err(Error::HabitatHttpClient)?,
+                             None,
+                             false);
         Ok(client)
     }
 
+    /// Holds package downloads to the rate `throttle` allows. Those of every client sharing the
+    /// `throttle` are held to it together.
+    pub fn throttled(mut self, throttle: Throttle) -> Self {
+        self.1 = Some(throttle);
+        self
+    }
+
+    /// Keeps the ETag Builder sends with each package downloaded in a file next to it (see
+    /// `etag_path`), and asks for a package that's already there only if it has changed since.
+    /// One that hasn't is answered with a `NOT_MODIFIED` `APIError`, and left as it is.
+    pub fn revalidating(mut self) -> Self {
+        self.2 = true;
+        self
+    }
+
     pub fn create<U>(endpoint: U,
                      product: &str,
                      version: &str,
@@ -164,23 +210,107 @@ impl BuilderAPIClient {
         fs::create_dir_all(&dst_path)?;
         let file_name = resp.get_header(X_FILENAME)?;
         let dst_file_path = dst_path.join(file_name);
+        let size = content_length(&resp)?;
         let w = AtomicWriter::new(&dst_file_path)?;
         w.with_writer(|mut f| {
-             // There will be no CONTENT_LENGTH header if an on prem
-             // builder is using chunked transfer encoding
-             match (progress, resp.get_header(CONTENT_LENGTH)) {
-                 (Some(mut progress), Ok(header)) => {
-                     let size = header.parse().map_err(Error::ParseIntError)?;
+             let received = match (progress, size) {
+                 (Some(mut progress), Some(size)) => {
                      progress.size(size);
                      let mut writer = BroadcastWriter::new(&mut f, progress);
-                     io::copy(&mut resp, &mut writer).map_err(Error::IO)
+                     io::copy(&mut resp, &mut writer).map_err(Error::IO)?
                  }
-                 _ => io::copy(&mut resp, &mut f).map_err(Error::IO),
-             }
+                 _ => io::copy(&mut resp, &mut f).map_err(Error::IO)?,
+             };
+             check_length(&dst_file_path, size, received)
          })?;
         Ok(dst_file_path)
     }
 
+    /// Like `download`, but for a file whose name is known up front. The body is written to a
+    /// `.part` file alongside `dst_file_path`, which is left behind if the transfer fails. The
+    /// next call then asks for just the rest of the file with a `Range` request, and starts over
+    /// if the server sends the whole file anyway, says the range doesn't fit the file, or sends
+    /// some other part of it than the rest. When revalidating, a file that's already there is
+    /// only sent again if its ETag no longer matches. `request` makes the request afresh each time one is needed.
+    fn download_resumable(&self,
+                          request: &dyn Fn() -> RequestBuilder,
+                          dst_file_path: &Path,
+                          token: Option<&str>,
+                          progress: Option<<BuilderAPIClient as BuilderAPIProvider>::Progress>)
+                          -> Result<PathBuf> {
+        let part_path = part_path(dst_file_path);
+        let offset = fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+        debug!("Downloading file to path: {}, resuming from byte {}",
+               dst_file_path.display(),
+               offset);
+
+        let rb = if offset > 0 {
+            request().header(RANGE, format!("bytes={}-", offset))
+        } else {
+            request()
+        };
+        let etag_path = etag_path(dst_file_path);
+        let rb = match fs::read_to_string(&etag_path) {
+            Ok(ref etag) if self.2 && dst_file_path.is_file() => {
+                rb.header(IF_NONE_MATCH, etag.trim())
+            }
+            _ => rb,
+        };
+        let mut resp = self.maybe_add_authz(rb, token).send()?;
+        // Appending a part that doesn't start where the `.part` ends would corrupt the artifact
+        let misplaced = resp.status() == StatusCode::PARTIAL_CONTENT
+                        && content_range_start(&resp) != Some(offset);
+        if (resp.status() == StatusCode::RANGE_NOT_SATISFIABLE || misplaced) && offset > 0 {
+            // What we have doesn't line up with what the server has, such as a `.part` that's
+            // already the whole file, so start again from scratch. Without the `.part` there's
+            // no range to ask for, so this only happens once.
+            debug!("Starting {} over, since the server can't send the rest of it",
+                   dst_file_path.display());
+            fs::remove_file(&part_path)?;
+            return self.download_resumable(request, dst_file_path, token, progress);
+        }
+        resp.ok_if(&[StatusCode::OK, StatusCode::PARTIAL_CONTENT])?;
+        if misplaced {
+            return Err(Error::InvalidHeader(CONTENT_RANGE.as_str().to_string()));
+        }
+        let etag = resp.get_header(ETAG).ok().map(String::from);
+
+        if let Some(parent) = dst_file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut f = if resp.status() == StatusCode::PARTIAL_CONTENT {
+            OpenOptions::new().create(true)
+                              .append(true)
+                              .open(&part_path)?
+        } else {
+            File::create(&part_path)?
+        };
+        // For a partial response, this is the length of just the part that's sent
+        let size = content_length(&resp)?;
+        let mut body = ThrottledReader::new(&mut resp, self.1.as_ref());
+        let received = match (progress, size) {
+            (Some(mut progress), Some(size)) => {
+                progress.size(size);
+                let mut writer = BroadcastWriter::new(&mut f, progress);
+                io::copy(&mut body, &mut writer).map_err(Error::IO)?
+            }
+            _ => io::copy(&mut body, &mut f).map_err(Error::IO)?,
+        };
+        // What did arrive is kept in the `.part` file, for the next attempt to pick up from
+        check_length(dst_file_path, size, received)?;
+        f.sync_all()?;
+        fs::rename(&part_path, dst_file_path)?;
+        if self.2 {
+            match etag {
+                Some(etag) => fs::write(&etag_path, etag)?,
+                // An ETag left over from before would no longer be for this file
+                None if etag_path.is_file() => fs::remove_file(&etag_path)?,
+                None => {}
+            }
+        }
+        Ok(dst_file_path.to_path_buf())
+    }
+
     fn seach_package_with_range(&self,
                                 search_term: &str,
                                 token: Option<&str>,
@@ -690,6 +820,7 @@ impl BuilderAPIProvider for BuilderAPIClient {
     /// * Package cannot be found
     /// * Remote Builder is not available
     /// * File cannot be created and written to
+    /// * Package is already there and hasn't changed, for a revalidating client
     fn fetch_package(&self,
                      (ident, target): (&PackageIdent, PackageTarget),
                      token: Option<&str>,
@@ -704,12 +835,38 @@ impl BuilderAPIProvider for BuilderAPIClient {
             return Err(Error::IdentNotFullyQualified);
         }
 
+        let request = || {
+            self.0.get_with_custom_url(&package_download(ident), |u| {
+                      u.set_query(Some(&format!("target={}", target)))
+                  })
+        };
+        let dst_file_path = dst_path.join(ident.archive_name_with_target(target)?);
+
+        self.download_resumable(&request, &dst_file_path, token, progress)
+            .map(PackageArchive::new)
+    }
+
+    fn stream_package(&self,
+                      (ident, target): (&PackageIdent, PackageTarget),
+                      token: Option<&str>,
+                      out: &mut dyn Write)
+                      -> Result<u64> {
+        if !ident.fully_qualified() {
+            return Err(Error::IdentNotFullyQualified);
+        }
+
         let req_builder = self.0.get_with_custom_url(&package_download(ident), |u| {
                                     u.set_query(Some(&format!("target={}", target)))
                                 });
-
-        self.download(req_builder, dst_path.as_ref(), token, progress)
-            .map(PackageArchive::new)
+        let mut resp = self.maybe_add_authz(req_builder, token).send()?;
+        resp.ok_if(&[StatusCode::OK])?;
+        let size = content_length(&resp)?;
+        let mut body = ThrottledReader::new(&mut resp, self.1.as_ref());
+        let received = io::copy(&mut body, out).map_err(Error::IO)?;
+        check_length(Path::new(&ident.archive_name_with_target(target)?),
+                     size,
+                     received)?;
+        Ok(received)
     }
 
     /// Checks whether a specified package exists
@@ -741,6 +898,37 @@ impl BuilderAPIProvider for BuilderAPIClient {
             .ok_if(&[StatusCode::OK])
     }
 
+    /// Returns the size of a package's artifact, without downloading it. That's `None` if
+    /// Builder doesn't say, as it won't when it's using chunked transfer encoding.
+    ///
+    /// The package ident must be fully qualified
+    ///
+    /// # Failures
+    ///
+    /// * Package cannot be found
+    /// * Remote Builder is not available
+    fn package_size(&self,
+                    (ident, target): (&PackageIdent, PackageTarget),
+                    token: Option<&str>)
+                    -> Result<Option<u64>> {
+        if !ident.fully_qualified() {
+            return Err(Error::IdentNotFullyQualified);
+        }
+
+        let mut resp =
+            self.maybe_add_authz(self.0.head_with_custom_url(&package_download(ident), |u| {
+                                           u.set_query(Some(&format!("target={}", target)))
+                                       }),
+                                 token)
+                .send()?;
+        resp.ok_if(&[StatusCode::OK])?;
+
+        match resp.get_header(CONTENT_LENGTH) {
+            Ok(header) => Ok(Some(header.parse().map_err(Error::ParseIntError)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
     /// Returns a package ident struct for the latest package. Arguably should be renamed
     ///
     /// An optional version can be specified which will scope the release returned to the latest
@@ -802,6 +990,49 @@ impl BuilderAPIProvider for BuilderAPIClient {
         Ok(package)
     }
 
+    /// Returns every release of a package for a target that's in a channel, fetching as many
+    /// pages of results as it takes. Only the releases of its version are listed for an ident
+    /// with one.
+    ///
+    /// # Failures
+    ///
+    /// * Remote depot unavailable
+    fn list_package_releases(&self,
+                             (package, target): (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Vec<PackageIdent>> {
+        debug!("Listing the releases of {} for {} in the {} channel",
+               package, target, channel);
+
+        let path = channel_package_path(channel, package);
+        let mut releases = Vec::new();
+        loop {
+            let range = releases.len();
+            let req = self.0.get_with_custom_url(&path, |url| {
+                                url.set_query(Some(&format!("target={}&range={}", target, range)));
+                            });
+            let mut resp = self.maybe_add_authz(req, token).send()?;
+            debug!("Response Status: {:?}", resp.status());
+
+            let more_to_come = match resp.status() {
+                StatusCode::OK => false,
+                StatusCode::PARTIAL_CONTENT => true,
+                _ => return Err(err_from_response(&mut resp)),
+            };
+            let mut encoded = String::new();
+            resp.read_to_string(&mut encoded)
+                .map_err(Error::BadResponseBody)?;
+            let mut results: PackageResults<PackageIdent> = serde_json::from_str(&encoded)?;
+            let last_page = results.data.is_empty();
+            releases.append(&mut results.data);
+
+            if !more_to_come || last_page {
+                return Ok(releases);
+            }
+        }
+    }
+
     /// Upload a package to a remote Builder.
     ///
     /// # Failures
@@ -1087,6 +1318,43 @@ impl BuilderAPIProvider for BuilderAPIClient {
         self.search_package_impl(search_term, limit, token, Self::seach_package_with_range)
     }
 
+    /// Returns an `origin/name` ident for every package in an origin, fetching as many pages of
+    /// results as it takes
+    ///
+    /// # Failures
+    ///
+    /// * Remote depot unavailable
+    fn list_origin_packages(&self, origin: &str, token: Option<&str>) -> Result<Vec<PackageIdent>> {
+        debug!("Listing the packages in origin {}", origin);
+
+        let path = format!("depot/pkgs/{}", origin);
+        let mut packages = Vec::new();
+        loop {
+            let range = packages.len();
+            let req = self.0.get_with_custom_url(&path, |url| {
+                                url.set_query(Some(&format!("range={}&distinct=true", range)));
+                            });
+            let mut resp = self.maybe_add_authz(req, token).send()?;
+            debug!("Response Status: {:?}", resp.status());
+
+            let more_to_come = match resp.status() {
+                StatusCode::OK => false,
+                StatusCode::PARTIAL_CONTENT => true,
+                _ => return Err(err_from_response(&mut resp)),
+            };
+            let mut encoded = String::new();
+            resp.read_to_string(&mut encoded)
+                .map_err(Error::BadResponseBody)?;
+            let mut results: PackageResults<PackageIdent> = serde_json::from_str(&encoded)?;
+            let last_page = results.data.is_empty();
+            packages.append(&mut results.data);
+
+            if !more_to_come || last_page {
+                return Ok(packages);
+            }
+        }
+    }
+
     /// Return a list of channels for a given origin
     ///
     /// # Failures
@@ -1119,6 +1387,53 @@ impl BuilderAPIProvider for BuilderAPIClient {
     }
 }
 
+/// How long the body of `resp` says it is. There's no `Content-Length` if an on prem Builder is
+/// using chunked transfer encoding.
+fn content_length(resp: &reqwest::Response) -> Result<Option<u64>> {
+    match resp.get_header(CONTENT_LENGTH) {
+        Ok(header) => Ok(Some(header.parse().map_err(Error::ParseIntError)?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Where the part a partial response has starts in the whole file, going by its `Content-Range`,
+/// such as `bytes 1000-4999/5000`. One without a byte range has no start to go by.
+fn content_range_start(resp: &reqwest::Response) -> Option<u64> {
+    let range = resp.get_header(CONTENT_RANGE).ok()?;
+    let mut parts = range.trim().splitn(2, ' ');
+    if parts.next()? != "bytes" {
+        return None;
+    }
+    parts.next()?.split('-').next()?.trim().parse().ok()
+}
+
+/// A connection that's cut short can still end the body cleanly, so `received` is checked
+/// against the `Content-Length` the response had, if it had one.
+fn check_length(path: &Path, expected: Option<u64>, received: u64) -> Result<()> {
+    match expected {
+        Some(expected) if expected != received => {
+            Err(Error::IncompleteDownload(path.to_path_buf(),
+                                          expected,
+                                          received))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Where `download_resumable` keeps a download until it's complete.
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Where a revalidating client keeps the ETag of the file it downloaded to `path`.
+pub fn etag_path(path: &Path) -> PathBuf {
+    let mut etag = path.as_os_str().to_owned();
+    etag.push(".etag");
+    PathBuf::from(etag)
+}
+
 fn origin_keys_path(origin: &str) -> String { format!("depot/origins/{}/keys", origin) }
 
 fn package_download(package: &PackageIdent) -> String {
@@ -1178,6 +1493,9 @@ fn channel_package_demote(channel: &ChannelIdent, package: &PackageIdent) -> Str
 mod tests {
     use super::*;
     use serde_json;
+    use std::{net::TcpListener,
+              thread};
+    use tempfile::TempDir;
 
     #[test]
     fn json_round_trip_u64_fields() {
@@ -1304,4 +1622,89 @@ mod tests {
                       .expect("valid search");
         assert_eq!(r.1, count);
     }
+
+    /// Answers `requests` requests for an artifact that's `body`, the way Builder does: with the
+    /// whole of it, unless a range is asked for, which `ranged` answers instead. Returns the URL
+    /// to ask.
+    fn serve_artifact(body: &'static str, requests: usize, ranged: fn(&str) -> String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let response = if request.contains("\r\nrange:") {
+                    ranged(body)
+                } else {
+                    format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                            body.len(),
+                            body)
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    /// Says a range doesn't fit, as for a `.part` that's already the whole artifact
+    fn not_satisfiable(_: &str) -> String {
+        String::from("HTTP/1.1 416 Range Not Satisfiable\r\ncontent-length: 0\r\n\
+                      connection: close\r\n\r\n")
+    }
+
+    /// Sends the whole of `body` as a part, whatever range was asked for
+    fn from_the_start(body: &str) -> String {
+        format!("HTTP/1.1 206 Partial Content\r\ncontent-range: bytes 0-{}/{}\r\n\
+                 content-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len() - 1,
+                body.len(),
+                body.len(),
+                body)
+    }
+
+    #[test]
+    fn a_part_that_is_already_whole_is_downloaded_again() {
+        let body = "HART-1\ncore-20180119235000\nBLAKE2b\nsignature\n\n";
+        let url = serve_artifact(body, 2, not_satisfiable);
+        let client = BuilderAPIClient::new(url.as_str(), "hab", "0.0.0", None).unwrap();
+        let dir = TempDir::new().unwrap();
+        let ident: PackageIdent = "core/redis/4.0.14/20190319155852".parse().unwrap();
+        let target: PackageTarget = "x86_64-linux".parse().unwrap();
+        let dst = dir.path()
+                     .join(ident.archive_name_with_target(target).unwrap());
+        fs::write(part_path(&dst), body).unwrap();
+
+        client.fetch_package((&ident, target), None, dir.path(), None)
+              .unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), body);
+        assert!(!part_path(&dst).exists());
+    }
+
+    #[test]
+    fn a_part_sent_from_somewhere_else_than_asked_is_not_appended() {
+        let body = "HART-1\ncore-20180119235000\nBLAKE2b\nsignature\n\n";
+        // As from a proxy that ignores the range asked for
+        let url = serve_artifact(body, 2, from_the_start);
+        let client = BuilderAPIClient::new(url.as_str(), "hab", "0.0.0", None).unwrap();
+        let dir = TempDir::new().unwrap();
+        let ident: PackageIdent = "core/redis/4.0.14/20190319155852".parse().unwrap();
+        let target: PackageTarget = "x86_64-linux".parse().unwrap();
+        let dst = dir.path()
+                     .join(ident.archive_name_with_target(target).unwrap());
+        fs::write(part_path(&dst), &body[..10]).unwrap();
+
+        client.fetch_package((&ident, target), None, dir.path(), None)
+              .unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), body);
+        assert!(!part_path(&dst).exists());
+    }
 }
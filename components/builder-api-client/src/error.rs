@@ -17,6 +17,7 @@ pub enum Error {
     APIError(reqwest::StatusCode, String),
     BadResponseBody(io::Error),
     DownloadWrite(PathBuf, io::Error),
+    IncompleteDownload(PathBuf, u64 /* expected */, u64 /* received */),
     HabitatCore(hab_core::Error),
     HabitatHttpClient(hab_http::Error),
     ReqwestError(reqwest::Error),
@@ -46,6 +47,13 @@ impl fmt::Display for Error {
                         p.display(),
                         e)
             }
+            Error::IncompleteDownload(ref p, expected, received) => {
+                format!("Only received {} of the {} bytes of {}; the connection was probably cut \
+                         short",
+                        received,
+                        expected,
+                        p.display())
+            }
             Error::HabitatCore(ref e) => format!("{}", e),
             Error::HabitatHttpClient(ref e) => format!("{}", e),
             Error::ReqwestError(ref err) => format!("{}", err),
@@ -13,6 +13,7 @@ extern crate serde_json;
 pub mod builder;
 pub mod error;
 pub mod response;
+pub mod throttle;
 
 use std::str::FromStr;
 
@@ -27,8 +28,10 @@ use std::{fmt,
 use chrono::DateTime;
 use reqwest::IntoUrl;
 
-pub use crate::error::{Error,
-                       Result};
+pub use crate::{error::{Error,
+                        Result},
+                hab_http::{ApiClientOptions,
+                           ClientIdentity}};
 
 use crate::{builder::BuilderAPIClient,
             hab_core::{crypto::keys::box_key_pair::WrappedSealedBox,
@@ -175,13 +178,18 @@ mod json {
 
 #[derive(Clone, Deserialize)]
 pub struct Package {
-    pub ident:    PackageIdent,
-    pub checksum: String,
-    pub manifest: String,
-    pub deps:     Vec<PackageIdent>,
-    pub tdeps:    Vec<PackageIdent>,
-    pub exposes:  Vec<u32>,
-    pub config:   String,
+    pub ident:       PackageIdent,
+    pub checksum:    String,
+    pub manifest:    String,
+    pub deps:        Vec<PackageIdent>,
+    pub tdeps:       Vec<PackageIdent>,
+    /// What the package was built with, which older Builders leave out
+    #[serde(default)]
+    pub build_deps:  Vec<PackageIdent>,
+    #[serde(default)]
+    pub build_tdeps: Vec<PackageIdent>,
+    pub exposes:     Vec<u32>,
+    pub config:      String,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -307,11 +315,24 @@ pub trait BuilderAPIProvider: Sync + Send {
                      progress: Option<Self::Progress>)
                      -> Result<PackageArchive>;
 
+    /// Writes the artifact for a fully qualified package to `out` as it arrives, rather than to
+    /// a file. Returns how many bytes were written.
+    fn stream_package(&self,
+                      ident_and_target: (&PackageIdent, PackageTarget),
+                      token: Option<&str>,
+                      out: &mut dyn Write)
+                      -> Result<u64>;
+
     fn check_package(&self,
                      ident_and_target: (&PackageIdent, PackageTarget),
                      token: Option<&str>)
                      -> Result<()>;
 
+    fn package_size(&self,
+                    ident_and_target: (&PackageIdent, PackageTarget),
+                    token: Option<&str>)
+                    -> Result<Option<u64>>;
+
     fn show_package(&self,
                     ident_and_target: (&PackageIdent, PackageTarget),
                     channel: &ChannelIdent,
@@ -324,6 +345,12 @@ pub trait BuilderAPIProvider: Sync + Send {
                              token: Option<&str>)
                              -> Result<Package>;
 
+    fn list_package_releases(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Vec<PackageIdent>>;
+
     fn delete_package(&self,
                       ident_and_target: (&PackageIdent, PackageTarget),
                       token: &str)
@@ -335,6 +362,8 @@ pub trait BuilderAPIProvider: Sync + Send {
                       token: Option<&str>)
                       -> Result<(Vec<PackageIdent>, usize)>;
 
+    fn list_origin_packages(&self, origin: &str, token: Option<&str>) -> Result<Vec<PackageIdent>>;
+
     fn create_channel(&self, origin: &str, channel: &ChannelIdent, token: &str) -> Result<()>;
 
     fn delete_channel(&self, origin: &str, channel: &ChannelIdent, token: &str) -> Result<()>;
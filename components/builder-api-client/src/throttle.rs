@@ -0,0 +1,107 @@
+//! Caps how fast downloads read from Builder. One `Throttle` can be shared by any number of
+//! downloads, on any number of threads, and they'll stay under its rate between them.
+
+use std::{cmp,
+          io::{self,
+               Read},
+          sync::{Arc,
+                 Mutex},
+          thread,
+          time::{Duration,
+                 Instant}};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// A token bucket that fills at a fixed number of bytes per second, holding up to a second's
+/// worth. It starts out empty, so the rate holds from the start.
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+    bucket:        Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens:   u64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Throttle { bytes_per_sec: cmp::max(bytes_per_sec, 1),
+                   bucket:        Arc::new(Mutex::new(Bucket { tokens:   0,
+                                                               refilled: Instant::now(), })), }
+    }
+
+    /// Blocks until `bytes` more can be transferred, which must be no more than a second's
+    /// worth.
+    fn take(&self, bytes: u64) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().expect("Throttle lock is poisoned");
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.refilled).as_nanos();
+                let refill = elapsed * u128::from(self.bytes_per_sec) / NANOS_PER_SEC;
+                if refill > 0 {
+                    bucket.tokens = cmp::min(u128::from(bucket.tokens) + refill,
+                                             u128::from(self.bytes_per_sec))
+                                    as u64;
+                    bucket.refilled = now;
+                }
+                if bucket.tokens >= bytes {
+                    bucket.tokens -= bytes;
+                    return;
+                }
+                let missing = u128::from(bytes - bucket.tokens);
+                let nanos = missing * NANOS_PER_SEC / u128::from(self.bytes_per_sec);
+                Duration::from_nanos(cmp::max(nanos, 1) as u64)
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Reads from `inner` no faster than `throttle` allows, or as fast as it can without one.
+pub(crate) struct ThrottledReader<'a, R> {
+    inner:    R,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, R> ThrottledReader<'a, R> {
+    pub(crate) fn new(inner: R, throttle: Option<&'a Throttle>) -> Self {
+        ThrottledReader { inner, throttle }
+    }
+}
+
+impl<'a, R: Read> Read for ThrottledReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.throttle {
+            Some(throttle) => {
+                let max = cmp::min(buf.len() as u64, throttle.bytes_per_sec) as usize;
+                let n = self.inner.read(&mut buf[..max])?;
+                throttle.take(n as u64);
+                Ok(n)
+            }
+            None => self.inner.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_are_held_to_the_rate() {
+        let throttle = Throttle::new(10_000);
+        let data = vec![0u8; 2_000];
+        let start = Instant::now();
+
+        let mut reader = ThrottledReader::new(&data[..], Some(&throttle));
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+
+        assert_eq!(read, data);
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+}
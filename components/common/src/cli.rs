@@ -86,11 +86,39 @@ pub fn cache_key_path_from_matches(matches: &ArgMatches<'_>) -> PathBuf {
 }
 
 pub fn file_into_idents(path: &str) -> Result<Vec<PackageIdent>, habitat_core::error::Error> {
+    file_into_ident_lines(path)?.into_iter()
+                                .map(|(_, ident)| ident)
+                                .collect()
+}
+
+/// Every line of the file with an ident on it, by line number, along with the ident or why it
+/// isn't one; for pointing out every bad line at once.
+pub fn file_into_ident_lines(
+    path: &str)
+    -> Result<Vec<(usize, Result<PackageIdent, habitat_core::error::Error>)>,
+              habitat_core::error::Error> {
     let s = std::fs::read_to_string(&path).map_err(|_| {
                 habitat_core::error::Error::FileNotFound(format!("Could not open file {}", path))
             })?;
 
-    s.lines().filter_map(line_to_ident).collect()
+    Ok(s.lines()
+        .enumerate()
+        .filter_map(|(index, line)| line_to_ident(line).map(|ident| (index + 1, ident)))
+        .map(|(number, ident)| (number, ident.map_err(|e| on_line(e, path, number))))
+        .collect())
+}
+
+/// Points an invalid ident at the line of the file it came from.
+fn on_line(err: habitat_core::error::Error,
+           path: &str,
+           number: usize)
+           -> habitat_core::error::Error {
+    match err {
+        habitat_core::error::Error::InvalidPackageIdent(ident) => {
+            habitat_core::error::Error::InvalidPackageIdentInFile(path.to_string(), number, ident)
+        }
+        err => err,
+    }
 }
 
 fn line_to_ident(line: &str) -> Option<Result<PackageIdent, habitat_core::error::Error>> {
@@ -123,4 +151,20 @@ mod tests {
 
         assert!(line_to_ident("core # not").unwrap().is_err());
     }
+
+    #[test]
+    fn invalid_idents_name_their_line() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("idents");
+        std::fs::write(&path, "# Databases\ncore/redis\n\ncore # not an ident\n").unwrap();
+        let path = path.to_string_lossy();
+
+        match file_into_idents(&path) {
+            Err(habitat_core::error::Error::InvalidPackageIdentInFile(p, 4, ident)) => {
+                assert_eq!(p, path);
+                assert_eq!(ident, "core");
+            }
+            other => panic!("Expected an invalid ident on line 4, got {:?}", other),
+        }
+    }
 }
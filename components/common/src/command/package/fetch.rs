@@ -0,0 +1,98 @@
+//! What installing a package and downloading one have in common: getting its artifact into a
+//! directory from Builder, making sure the public key it was signed with is at hand, and
+//! verifying it against that key. `hab pkg install` and `hab pkg download` both go through here
+//! for those steps, and differ only in what they do around them.
+
+use std::path::{Path,
+                PathBuf};
+
+use reqwest::StatusCode;
+
+use crate::{api_client,
+            hcore::{self,
+                    crypto::artifact,
+                    package::{PackageArchive,
+                              PackageIdent,
+                              PackageTarget}}};
+
+/// Where the artifact for `ident` for `target` is in `dir`, whether or not it's there. `ident`
+/// has to be fully qualified.
+pub fn artifact_path(dir: &Path, ident: &PackageIdent, target: PackageTarget) -> PathBuf {
+    dir.join(ident.archive_name_with_target(target)
+                  .expect("fully qualified ident"))
+}
+
+/// The artifact Builder answered a request for one with, if it has one. A Builder that doesn't
+/// support the artifact's platform answers that it's not implemented, which means there's
+/// nothing to fetch rather than that something went wrong.
+pub fn supported(fetched: api_client::Result<PackageArchive>)
+                 -> api_client::Result<Option<PackageArchive>> {
+    match fetched {
+        Ok(artifact) => Ok(Some(artifact)),
+        Err(api_client::Error::APIError(StatusCode::NOT_IMPLEMENTED, _)) => {
+            debug!("Host platform or architecture not supported by the targeted depot");
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Calls `ensure_key` with the signer of `artifact`, so that its public key can be put in place,
+/// and then verifies `artifact` against the public keys in `keys`, if there are any to verify
+/// against. Returns the signer.
+pub fn fetch_key_and_verify<E, F>(artifact: &PackageArchive,
+                                  keys: Option<&Path>,
+                                  ensure_key: F)
+                                  -> Result<String, E>
+    where E: From<hcore::Error>,
+          F: FnOnce(&str) -> Result<(), E>
+{
+    let signer = artifact::artifact_signer(&artifact.path)?;
+    ensure_key_and_verify(artifact, &signer, keys, ensure_key)?;
+    Ok(signer)
+}
+
+/// Like `fetch_key_and_verify`, for when the signer of `artifact` is already known.
+pub fn ensure_key_and_verify<E, F>(artifact: &PackageArchive,
+                                   signer: &str,
+                                   keys: Option<&Path>,
+                                   ensure_key: F)
+                                   -> Result<(), E>
+    where E: From<hcore::Error>,
+          F: FnOnce(&str) -> Result<(), E>
+{
+    ensure_key(signer)?;
+    if let Some(keys) = keys {
+        artifact.verify(&keys)?;
+        debug!("Verified {} signed by {}", artifact.path.display(), signer);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io,
+              str::FromStr};
+
+    #[test]
+    fn an_unsupported_platform_is_nothing_to_fetch() {
+        let unsupported =
+            Err(api_client::Error::APIError(StatusCode::NOT_IMPLEMENTED, String::new()));
+        assert!(supported(unsupported).unwrap().is_none());
+
+        let missing = Err(api_client::Error::APIError(StatusCode::NOT_FOUND, String::new()));
+        assert!(supported(missing).is_err());
+        let broken = Err(api_client::Error::IO(io::Error::from(io::ErrorKind::Other)));
+        assert!(supported(broken).is_err());
+    }
+
+    #[test]
+    fn artifact_paths_are_named_for_their_target() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let cache = Path::new("/hab/cache/artifacts");
+        assert_eq!(artifact_path(cache, &ident, target),
+                   cache.join("core-redis-4.0.14-20190319155852-x86_64-linux.hart"));
+    }
+}
@@ -33,13 +33,11 @@ use std::{borrow::Cow,
           str::FromStr,
           time::Duration};
 
-use crate::{api_client::{self,
-                         BoxedClient,
+use crate::{api_client::{BoxedClient,
                          Client,
                          Error::APIError},
             hcore::{self,
-                    crypto::{artifact,
-                             keys::parse_name_with_rev,
+                    crypto::{keys::parse_name_with_rev,
                              SigKeyPair},
                     fs::{cache_key_path,
                          pkg_install_path,
@@ -58,7 +56,8 @@ use reqwest::StatusCode;
 use retry::{delay,
             retry};
 
-use crate::{error::{Error,
+use crate::{command::package::fetch,
+            error::{Error,
                     Result},
             templating::{self,
                          hooks::{Hook,
@@ -828,7 +827,9 @@ impl<'a> InstallTask<'a> {
     /// the local package cache. It does not mean that the package is
     /// actually *in* the package cache, though.
     fn cached_artifact_path(&self, ident: &FullyQualifiedPackageIdent<'_>) -> PathBuf {
-        self.artifact_cache_path.join(ident.archive_name())
+        fetch::artifact_path(self.artifact_cache_path,
+                             ident.as_ref(),
+                             PackageTarget::active_target())
     }
 
     fn fetch_latest_pkg_ident_for(&self,
@@ -858,19 +859,15 @@ impl<'a> InstallTask<'a> {
         where T: UIWriter
     {
         ui.status(Status::Downloading, ident)?;
-        match self.api_client.fetch_package((ident.as_ref(), target),
-                                            token,
-                                            self.artifact_cache_path,
-                                            ui.progress())
-        {
-            Ok(_) => Ok(()),
-            Err(api_client::Error::APIError(StatusCode::NOT_IMPLEMENTED, _)) => {
-                println!("Host platform or architecture not supported by the targeted depot; \
-                          skipping.");
-                Ok(())
-            }
-            Err(e) => Err(Error::from(e)),
+        let fetched = self.api_client.fetch_package((ident.as_ref(), target),
+                                                    token,
+                                                    self.artifact_cache_path,
+                                                    ui.progress());
+        if fetch::supported(fetched)?.is_none() {
+            println!("Host platform or architecture not supported by the targeted depot; \
+                      skipping.");
         }
+        Ok(())
     }
 
     fn fetch_origin_key<T>(&self,
@@ -964,12 +961,12 @@ impl<'a> InstallTask<'a> {
             )));
         }
 
-        let nwr = artifact::artifact_signer(&artifact.path)?;
-        if SigKeyPair::get_public_key_path(&nwr, self.key_cache_path).is_err() {
-            self.fetch_origin_key(ui, &nwr, token)?;
-        }
-
-        artifact.verify(&self.key_cache_path)?;
+        let nwr = fetch::fetch_key_and_verify(artifact, Some(self.key_cache_path), |nwr| {
+            if SigKeyPair::get_public_key_path(nwr, self.key_cache_path).is_err() {
+                self.fetch_origin_key(ui, nwr, token)?;
+            }
+            Ok(())
+        })?;
         debug!("Verified {} signed by {}", ident, &nwr);
         Ok(())
     }
@@ -29,6 +29,9 @@ pub const NONINTERACTIVE_ENVVAR: &str = "HAB_NONINTERACTIVE";
 
 pub const NOCOLORING_ENVVAR: &str = "HAB_NOCOLORING";
 
+/// Turns color off when set to anything, as https://no-color.org describes.
+pub const NO_COLOR_ENVVAR: &str = "NO_COLOR";
+
 pub const GLYPH_STYLE_ENVVAR: &str = "HAB_GLYPH_STYLE";
 
 #[derive(Clone, Copy)]
@@ -294,6 +297,8 @@ pub trait UIWriter {
     fn is_err_a_terminal(&self) -> bool;
     /// Returns a progress bar widget implementation for writing operation's progress to.
     fn progress(&self) -> Option<Box<dyn DisplayProgress>>;
+    /// The width in columns of the terminal that normal messages go to, if it can be found.
+    fn out_width(&self) -> Option<usize> { None }
 
     /// Write a message formatted with `begin`.
     fn begin<T>(&mut self, message: T) -> io::Result<()>
@@ -417,6 +422,21 @@ impl UI {
 
     /// Creates a new default `UI` with a coloring strategy and tty hinting.
     pub fn default_with_env() -> Self {
+        let coloring = if env::var(NOCOLORING_ENVVAR).map(|val| val == "1" || val == "true")
+                                                     .unwrap_or(false)
+                          || env::var_os(NO_COLOR_ENVVAR).is_some()
+        {
+            ColorChoice::Never
+        } else {
+            ColorChoice::Auto
+        };
+
+        Self::default_with_coloring(coloring)
+    }
+
+    /// Creates a new default `UI` with the given coloring strategy, whatever the environment
+    /// says, and tty hinting from the environment.
+    pub fn default_with_coloring(coloring: ColorChoice) -> Self {
         let isatty = if env::var(NONINTERACTIVE_ENVVAR)
             // Keep string boolean for backwards-compatibility
             .map(|val| val == "1" || val == "true")
@@ -426,13 +446,6 @@ impl UI {
         } else {
             None
         };
-        let coloring = if env::var(NOCOLORING_ENVVAR).map(|val| val == "1" || val == "true")
-                                                     .unwrap_or(false)
-        {
-            ColorChoice::Never
-        } else {
-            ColorChoice::Auto
-        };
 
         let ui = UI::default_with(coloring, isatty);
         debug!("{:?}", &ui);
@@ -494,6 +507,14 @@ impl UIWriter for UI {
             None
         }
     }
+
+    fn out_width(&self) -> Option<usize> {
+        if self.is_out_a_terminal() {
+            tty::width(StdStream::Stdout)
+        } else {
+            None
+        }
+    }
 }
 
 impl UIReader for UI {
@@ -685,22 +706,29 @@ impl OutputStream {
     }
 
     pub fn from_stdout(coloring: ColorChoice, isatty: Option<bool>) -> Self {
-        Self::new(WriteStream::from_stdout(coloring), coloring, match isatty {
-            Some(val) => val,
-            None => tty::isatty(StdStream::Stdout),
-        })
+        let isatty = isatty.unwrap_or_else(|| tty::isatty(StdStream::Stdout));
+        let coloring = coloring_for(coloring, isatty);
+        Self::new(WriteStream::from_stdout(coloring), coloring, isatty)
     }
 
     pub fn from_stderr(coloring: ColorChoice, isatty: Option<bool>) -> Self {
-        Self::new(WriteStream::from_stderr(coloring), coloring, match isatty {
-            Some(val) => val,
-            None => tty::isatty(StdStream::Stderr),
-        })
+        let isatty = isatty.unwrap_or_else(|| tty::isatty(StdStream::Stderr));
+        let coloring = coloring_for(coloring, isatty);
+        Self::new(WriteStream::from_stderr(coloring), coloring, isatty)
     }
 
     pub fn is_a_terminal(&self) -> bool { self.isatty }
 }
 
+// Left to itself, `termcolor` colors anything with a `TERM`, so output that's piped or redirected
+// would be full of escape codes
+fn coloring_for(coloring: ColorChoice, isatty: bool) -> ColorChoice {
+    match coloring {
+        ColorChoice::Auto if !isatty => ColorChoice::Never,
+        _ => coloring,
+    }
+}
+
 impl WriteColor for OutputStream {
     fn supports_color(&self) -> bool {
         match self.inner {
@@ -810,6 +838,42 @@ mod tty {
             consoleapi::GetConsoleMode(handle, &mut out) != 0
         }
     }
+
+    #[cfg(unix)]
+    pub fn width(output: StdStream) -> Option<usize> {
+        let fd = match output {
+            StdStream::Stdin => libc::STDIN_FILENO,
+            StdStream::Stdout => libc::STDOUT_FILENO,
+            StdStream::Stderr => libc::STDERR_FILENO,
+        };
+
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+            return None;
+        }
+        Some(size.ws_col as usize)
+    }
+    #[cfg(windows)]
+    pub fn width(output: StdStream) -> Option<usize> {
+        use winapi::um::{processenv,
+                         winbase,
+                         wincon};
+
+        let handle = match output {
+            StdStream::Stdin => winbase::STD_INPUT_HANDLE,
+            StdStream::Stdout => winbase::STD_OUTPUT_HANDLE,
+            StdStream::Stderr => winbase::STD_ERROR_HANDLE,
+        };
+
+        unsafe {
+            let handle = processenv::GetStdHandle(handle);
+            let mut info: wincon::CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
+            if wincon::GetConsoleScreenBufferInfo(handle, &mut info) == 0 {
+                return None;
+            }
+            Some((info.srWindow.Right - info.srWindow.Left + 1) as usize)
+        }
+    }
 }
 
 /// A moving progress bar to track progress of a sized event, similar to wget, curl, npm, etc.
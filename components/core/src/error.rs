@@ -89,6 +89,8 @@ pub enum Error {
     InvalidBinding(String),
     /// Occurs when a package identifier string cannot be successfully parsed.
     InvalidPackageIdent(String),
+    /// Occurs when a line of a file of package identifiers cannot be parsed.
+    InvalidPackageIdentInFile(String /* path */, usize /* line */, String),
     /// Occurs when a package target string cannot be successfully parsed.
     InvalidPackageTarget(String),
     /// Occurs when a package type is not recognized.
@@ -276,6 +278,11 @@ impl fmt::Display for Error {
                          origin/name (example: acme/redis)",
                         e)
             }
+            Error::InvalidPackageIdentInFile(ref path, line, ref e) => {
+                format!("Invalid package identifier {:?} on line {} of {}. A valid identifier is \
+                         in the form origin/name (example: acme/redis)",
+                        e, line, path)
+            }
             Error::InvalidPackageTarget(ref e) => {
                 format!("Invalid package target: {}. A valid target is in the form \
                          architecture-platform (example: x86_64-linux)",
@@ -3,10 +3,12 @@
 mod windows;
 
 #[cfg(windows)]
-pub use self::windows::symlink;
+pub use self::windows::{available_space,
+                        symlink};
 
 #[cfg(not(windows))]
 mod linux;
 
 #[cfg(not(windows))]
-pub use self::linux::symlink;
+pub use self::linux::{available_space,
+                      symlink};
@@ -1 +1,17 @@
 pub use std::os::unix::fs::symlink;
+use std::{ffi::CString,
+          io,
+          mem,
+          os::unix::ffi::OsStrExt,
+          path::Path};
+
+/// The number of bytes that an unprivileged user can still write to the filesystem `path` is on.
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes());
+    let path = path.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
@@ -1,6 +1,26 @@
 use std::{io,
-          path::Path};
+          os::windows::ffi::OsStrExt,
+          path::Path,
+          ptr};
+use winapi::{shared::ntdef::ULARGE_INTEGER,
+             um::fileapi::GetDiskFreeSpaceExW};
 
 pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<()> {
     unimplemented!();
 }
+
+/// The number of bytes that the current user can still write to the volume `path` is on.
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    if unsafe {
+        GetDiskFreeSpaceExW(path.as_ptr(),
+                            &mut available,
+                            ptr::null_mut(),
+                            ptr::null_mut())
+    } == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { *available.QuadPart() })
+}
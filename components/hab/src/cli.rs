@@ -1,11 +1,11 @@
 use crate::command::studio;
 
+use chrono::DateTime;
 use clap::{App,
            AppSettings,
            Arg,
            ArgMatches};
-use habitat_common::{cli::{file_into_idents,
-                           BINLINK_DIR_ENVVAR,
+use habitat_common::{cli::{BINLINK_DIR_ENVVAR,
                            DEFAULT_BINLINK_DIR,
                            PACKAGE_TARGET_ENVVAR,
                            RING_ENVVAR,
@@ -801,6 +801,17 @@ pub fn get(feature_flags: FeatureFlag) -> App<'static, 'static> {
             \n    start      Alias for: 'svc start'\
             \n    stop       Alias for: 'svc stop'\
             \n    term       Alias for: 'sup term'\
+            \n\
+            \nEXIT CODES:\
+            \n    0      Success\
+            \n    1      Any failure not listed here\
+            \n    2      A package, or an artifact for it, isn't on Builder\
+            \n    3      Permission denied\
+            \n    4      Builder couldn't be reached, or failed to answer\
+            \n    5      An artifact's signature couldn't be verified\
+            \n    6      Some artifacts of a 'pkg download' couldn't be downloaded, with\
+            \n           --continue-on-error, or are missing afterwards\
+            \n    130    Interrupted, and stopped once what was underway was done\
             \n"
         )
     )
@@ -931,21 +942,222 @@ fn sub_pkg_build() -> App<'static, 'static> {
 fn sub_pkg_download() -> App<'static, 'static> {
     let sub = clap_app!(@subcommand download =>
     (about: "Download Habitat artifacts (including dependencies and keys) from Builder")
-    (@arg AUTH_TOKEN: -z --auth +takes_value "Authentication token for Builder")
+    (@arg AUTH_TOKEN: -z --auth +takes_value conflicts_with[TOKEN_FILE]
+        "Authentication token for Builder")
+    (@arg TOKEN_FILE: --("token-file") +takes_value
+        "Read the authentication token for Builder from this file, or from stdin if it's -, \
+         so that it isn't on the command line for anyone to see")
     (@arg BLDR_URL: --url -u +takes_value {valid_url} default_value(habitat_core::url::DEFAULT_BLDR_URL)
-        "Specify an alternate Builder endpoint. If not specified, the value will \
-         be taken from the HAB_BLDR_URL environment variable if defined.")
+        "Specify an alternate Builder endpoint, or a file:// URL for a directory to use as with \
+         --source. If not specified, the value will be taken from the HAB_BLDR_URL environment \
+         variable if defined.")
+    (@arg FALLBACK_BLDR_URL: --("fallback-url") +takes_value +multiple number_of_values(1)
+        {valid_url}
+        "A Builder serving the same packages, such as a replica, to try when the ones before it \
+         can't be reached. Can be given more than once")
+    (@arg SOURCE: --source +takes_value {dir_exists} conflicts_with[FALLBACK_BLDR_URL]
+        "Resolve and download from this directory, an export laid out like a download \
+         directory, instead of from Builder. Channels are ignored")
     (@arg CHANNEL: --channel -c +takes_value default_value[stable] env(ChannelIdent::ENVVAR)
         "Download from the specified release channel")
+    (@arg PROXY: --proxy +takes_value {valid_url}
+        "Send every request to Builder through this proxy, instead of any set by the \
+         HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables")
+    (@arg CLIENT_CERT: --("client-cert") +takes_value {file_exists}
+        "A PKCS #12 (.p12 or .pfx) file with a client certificate and key, for a Builder that \
+         requires clients to authenticate with one")
+    (@arg CLIENT_CERT_PASSWORD: --("client-cert-password") +takes_value
+        env("HAB_CLIENT_CERT_PASSWORD") requires[CLIENT_CERT]
+        "The password for the --client-cert file")
+    (@arg CA_BUNDLE: --("ca-bundle") +takes_value {file_exists}
+        "A PEM file of CA certificates to trust as well as the usual ones, for a Builder whose \
+         certificate is signed by a private CA")
+    (@arg USER_AGENT: --("user-agent") +takes_value
+        "The User-Agent to send Builder, instead of one naming this version of hab, such as to \
+         tell a sync job's requests apart in Builder's logs")
+    (@arg FILTER_BLDR_URL: --("filter-builder") +takes_value {valid_url}
+        "Don't download anything the Builder at this endpoint already has, such as the on \
+         premises Builder being synced to")
+    (@arg FALLBACK_CHANNEL: --("fallback-channel") +takes_value +multiple number_of_values(1)
+        "A channel to look in for packages that aren't in the --channel channel. Can be given \
+         more than once; channels are tried in the order given")
     (@arg DOWNLOAD_DIRECTORY: --("download-directory") +takes_value "The path to store downloaded artifacts")
+    (@arg LAYOUT: --layout +takes_value possible_value[nested flat by-channel]
+        "How to lay out the download directory: artifacts and keys in their own subdirectories \
+         with nested, all directly in it with flat, or each channel's artifacts in their own \
+         <channel>/artifacts subdirectory with by-channel (default: nested)")
     (@arg PKG_IDENT_FILE: --file +takes_value +multiple {valid_ident_file}
-        "File with newline separated package identifiers")
+        "File with newline separated package identifiers. Can be given more than once. A \
+         directory stands for every .txt file in it, read as such, and every .toml file, read \
+         as a --manifest")
+    (@arg MANIFEST: --manifest +takes_value +multiple number_of_values(1) {file_exists}
+        "TOML file listing package identifiers as [[package]] entries, each of which can \
+         override the channel and target to download it from. Can be given more than once")
     (@arg PKG_IDENT: +multiple {valid_ident}
-            "One or more Habitat package identifiers (ex: acme/redis)")
+            "One or more Habitat package identifiers (ex: acme/redis), or acme/* for every \
+             package in the acme origin")
     (@arg PKG_TARGET: --target -t +takes_value {valid_target}
-            "Target architecture to fetch. E.g. x86_64-linux")
+            "Target architecture to fetch. E.g. x86_64-linux (default: this host's)")
+    (@arg NO_DEPS: --("no-deps")
+            "Download only the packages given, without their transitive dependencies")
+    (@arg INCLUDE_BUILD_DEPS: --("include-build-deps") alias[bdeps] conflicts_with[NO_DEPS]
+            "Also download what the packages were built with, for building them again offline")
+    (@arg MAX_DEPTH: --("max-depth") +takes_value {valid_numeric::<usize>} conflicts_with[NO_DEPS]
+            "Download dependencies only as far as N levels down: 0 for none of them, 1 for only \
+             those the packages depend on directly, and so on (default: all of them)")
+    (@arg KEEP_VERSIONS: --("keep-versions") +takes_value {valid_numeric::<usize>}
+            "Download the latest N releases of each package in its channel, and what each of \
+             them depends on, instead of only the latest (default: 1)")
+    (@arg EXCLUDE: --exclude +takes_value +multiple number_of_values(1) {valid_ident}
+            "Leave out the packages matching this identifier, even if something else depends on \
+             them (ex: core/gcc, core/gcc/8.2.0). Can be given more than once")
+    (@arg EXCLUDE_DEPS_OF: --("exclude-deps-of") +takes_value +multiple number_of_values(1)
+            {valid_ident} conflicts_with[NO_DEPS]
+            "Download the package asked for matching this identifier without its dependencies, \
+             for when they come from somewhere else. What else is asked for still gets them. \
+             Can be given more than once")
+    (@arg SINCE: --since +takes_value {valid_rfc3339}
+            "Leave out packages released before this time (ex: 2019-10-14T00:00:00Z), such as \
+             the last sync of a mirror. Packages whose release isn't a timestamp are kept")
     (@arg VERIFY: --verify
             "Verify package integrity after download (Warning: this can be slow)")
+    (@arg REQUIRE_SIGNATURES: --("require-signatures")
+            "Fail if any artifact's signature can't be verified. Implies --verify")
+    (@arg VERIFY_ONLY: --("verify-only") conflicts_with[PKG_IDENT] conflicts_with[PKG_IDENT_FILE]
+            conflicts_with[MANIFEST]
+            "Don't download anything; verify the artifacts already in the download directory \
+             against the keys there")
+    (@arg FORCE: --force
+            "Download every artifact again, even if it's already in the download directory")
+    (@arg REVALIDATE: --revalidate
+            "Ask Builder whether each artifact already in the download directory has changed, \
+             and download it again if it has. This keeps the ETag of every artifact downloaded")
+    (@arg BY_CHECKSUM: --("by-checksum")
+            "Recognize the artifacts already in the download directory by their checksum \
+             instead of their file name, using a renamed one and downloading one that doesn't \
+             match again. This asks Builder for the checksum of each artifact")
+    (@arg NO_LOCAL_CACHE: --("no-local-cache")
+            "Download every artifact from Builder, even one that's already in the local \
+             artifact cache at /hab/cache/artifacts")
+    (@arg FAIL_ON_UNSUPPORTED: --("fail-on-unsupported")
+            "Fail instead of skipping artifacts whose platform the depot doesn't support")
+    (@arg CONTINUE_ON_ERROR: --("continue-on-error")
+            "Carry on past artifacts that can't be downloaded, and fail once the rest are down")
+    (@arg WARN_ON_WRONG_TARGET: --("warn-on-wrong-target")
+            "Keep an artifact from Builder that's for some other target than asked for, with a \
+             warning, instead of failing")
+    (@arg SKIP_SPACE_CHECK: --("skip-space-check")
+            "Don't check that the download directory has room for the artifacts before \
+             downloading them")
+    (@arg PRECHECK: --precheck
+            "Check that every artifact resolved exists on Builder before downloading any of them, \
+             and report all the missing ones at once")
+    (@arg NO_KEYS: --("no-keys")
+            "Don't download signing keys; any verification uses the keys already in the \
+             download directory")
+    (@arg KEYS_FROM: --("keys-from") +takes_value {dir_exists} conflicts_with[NO_KEYS]
+            "Trust only the public keys in this directory, and never download keys from \
+             Builder. Every artifact must be signed by one of them")
+    (@arg TRUSTED_ORIGIN: --("trusted-origin") +takes_value +multiple number_of_values(1)
+            "Only accept artifacts signed by this origin, or by just this one of its keys when \
+             given with the key's revision (ex: core, core-20180119235000). Can be given more \
+             than once")
+    (@arg RESOLVE_JOBS: --("resolve-jobs") +takes_value {valid_numeric::<usize>}
+            "Maximum number of package identifiers to resolve against Builder at once (default: 4)")
+    (@arg CONCURRENT_DOWNLOADS: -N --("concurrent-downloads") +takes_value {valid_numeric::<usize>}
+            "Maximum number of artifacts to download at once (default: 1)")
+    (@arg VERIFY_JOBS: --("verify-jobs") +takes_value {valid_numeric::<usize>}
+            "Verify the artifacts once they're all downloaded, this many at a time, instead of \
+             each one as it's downloaded. Implies --verify")
+    (@arg VERIFY_ALL: --("verify-all")
+            "Verify every artifact, cached ones included, once they're all downloaded, and fail \
+             naming each one that doesn't verify. Implies --verify")
+    (@arg REVERIFY: --reverify
+            "Verify artifacts again even if they verified on an earlier run and haven't changed \
+             since")
+    (@arg REMOVE_IF_UNVERIFIED: --("remove-if-unverified") requires[VERIFY_ALL]
+            "If --verify-all finds anything that doesn't verify, remove it along with everything \
+             else the download put in the download directory")
+    (@arg CLEAN_ON_FAILURE: --("clean-on-failure") conflicts_with[CONTINUE_ON_ERROR]
+            "If the download fails, remove the artifacts and keys it put in the download \
+             directory, leaving what was already there")
+    (@arg MAX_BANDWIDTH: --("max-bandwidth") +takes_value {valid_max_bandwidth}
+            "Limit all the downloads together to this many KB/s")
+    (@arg API_RATE_LIMIT: --("api-rate-limit") +takes_value {valid_numeric::<u32>}
+            "Make no more than this many requests a second to Builder to resolve packages and \
+             fetch keys, for a Builder that limits how fast it can be asked. Downloading \
+             artifacts isn't counted, as --max-bandwidth limits that")
+    (@arg DRYRUN: --("dry-run")
+            "Just resolve and list the artifacts that would be downloaded, don't download them")
+    (@arg QUIET: -q --quiet
+            "Don't print a status line for each package and artifact; warnings, errors and the \
+             final summary are still printed")
+    (@arg COLOR: --color +takes_value possible_value[auto always never]
+            "Whether to color the output. With auto, it's colored when it's going to a terminal \
+             and NO_COLOR isn't set (default: auto)")
+    (@arg PROGRESS_FORMAT: --("progress-format") +takes_value possible_value[human ndjson]
+            "Also write each resolve, download and verify event as a line of JSON to stderr, \
+             with ndjson (default: human)")
+    (@arg OVERALL_PROGRESS: --("overall-progress")
+            "Show one progress bar for all of the artifacts together, with how many are done, \
+             the bytes downloaded and the time left, instead of one for each artifact. \
+             Resolving the package identifiers shows one too, with how many are resolved")
+    (@arg JSON: --json conflicts_with[VERIFY_ONLY] conflicts_with[OVERALL_PROGRESS]
+            "Print nothing but a JSON document of what was downloaded at the end, with warnings \
+             and errors written to stderr as lines of JSON")
+    (@arg STDOUT: --stdout conflicts_with[PKG_IDENT_FILE] conflicts_with[MANIFEST]
+            conflicts_with[INCLUDE_BUILD_DEPS] conflicts_with[MAX_DEPTH]
+            conflicts_with[KEEP_VERSIONS] conflicts_with[JSON] conflicts_with[VERIFY_ONLY]
+            "Write the artifact for a single fully qualified package ident to stdout, instead of \
+             to the download directory. Its dependencies and keys aren't downloaded, and it \
+             isn't verified")
+    (@arg LOCKFILE: --lockfile +takes_value
+            "Write the fully qualified idents each package identifier resolved to, and their \
+             dependencies, to this file")
+    (@arg FROZEN: --frozen alias[locked] requires[LOCKFILE] conflicts_with[PKG_IDENT]
+            conflicts_with[PKG_IDENT_FILE] conflicts_with[MANIFEST]
+            "Download exactly the packages recorded in the --lockfile file instead of resolving \
+             package identifiers against Builder")
+    (@arg GRAPH: --graph +takes_value conflicts_with[FROZEN]
+            "Write what each package identifier resolved to, and the dependencies that brought \
+             along, to this file: as JSON if it ends in .json, and Graphviz DOT otherwise")
+    (@arg INCREMENTAL: --incremental
+            "Only download what's new since the last successful download to the same directory, \
+             even if that's since been moved out of it")
+    (@arg REPORT_JSON: --("report-json") +takes_value
+            "Write a JSON report of each artifact downloaded, and how, to this file")
+    (@arg SBOM: --sbom +takes_value
+            "Write a CycloneDX SBOM of the downloaded artifacts to this file, with the licenses \
+             their plans declare. Packages that don't declare one are marked as missing it")
+    (@arg WITH_METADATA: --("with-metadata")
+            "Write the ident, target, channel, signer and checksum of each artifact to a .json \
+             file next to it, for tools that index the download directory")
+    (@arg CHECKSUMS: --checksums
+            "Write a SHA256SUMS file for the downloaded artifacts to the download directory")
+    (@arg CHECKSUM_KEYS: --("checksum-keys") requires[CHECKSUMS]
+            "Include the downloaded signing keys in the SHA256SUMS file")
+    (@arg BUNDLE: --bundle +takes_value
+            "Pack the downloaded artifacts and signing keys into this tarball, along with a \
+             SHA256SUMS file for them. It's gzipped if the name ends in .gz or .tgz")
+    (@arg PRUNE: --prune
+            "Remove the artifacts in the download directory that aren't in the resolved set")
+    (@arg PRUNE_DRY_RUN: --("prune-dry-run") conflicts_with[PRUNE]
+            "List the artifacts that --prune would remove, without removing them")
+    (@arg PRUNE_KEYS: --("prune-keys")
+            "When pruning, also remove the signing keys no remaining artifact needs")
+    (@arg RETRIES: --retries +takes_value {valid_numeric::<usize>}
+            "Number of times to retry a failed request to Builder (default: 5)")
+    (@arg RETRY_WAIT_MS: --("retry-wait-ms") +takes_value {valid_numeric::<u64>}
+            "Milliseconds to wait before retrying a failed request to Builder (default: 3000)")
+    (@arg RETRY_BACKOFF: --("retry-backoff") +takes_value possible_value[fixed exponential]
+            "Whether to wait the same time before each retry, or back off exponentially \
+             (default: fixed)")
+    (@arg REQUEST_TIMEOUT: --("request-timeout") +takes_value {valid_numeric::<u64>}
+            "Seconds a single request to Builder can take before it fails and is retried \
+             (default: 120)")
+    (@arg DEADLINE: --deadline +takes_value {valid_numeric::<u64>}
+            "Give up after this many seconds in all. Whatever is underway is allowed to finish, \
+             but nothing new is started, and the download fails listing what's left")
     );
     sub
 }
@@ -1406,6 +1618,14 @@ fn valid_url(val: String) -> result::Result<(), String> {
     }
 }
 
+#[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
+fn valid_rfc3339(val: String) -> result::Result<(), String> {
+    match DateTime::parse_from_rfc3339(&val) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("'{}' is not an RFC 3339 time", &val)),
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
 fn valid_numeric<T: FromStr>(val: String) -> result::Result<(), String> {
     match val.parse::<T>() {
@@ -1414,6 +1634,15 @@ fn valid_numeric<T: FromStr>(val: String) -> result::Result<(), String> {
     }
 }
 
+#[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
+fn valid_max_bandwidth(val: String) -> result::Result<(), String> {
+    // It's limited in bytes a second, which there must be a number for
+    match val.parse::<u64>().ok().and_then(|kbps| kbps.checked_mul(1024)) {
+        Some(_) => Ok(()),
+        None => Err(format!("'{}' is not a valid number of KB/s", &val)),
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
 fn valid_health_check_interval(val: String) -> result::Result<(), String> {
     match HealthCheckInterval::from_str(&val) {
@@ -1448,8 +1677,12 @@ fn valid_ident(val: String) -> result::Result<(), String> {
 
 #[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
 fn valid_ident_file(val: String) -> result::Result<(), String> {
-    file_into_idents(&val).map(|_| ())
-                          .map_err(|e| e.to_string())
+    // The idents in it are checked when they're read, along with any others
+    if Path::new(&val).is_dir() {
+        Ok(())
+    } else {
+        file_exists(val)
+    }
 }
 
 #[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
@@ -1922,4 +2155,19 @@ mod tests {
             assert_eq!(error.kind, clap::ErrorKind::ValueValidation);
         }
     }
+
+    mod pkg_download {
+        use super::*;
+
+        #[test]
+        fn max_bandwidth_must_fit_in_bytes_a_second() {
+            let matches = sub_pkg_download().get_matches_from_safe(vec![
+                "download",
+                "core/redis",
+                "--max-bandwidth",
+                "18014398509481984",
+            ]);
+            assert_eq!(matches.unwrap_err().kind, clap::ErrorKind::ValueValidation);
+        }
+    }
 }
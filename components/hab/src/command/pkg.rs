@@ -22,7 +22,7 @@ pub mod upload;
 pub mod verify;
 
 /// Used in commands like uninstall which provide a --dry-run option
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum ExecutionStrategy {
     /// Don't actually run commands that mutate the state of the system,
     /// simply print their output
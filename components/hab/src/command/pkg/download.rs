@@ -19,422 +19,4771 @@
 //! The most common usage will have a file containing newline separated list of package
 //! identifiers.
 //!
+//! Everything in an origin can be mirrored at once:
+//!
+//! ```bash
+//! $ hab pkg download --download-directory download 'core/*'
+//! ```
+//!
+//! A download directory that's been copied elsewhere can be checked again, without talking to
+//! Builder at all:
+//!
+//! ```bash
+//! $ hab pkg download --verify-only --download-directory download
+//! ```
+//!
 //! # Internals
 //!
-//! * Resolve the list of partial artifact identifiers to fully qualified idents
+//! * Resolve the list of partial artifact identifiers to fully qualified idents, using a bounded
+//!   pool of worker threads
 //! * Gather the TDEPS of the list (done concurrently with the above step)
+//! * Optionally record the resolution in a lockfile, or skip resolving entirely and download
+//!   exactly what a previous lockfile recorded
+//! * Optionally drop anything that another Builder (the one being synced to) already has
 //! * Download the artifact
 //! * Verify it is un-altered
 //! * Fetch the signing keys
+//! * Optionally write a JSON report of what happened to each artifact
+//! * Optionally prune artifacts (and keys) the resolution no longer wants
+//! * Optionally write a SHA256SUMS file covering the downloaded files
 
-use std::{collections::HashSet,
-          fs::DirBuilder,
+use std::{collections::{HashMap,
+                        HashSet},
+          ffi::OsStr,
+          fmt,
+          fs::{self,
+               DirBuilder},
+          hash::Hash,
+          io::{self,
+               Write},
+          iter,
           path::{Path,
                  PathBuf},
-          time::Duration};
+          slice,
+          sync::{atomic::{AtomicBool,
+                          AtomicUsize,
+                          Ordering},
+                 mpsc,
+                 Mutex,
+                 MutexGuard},
+          time::{Duration,
+                 Instant}};
 
 use crate::{api_client::{self,
+                         builder::{etag_path,
+                                   BuilderAPIClient},
+                         throttle::Throttle,
+                         ApiClientOptions,
                          BoxedClient,
-                         Client,
+                         ClientIdentity,
+                         DisplayProgress,
                          Error::APIError,
                          Package},
-            common::Error as CommonError,
-            hcore::{crypto::{artifact,
+            common::{cli::file_into_ident_lines,
+                     Error as CommonError},
+            hcore::{config::ConfigFile,
+                    crypto::{artifact,
+                             hash,
                              keys::parse_name_with_rev,
                              SigKeyPair},
-                    fs::cache_root_path,
-                    package::{PackageArchive,
+                    fs::{cache_artifact_path,
+                         cache_root_path},
+                    os::filesystem,
+                    package::{ident,
+                              Identifiable,
+                              PackageArchive,
                               PackageIdent,
                               PackageTarget},
                     ChannelIdent,
-                    Error as CoreError}};
+                    Error as CoreError},
+            logging};
 
+use chrono::{DateTime,
+             TimeZone,
+             Utc};
+use crossbeam_utils::thread;
 use reqwest::StatusCode;
 use retry::{delay,
-            retry};
+            retry,
+            Error as RetryError,
+            OperationResult};
 
+use self::{bundle::write_bundle,
+           checksums::write_sha256sums,
+           events::{notify,
+                    NdjsonEvents},
+           failover::FailoverClient,
+           graph::write_graph,
+           json::print_document,
+           lockfile::Lockfile,
+           manifest::{DownloadSource,
+                      Manifest},
+           metadata::ArtifactMetadata,
+           overall::OverallProgress,
+           prune::{files_with_extension,
+                   find_file,
+                   stale_artifacts,
+                   stale_keys},
+           rate_limit::RateLimitedClient,
+           sbom::write_sbom,
+           state::SyncState,
+           table::{human_size,
+                   write_table}};
+use super::ExecutionStrategy;
 use crate::error::{Error,
                    Result};
 
-use habitat_common::ui::{Glyph,
-                         Status,
-                         UIWriter};
+use habitat_common::{command::package::fetch,
+                     ui::{Glyph,
+                          Status,
+                          UIWriter}};
 
+pub use self::{client::DownloadClient,
+               events::{DownloadObserver,
+                        EventKind,
+                        Phase,
+                        ProgressEvent},
+               json::JsonUi,
+               local_depot::LocalDepot,
+               report::{ArtifactReport,
+                        DownloadReport,
+                        FailedArtifact,
+                        PhaseTimings,
+                        ResolvedIdent,
+                        SkippedArtifact}};
+
+mod bundle;
+mod checksums;
+mod client;
+mod events;
+mod failover;
+mod graph;
+mod interrupt;
+mod json;
+mod local_depot;
+mod lockfile;
+mod long_path;
+mod manifest;
+mod metadata;
+mod overall;
+mod prune;
+mod rate_limit;
+mod report;
+mod sbom;
+mod state;
+mod table;
+mod verified;
+
+/// How many times a failed request to Builder is retried, and how long to wait between attempts,
+/// unless overridden.
 pub const RETRIES: usize = 5;
 pub const RETRY_WAIT: Duration = Duration::from_millis(3000);
 
-/// Download a Habitat package.
-///
-/// If an `PackageIdent` is given, we retrieve the package from the specified Builder
-/// `url`. Providing a fully-qualified identifer will result in that exact package being downloaded
-/// (regardless of `channel`). Providing a partially-qualified identifier will result in the
-/// installation of latest appropriate release from the given `channel`.
-///
-/// Any dependencies of will be retrieved from Builder (if they're not already downloaded locally).
-///
-/// At the end of this function, the specified package and all its
-/// dependencies will be downloaded on the system in the
-/// <download_path>/artifacts directory. Any signing keys will also be
-/// downloaded and put in the <download_path/keys> directory.
-
-/// Also, in the future we may want to accept an alternate builder to 'filter' what we pull down by
-/// That would greatly optimize the 'sync' to on prem builder case, as we could point to that
-/// and only fetch what we don't already have.
-#[allow(clippy::too_many_arguments)]
-pub fn start<U>(ui: &mut U,
-                url: &str,
-                channel: &ChannelIdent,
-                product: &str,
-                version: &str,
-                idents: Vec<PackageIdent>,
-                target: PackageTarget,
-                download_path: Option<&PathBuf>,
-                token: Option<&str>,
-                verify: bool)
-                -> Result<()>
-    where U: UIWriter
-{
-    debug!("Starting download with url: {}, channel: {}, product: {}, version: {}, target: {}, \
-            download_path: {:?}, token: {:?}, verify: {}, ident_count: {}",
-           url,
-           channel,
-           product,
-           version,
-           target,
-           download_path,
-           token,
-           verify,
-           idents.len());
+/// The longest an exponential backoff will wait between attempts.
+pub const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
 
-    let download_path_default = &cache_root_path::<PathBuf>(None); // Satisfy E0716
-    let download_path_expanded = download_path.unwrap_or(download_path_default).as_ref();
-    debug!("Using download_path {:?} expanded to {:?}",
-           download_path, download_path_expanded);
+/// How long a single request to Builder can take before it fails and is retried, unless
+/// overridden. A connection that's stalled outright would otherwise never return to be retried.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How much more space than the artifacts are expected to take that a download wants to have
+/// free, as a percentage. That leaves room for the keys, and for estimates that are off.
+const SPACE_MARGIN_PERCENT: u64 = 10;
 
-    if idents.is_empty() {
-        ui.fatal("No package identifers provided. Specify identifiers on the command line, or \
-                  via a input file")?;
-        return Err(CommonError::MissingCLIInputError(String::from("No package identifiers \
-                                                                   found")).into());
+/// The number of package idents that are resolved against Builder at the same time, unless
+/// overridden.
+pub const DEFAULT_RESOLVE_JOBS: usize = 4;
+
+/// The number of artifacts that are downloaded at the same time, unless overridden.
+pub const DEFAULT_CONCURRENT_DOWNLOADS: usize = 1;
+
+/// Where an artifact in the download directory came from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactSource {
+    /// It was already in the download directory
+    Cache,
+    /// It was fetched from Builder during this run
+    Builder,
+    /// It was in the local Habitat artifact cache, and was linked or copied from there during
+    /// this run
+    #[serde(rename = "local-cache")]
+    LocalCache,
+    /// Builder doesn't support its platform, so there's nothing to download
+    Unsupported,
+}
+
+impl ArtifactSource {
+    /// Whether the artifact only arrived in the download directory during this run, and so
+    /// hasn't been verified yet.
+    fn is_new(self) -> bool {
+        self == ArtifactSource::Builder || self == ArtifactSource::LocalCache
     }
+}
 
-    // We deliberately use None to specify the default path as this is used for cert paths, which
-    // we don't want to override.
-    let api_client = Client::new(url, product, version, None)?;
-    let task = DownloadTask { idents,
-                              target,
-                              url,
-                              api_client,
-                              token,
-                              channel,
-                              download_path: download_path_expanded,
-                              verify };
+type FetchResult = std::result::Result<ArtifactSource, RetryError<api_client::Error>>;
 
-    let download_count = task.execute(ui)?;
+type MetadataCache = HashMap<(PackageIdent, PackageTarget, ChannelIdent), Package>;
 
-    debug!("Expanded package count: {}", download_count);
+/// How the wait between attempts changes as they keep failing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetryBackoff {
+    /// Always wait the same amount of time
+    Fixed,
+    /// Double the wait after each attempt, up to `MAX_RETRY_WAIT`, with some random jitter so
+    /// that many clients don't retry in lockstep
+    Exponential,
+}
 
-    Ok(())
+/// How failed requests to Builder are retried, whether for package metadata, artifacts or keys.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retries: usize,
+    /// The wait before the first retry
+    pub wait:    Duration,
+    pub backoff: RetryBackoff,
 }
 
-struct DownloadTask<'a> {
-    idents:        Vec<PackageIdent>,
-    target:        PackageTarget,
-    url:           &'a str,
-    api_client:    BoxedClient,
-    token:         Option<&'a str>,
-    channel:       &'a ChannelIdent,
-    download_path: &'a Path,
-    verify:        bool,
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { retries: RETRIES,
+                      wait:    RETRY_WAIT,
+                      backoff: RetryBackoff::Fixed, }
+    }
 }
 
-impl<'a> DownloadTask<'a> {
-    fn execute<T>(&self, ui: &mut T) -> Result<usize>
-        where T: UIWriter
+impl RetryPolicy {
+    /// Runs `operation` until it succeeds, fails in a way that retrying won't fix, or runs out
+    /// of retries.
+    fn run<R, F>(&self, mut operation: F) -> std::result::Result<R, RetryError<api_client::Error>>
+        where F: FnMut() -> api_client::Result<R>
     {
-        // This was written intentionally with an eye towards data parallelism
-        // Any or all of these phases should naturally fit a fork-join model
-
-        ui.begin(format!("Resolving dependencies for {} package idents",
-                         self.idents.len()))?;
-        ui.begin(format!("Using channel {} from {}", self.channel, self.url))?;
-        ui.begin(format!("Using target {}", self.target))?;
-        ui.begin(format!("Storing in download directory {:?} ", self.download_path))?;
+        retry(self.delays(), || {
+            match operation() {
+                Ok(r) => OperationResult::Ok(r),
+                Err(e) if is_retryable(&e) => OperationResult::Retry(e),
+                Err(e) => OperationResult::Err(e),
+            }
+        })
+    }
 
-        self.verify_and_prepare_download_directory(ui)?;
+    /// The waits between each attempt, for passing to `retry`.
+    fn delays(&self) -> Box<dyn Iterator<Item = Duration>> {
+        match self.backoff {
+            RetryBackoff::Fixed => Box::new(delay::Fixed::from(self.wait).take(self.retries)),
+            RetryBackoff::Exponential => {
+                let first = self.wait.min(MAX_RETRY_WAIT);
+                Box::new(iter::successors(Some(first), |wait| {
+                             Some((*wait * 2).min(MAX_RETRY_WAIT))
+                         }).map(delay::jitter)
+                           .take(self.retries))
+            }
+        }
+    }
+}
 
-        // Phase 1: Expand to fully qualified deps and TDEPS
-        let expanded_idents = self.expand_sources(ui)?;
+/// Which of the downloaded files go in the SHA256SUMS file.
+#[derive(Clone, Copy, Debug)]
+pub enum ChecksumScope {
+    Artifacts,
+    ArtifactsAndKeys,
+}
 
-        // Phase 2: Download artifacts
-        let downloaded_artifacts = self.download_artifacts(ui, &expanded_idents)?;
+/// Where in the download directory artifacts and keys go.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DownloadLayout {
+    /// Artifacts in `artifacts/` and public keys in `keys/`, as `hab pkg bulkupload` reads them
+    Nested,
+    /// Both directly in the download directory, for mirrors that serve a single flat directory
+    Flat,
+    /// Artifacts in `<channel>/artifacts/` for the channel each package was found in, and public
+    /// keys in `keys/`, for mirrors that serve each channel on its own
+    ByChannel,
+}
 
-        Ok(downloaded_artifacts.len())
+impl DownloadLayout {
+    /// Where the artifacts found in `channel` go.
+    fn artifacts_path(self, download_path: &Path, channel: &ChannelIdent) -> PathBuf {
+        match self {
+            DownloadLayout::Nested => artifacts_path(download_path),
+            DownloadLayout::Flat => download_path.to_path_buf(),
+            DownloadLayout::ByChannel => artifacts_path(&download_path.join(channel.as_str())),
+        }
     }
 
-    // For each source, use the builder/depot to expand it to a fully qualifed form
-    // The same call gives us the TDEPS, add those as well.
-    fn expand_sources<T>(&self, ui: &mut T) -> Result<HashSet<(PackageIdent, PackageTarget)>>
-        where T: UIWriter
-    {
-        let mut expanded_packages = Vec::<Package>::new();
-        let mut expanded_idents = HashSet::<(PackageIdent, PackageTarget)>::new();
-
-        // This loop should be easy to convert to a parallel map.
-        for ident in &self.idents {
-            let package = self.determine_latest_from_ident(ui, &ident.clone(), self.target)?;
-            expanded_packages.push(package);
+    fn keys_path(self, download_path: &Path) -> PathBuf {
+        match self {
+            DownloadLayout::Nested | DownloadLayout::ByChannel => keys_path(download_path),
+            DownloadLayout::Flat => download_path.to_path_buf(),
         }
+    }
 
-        // Collect all the expanded deps into one structure
-        // Done separately because it's not as easy to parallelize
-        for package in expanded_packages {
-            for ident in package.tdeps {
-                expanded_idents.insert((ident.clone(), self.target));
+    /// Every directory of artifacts already in the download directory.
+    fn existing_artifacts_paths(self, download_path: &Path) -> io::Result<Vec<PathBuf>> {
+        if self != DownloadLayout::ByChannel {
+            return Ok(vec![self.artifacts_path(download_path, &ChannelIdent::stable())]);
+        }
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(download_path)? {
+            let path = artifacts_path(&entry?.path());
+            if path.is_dir() {
+                paths.push(path);
             }
-            expanded_idents.insert((package.ident.clone(), self.target));
         }
+        paths.sort();
+        Ok(paths)
+    }
+}
 
-        ui.status(Status::Found,
-                  format!("{} artifacts", expanded_idents.len()))?;
+/// How progress is reported, besides the usual terminal output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProgressFormat {
+    Human,
+    /// Also write each event as a line of JSON to stderr
+    Ndjson,
+}
 
-        Ok(expanded_idents)
+/// What to do with the artifacts in the download directory that aren't in the resolved set.
+#[derive(Clone, Copy, Debug)]
+pub struct PrunePolicy {
+    /// Also remove the public keys that none of the remaining artifacts are signed with
+    pub keys:    bool,
+    /// Only list what would be removed
+    pub dry_run: bool,
+}
+
+/// How a download uses its lockfile.
+#[derive(Clone, Copy, Debug)]
+pub enum LockfileMode<'a> {
+    /// Resolve the idents against Builder as usual, and record the result in this file
+    Write(&'a Path),
+    /// Don't resolve anything; download exactly the packages recorded in this file
+    Frozen(&'a Path),
+}
+
+/// Sets up a download, for running one from code rather than from the command line. Everything
+/// but the Builder to download from has the same default as `hab pkg download` does.
+#[derive(Debug)]
+pub struct DownloadBuilder<'a> {
+    url:                  &'a str,
+    fallback_urls:        Vec<&'a str>,
+    source:               Option<&'a Path>,
+    product:              &'a str,
+    version:              &'a str,
+    api_client:           Option<Box<dyn DownloadClient>>,
+    proxy:                Option<&'a str>,
+    client_identity:      Option<ClientIdentity>,
+    ca_bundle:            Option<&'a Path>,
+    user_agent:           Option<&'a str>,
+    filter_url:           Option<&'a str>,
+    channels:             Vec<ChannelIdent>,
+    idents:               Vec<PackageIdent>,
+    ident_files:          Vec<&'a Path>,
+    manifests:            Vec<&'a Path>,
+    target:               PackageTarget,
+    target_given:         bool,
+    no_deps:              bool,
+    build_deps:           bool,
+    max_depth:            Option<usize>,
+    keep_versions:        usize,
+    excludes:             Vec<PackageIdent>,
+    exclude_deps_of:      Vec<PackageIdent>,
+    since:                Option<DateTime<Utc>>,
+    download_path:        Option<PathBuf>,
+    layout:               DownloadLayout,
+    token:                Option<&'a str>,
+    verify:               bool,
+    require_signatures:   bool,
+    download_keys:        bool,
+    keys_from:            Option<&'a Path>,
+    trusted_origins:      Vec<String>,
+    force:                bool,
+    revalidate:           bool,
+    by_checksum:          bool,
+    use_local_cache:      bool,
+    fail_on_unsupported:  bool,
+    warn_on_wrong_target: bool,
+    continue_on_error:    bool,
+    check_space:          bool,
+    precheck:             bool,
+    resolve_jobs:         usize,
+    concurrent_downloads: usize,
+    verify_jobs:          Option<usize>,
+    verify_all:           bool,
+    reverify:             bool,
+    remove_if_unverified: bool,
+    clean_on_failure:     bool,
+    max_bandwidth:        Option<u64>,
+    api_rate_limit:       Option<u32>,
+    lockfile:             Option<LockfileMode<'a>>,
+    incremental:          bool,
+    graph:                Option<&'a Path>,
+    report_path:          Option<&'a Path>,
+    sbom:                 Option<&'a Path>,
+    with_metadata:        bool,
+    checksums:            Option<ChecksumScope>,
+    bundle:               Option<&'a Path>,
+    prune:                Option<PrunePolicy>,
+    retry_policy:         RetryPolicy,
+    request_timeout:      Duration,
+    deadline:             Option<Duration>,
+    quiet:                bool,
+    progress_format:      ProgressFormat,
+    observers:            Vec<Box<dyn DownloadObserver>>,
+    overall_progress:     bool,
+    json:                 bool,
+    stdout:               bool,
+    execution_strategy:   ExecutionStrategy,
+}
+
+impl<'a> DownloadBuilder<'a> {
+    /// Downloads from the Builder at `url`, identifying as `product` at `version` in the
+    /// User-Agent of each request.
+    pub fn new(url: &'a str, product: &'a str, version: &'a str) -> Self {
+        DownloadBuilder { url,
+                          fallback_urls: Vec::new(),
+                          source: None,
+                          product,
+                          version,
+                          api_client: None,
+                          proxy: None,
+                          client_identity: None,
+                          ca_bundle: None,
+                          user_agent: None,
+                          filter_url: None,
+                          channels: vec![ChannelIdent::stable()],
+                          idents: Vec::new(),
+                          ident_files: Vec::new(),
+                          manifests: Vec::new(),
+                          target: PackageTarget::active_target(),
+                          target_given: false,
+                          no_deps: false,
+                          build_deps: false,
+                          max_depth: None,
+                          keep_versions: 1,
+                          excludes: Vec::new(),
+                          exclude_deps_of: Vec::new(),
+                          since: None,
+                          download_path: None,
+                          layout: DownloadLayout::Nested,
+                          token: None,
+                          verify: false,
+                          require_signatures: false,
+                          download_keys: true,
+                          keys_from: None,
+                          trusted_origins: Vec::new(),
+                          force: false,
+                          revalidate: false,
+                          by_checksum: false,
+                          use_local_cache: true,
+                          fail_on_unsupported: false,
+                          warn_on_wrong_target: false,
+                          continue_on_error: false,
+                          check_space: true,
+                          precheck: false,
+                          resolve_jobs: DEFAULT_RESOLVE_JOBS,
+                          concurrent_downloads: DEFAULT_CONCURRENT_DOWNLOADS,
+                          verify_jobs: None,
+                          verify_all: false,
+                          reverify: false,
+                          remove_if_unverified: false,
+                          clean_on_failure: false,
+                          max_bandwidth: None,
+                          api_rate_limit: None,
+                          lockfile: None,
+                          incremental: false,
+                          graph: None,
+                          report_path: None,
+                          sbom: None,
+                          with_metadata: false,
+                          checksums: None,
+                          bundle: None,
+                          prune: None,
+                          retry_policy: RetryPolicy::default(),
+                          request_timeout: DEFAULT_REQUEST_TIMEOUT,
+                          deadline: None,
+                          quiet: false,
+                          progress_format: ProgressFormat::Human,
+                          observers: Vec::new(),
+                          overall_progress: false,
+                          json: false,
+                          stdout: false,
+                          execution_strategy: ExecutionStrategy::Run }
     }
 
-    fn download_artifacts<T>(&self,
-                             ui: &mut T,
-                             expanded_idents: &HashSet<(PackageIdent, PackageTarget)>)
-                             -> Result<Vec<PackageArchive>>
-        where T: UIWriter
-    {
-        let mut downloaded_artifacts = Vec::<PackageArchive>::new();
+    /// Adds a package to download, along with its dependencies.
+    pub fn ident(mut self, ident: PackageIdent) -> Self {
+        self.idents.push(ident);
+        self
+    }
 
-        ui.status(Status::Downloading,
-                  format!("Downloading {} artifacts (and their signing keys)",
-                          expanded_idents.len()))?;
+    /// Adds the packages listed in a file, one ident per line, as `--file` does.
+    pub fn ident_file(mut self, ident_file: &'a Path) -> Self {
+        self.ident_files.push(ident_file);
+        self
+    }
 
-        for (ident, target) in expanded_idents {
-            let archive: PackageArchive = match self.get_downloaded_archive(ui, ident, *target) {
-                Ok(v) => v,
-                Err(e) => {
-                    // Is this the right status? Or should this be a debug message?
-                    debug!("Error fetching archive {} for {}: {:?}", ident, *target, e);
-                    ui.status(Status::Missing,
-                              format!("Error fetching archive {} for {}", ident, *target))?;
-                    return Err(e);
-                }
-            };
+    /// Adds the packages listed in a download manifest. See the `manifest` module.
+    pub fn manifest(mut self, manifest: &'a Path) -> Self {
+        self.manifests.push(manifest);
+        self
+    }
 
-            downloaded_artifacts.push(archive);
-        }
+    /// The channels to look for packages in, in order. The default is just `stable`.
+    pub fn channels(mut self, channels: Vec<ChannelIdent>) -> Self {
+        self.channels = channels;
+        self
+    }
 
-        Ok(downloaded_artifacts)
+    /// The target for packages that don't name their own. This host's target, unless given.
+    pub fn target(mut self, target: PackageTarget) -> Self {
+        self.target = target;
+        self.target_given = true;
+        self
     }
 
-    fn determine_latest_from_ident<T>(&self,
-                                      ui: &mut T,
-                                      ident: &PackageIdent,
-                                      target: PackageTarget)
-                                      -> Result<Package>
-        where T: UIWriter
-    {
-        // Unlike in the install command, we always hit the online
-        // depot; our purpose is to sync with latest, and falling back
-        // to a local package would defeat that. Find the latest
-        // package in the proper channel from Builder API,
-        ui.status(Status::Determining, format!("latest version of {}", ident))?;
-        match self.fetch_latest_package_in_channel_for(ident, target, self.channel, self.token) {
-            Ok(latest_package) => {
-                ui.status(Status::Using, format!("{}", latest_package.ident))?;
-                Ok(latest_package)
-            }
-            Err(Error::APIClient(APIError(StatusCode::NOT_FOUND, _))) => {
-                // In install we attempt to recommend a channel to look in. That's a bit of a
-                // heavyweight process, and probably a bad idea in the context of
-                // what's a normally a batch process. It might be OK to fall back to
-                // the stable channel, but for now, error.
-                ui.warn(format!("No packages matching ident {} for {} exist in the '{}' \
-                                 channel. Check the package ident, target, channel and Builder \
-                                 url ({}) for correctness",
-                                ident, target, self.channel, self.url))?;
-                Err(CommonError::PackageNotFound(format!("{} for {} in channel {}",
-                                                         ident, target, self.channel)).into())
-            }
-            Err(e) => {
-                debug!("Error fetching ident {} for target {}: {:?}",
-                       ident, target, e);
-                ui.warn(format!("Error fetching ident {} for target {}", ident, target))?;
-                Err(e)
-            }
-        }
+    /// Talks to Builder through `api_client` instead of a client for `url`. Nothing else about
+    /// the client is set up then, including `max_bandwidth`.
+    pub fn api_client(mut self, api_client: Box<dyn DownloadClient>) -> Self {
+        self.api_client = Some(api_client);
+        self
     }
 
-    // This function and its sibling get_cached_artifact in
-    // install.rs deserve to be refactored to eke out commonality.
-    /// This ensures the identified package is in the local download directory,
-    /// verifies it, and returns a handle to the package's metadata.
-    fn get_downloaded_archive<T>(&self,
-                                 ui: &mut T,
-                                 ident: &PackageIdent,
-                                 target: PackageTarget)
-                                 -> Result<PackageArchive>
-        where T: UIWriter
-    {
-        let fetch_artifact = || self.fetch_artifact(ui, ident, target);
-        if self.downloaded_artifact_path(ident, target).is_file() {
-            debug!("Found {} in download directory, skipping remote download",
-                   ident);
-            ui.status(Status::Custom(Glyph::Elipses, String::from("Using cached")),
-                      format!("{}", ident))?;
-        } else if let Err(err) = retry(delay::Fixed::from(RETRY_WAIT).take(RETRIES), fetch_artifact)
-        {
-            return Err(CommonError::DownloadFailed(format!("We tried {} times but could not \
-                                                            download {} for {}. Last error \
-                                                            was: {}",
-                                                           RETRIES, ident, target, err)).into());
-        }
+    /// Resolves and downloads from the depot export at `source` instead of from Builder. See
+    /// `LocalDepot`. A `file://` URL does the same.
+    pub fn source(mut self, source: &'a Path) -> Self {
+        self.source = Some(source);
+        self
+    }
 
-        // At this point the artifact is in the download directory...
-        let mut artifact = PackageArchive::new(self.downloaded_artifact_path(ident, target));
-        self.fetch_keys_and_verify_artifact(ui, ident, target, &mut artifact)?;
-        Ok(artifact)
+    pub fn token(mut self, token: &'a str) -> Self {
+        self.token = Some(token);
+        self
     }
 
-    // This function and its sibling in install.rs deserve to be refactored to eke out commonality.
-    /// Retrieve the identified package from the depot, ensuring that
-    /// the artifact is downloaded.
-    fn fetch_artifact<T>(&self,
-                         ui: &mut T,
-                         ident: &PackageIdent,
-                         target: PackageTarget)
-                         -> Result<()>
-        where T: UIWriter
-    {
-        ui.status(Status::Downloading, format!("{}", ident))?;
-        match self.api_client.fetch_package((ident, target),
-                                            self.token,
-                                            &self.path_for_artifact(),
-                                            ui.progress())
-        {
-            Ok(_) => Ok(()),
-            Err(api_client::Error::APIError(StatusCode::NOT_IMPLEMENTED, _)) => {
-                println!("Host platform or architecture not supported by the targeted depot; \
-                          skipping.");
-                Ok(())
-            }
-            Err(e) => Err(e.into()),
-        }
+    /// Falls back to the Builder at `url` when the ones before it can't be reached. It should
+    /// serve the same packages, as a replica does.
+    pub fn fallback_url(mut self, url: &'a str) -> Self {
+        self.fallback_urls.push(url);
+        self
     }
 
-    fn fetch_origin_key<T>(&self,
-                           ui: &mut T,
-                           name_with_rev: &str,
-                           token: Option<&str>)
-                           -> Result<()>
-        where T: UIWriter
-    {
-        let (name, rev) = parse_name_with_rev(&name_with_rev)?;
-        self.api_client.fetch_origin_key(&name,
-                                          &rev,
-                                          token,
-                                          &self.path_for_keys(),
-                                          ui.progress())?;
-        Ok(())
+    pub fn proxy(mut self, proxy: &'a str) -> Self {
+        self.proxy = Some(proxy);
+        self
     }
 
-    fn fetch_keys_and_verify_artifact<T>(&self,
-                                         ui: &mut T,
-                                         ident: &PackageIdent,
-                                         target: PackageTarget,
-                                         artifact: &mut PackageArchive)
-                                         -> Result<()>
-        where T: UIWriter
-    {
-        // We need to look at the artifact to know the signing keys to fetch
-        // Once we have them, it's the natural time to verify.
-        // Otherwise, it might make sense to take this fetch out of the verification code.
-        let signer = artifact::artifact_signer(&artifact.path)?;
-        if SigKeyPair::get_public_key_path(&signer, &self.path_for_keys()).is_err() {
-            ui.status(Status::Downloading,
-                      format!("public key for signer {:?}", signer))?;
-            self.fetch_origin_key(ui, &signer, self.token)?;
-        }
+    pub fn client_identity(mut self, client_identity: ClientIdentity) -> Self {
+        self.client_identity = Some(client_identity);
+        self
+    }
 
-        if self.verify {
-            ui.status(Status::Verifying, artifact.ident()?)?;
-            artifact.verify(&self.path_for_keys())?;
-            debug!("Verified {} for {} signed by {}", ident, target, &signer);
-        }
-        Ok(())
+    /// Trusts the CA certificates in `ca_bundle` as well as the usual ones, for a Builder whose
+    /// certificate is signed by a private CA.
+    pub fn ca_bundle(mut self, ca_bundle: &'a Path) -> Self {
+        self.ca_bundle = Some(ca_bundle);
+        self
     }
 
-    // This function and its sibling in install.rs deserve to be refactored to eke out commonality.
-    /// Returns the path to the location this package would exist at in
-    /// the local package cache. It does not mean that the package is
-    /// actually *in* the package download directory, though.
-    fn downloaded_artifact_path(&self, ident: &PackageIdent, target: PackageTarget) -> PathBuf {
-        self.path_for_artifact()
-            .join(ident.archive_name_with_target(target).unwrap())
+    /// Sends this User-Agent with every request, instead of the one made from the product and
+    /// version, so that Builder's logs can tell a sync job's requests apart.
+    pub fn user_agent(mut self, user_agent: &'a str) -> Self {
+        self.user_agent = Some(user_agent);
+        self
     }
 
-    fn fetch_latest_package_in_channel_for(&self,
-                                           ident: &PackageIdent,
-                                           target: PackageTarget,
-                                           channel: &ChannelIdent,
-                                           token: Option<&str>)
-                                           -> Result<Package> {
-        self.api_client
-            .show_package_metadata((&ident, target), channel, token)
-            .map_err(Error::from)
+    /// Leaves out whatever the Builder at `filter_url` already has.
+    pub fn filter_url(mut self, filter_url: &'a str) -> Self {
+        self.filter_url = Some(filter_url);
+        self
     }
 
-    /// The cache_*_path functions in fs don't let you override a path base with Some(base)
-    /// So we have to build our own paths.
-    fn path_for_keys(&self) -> PathBuf { self.download_path.join("keys") }
+    /// Where the artifacts and keys go. The default is the Habitat cache.
+    pub fn download_path<P: Into<PathBuf>>(mut self, download_path: P) -> Self {
+        self.download_path = Some(download_path.into());
+        self
+    }
 
-    fn path_for_artifact(&self) -> PathBuf { self.download_path.join("artifacts") }
+    pub fn layout(mut self, layout: DownloadLayout) -> Self {
+        self.layout = layout;
+        self
+    }
 
-    /// Sanity check the download directory tree. The errors from the api around permissions are
-    /// opaque; this validates the directory in advance to help provide useful feedback.
-    fn verify_and_prepare_download_directory<T>(&self, ui: &mut T) -> Result<()>
-        where T: UIWriter
-    {
-        let system_paths = [self.download_path,
-                            &self.path_for_keys(),
-                            &self.path_for_artifact()];
+    pub fn no_deps(mut self, no_deps: bool) -> Self {
+        self.no_deps = no_deps;
+        self
+    }
 
-        ui.status(Status::Verifying,
-                  format!("the download directory \"{}\"",
-                          self.download_path.display()))?;
+    /// Downloads what the packages were built with as well as what they need to run, for
+    /// building them again somewhere that can't reach Builder.
+    pub fn build_deps(mut self, build_deps: bool) -> Self {
+        self.build_deps = build_deps;
+        self
+    }
 
-        let mut builder = DirBuilder::new();
-        builder.recursive(true);
+    /// Downloads the dependencies of each package only as far as `max_depth` levels down, where
+    /// 0 is none of them and 1 is only those it depends on directly.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
 
-        // Create directories if they don't exist
-        for dir in &system_paths {
-            builder.create(dir).map_err(|_| {
-                                    mk_perm_error(format!("Can't create directory {:?} needed \
-                                                           for download",
-                                                          dir))
-                                })?
-        }
+    /// Downloads the latest `keep_versions` releases of each package asked for in its channel,
+    /// and what each of them depends on, instead of only the latest, so that there's something
+    /// to roll back to. Idents that are fully qualified are the one release they name either way.
+    pub fn keep_versions(mut self, keep_versions: usize) -> Self {
+        self.keep_versions = keep_versions;
+        self
+    }
 
-        // Check permissions of directories:
-        for dir in &system_paths {
-            let metadata = std::fs::metadata(dir)?;
-            if !metadata.is_dir() {
-                return Err(mk_perm_error(format!("{} isn't a directory, needed for \
-                                                  download",
-                                                 dir.display())));
-            }
-            if metadata.permissions().readonly() {
-                return Err(mk_perm_error(format!("{} isn't writeable, needed for \
-                                                  download",
-                                                 dir.display())));
-            }
-        }
-        Ok(())
+    /// Leaves out a package, even when something else depends on it.
+    pub fn exclude(mut self, ident: PackageIdent) -> Self {
+        self.excludes.push(ident);
+        self
     }
-}
 
-fn mk_perm_error(msg: String) -> Error { CoreError::PermissionFailed(msg).into() }
+    /// Downloads a package asked for without what it depends on, which is to come from somewhere
+    /// else. Anything else asked for that depends on the same packages still gets them.
+    pub fn exclude_deps_of(mut self, ident: PackageIdent) -> Self {
+        self.exclude_deps_of.push(ident);
+        self
+    }
+
+    /// Leaves out packages released before `since`, such as the last time a mirror was synced.
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Fails if any artifact's signature can't be verified. Implies `verify`.
+    pub fn require_signatures(mut self, require_signatures: bool) -> Self {
+        self.require_signatures = require_signatures;
+        self
+    }
+
+    pub fn download_keys(mut self, download_keys: bool) -> Self {
+        self.download_keys = download_keys;
+        self
+    }
+
+    /// Trusts only the public keys in this directory, rather than any from Builder.
+    pub fn keys_from(mut self, keys_from: &'a Path) -> Self {
+        self.keys_from = Some(keys_from);
+        self
+    }
+
+    /// Accepts only artifacts signed by the origins trusted this way. An origin key's name with
+    /// its revision, like `core-20180119235000`, trusts just that one of the origin's keys.
+    pub fn trusted_origin(mut self, origin: &str) -> Self {
+        self.trusted_origins.push(origin.to_string());
+        self
+    }
+
+    /// Downloads every artifact again, even one that's already in the download directory.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Asks Builder again for each artifact already in the download directory that it gave an
+    /// ETag for, downloading it only if it has changed since.
+    pub fn revalidate(mut self, revalidate: bool) -> Self {
+        self.revalidate = revalidate;
+        self
+    }
+
+    /// Recognizes the artifacts already in the download directory by their checksum rather than
+    /// their file name, so that a renamed one is used and a wrong one is downloaded again.
+    pub fn by_checksum(mut self, by_checksum: bool) -> Self {
+        self.by_checksum = by_checksum;
+        self
+    }
+
+    pub fn use_local_cache(mut self, use_local_cache: bool) -> Self {
+        self.use_local_cache = use_local_cache;
+        self
+    }
+
+    pub fn fail_on_unsupported(mut self, fail_on_unsupported: bool) -> Self {
+        self.fail_on_unsupported = fail_on_unsupported;
+        self
+    }
+
+    /// Only warns about an artifact from Builder that's for some other target than the one
+    /// asked for, rather than failing.
+    pub fn warn_on_wrong_target(mut self, warn_on_wrong_target: bool) -> Self {
+        self.warn_on_wrong_target = warn_on_wrong_target;
+        self
+    }
+
+    /// Carries on past an artifact that can't be downloaded, failing only once the rest are
+    /// down.
+    pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+
+    pub fn check_space(mut self, check_space: bool) -> Self {
+        self.check_space = check_space;
+        self
+    }
+
+    /// Checks that Builder has an artifact for everything that was resolved before downloading
+    /// any of them, so that all the missing ones are reported up front.
+    pub fn precheck(mut self, precheck: bool) -> Self {
+        self.precheck = precheck;
+        self
+    }
+
+    pub fn resolve_jobs(mut self, resolve_jobs: usize) -> Self {
+        self.resolve_jobs = resolve_jobs;
+        self
+    }
+
+    pub fn concurrent_downloads(mut self, concurrent_downloads: usize) -> Self {
+        self.concurrent_downloads = concurrent_downloads;
+        self
+    }
+
+    /// Verifies the artifacts this many at a time once they're all downloaded. Implies `verify`.
+    pub fn verify_jobs(mut self, verify_jobs: usize) -> Self {
+        self.verify_jobs = Some(verify_jobs);
+        self
+    }
+
+    /// Verifies every artifact, cached ones included, once they're all downloaded, and fails
+    /// naming each one that doesn't verify. Implies `verify`.
+    pub fn verify_all(mut self, verify_all: bool) -> Self {
+        self.verify_all = verify_all;
+        self
+    }
+
+    /// Verifies artifacts that are recorded as having verified already, and haven't changed
+    /// since, rather than taking the record's word for it.
+    pub fn reverify(mut self, reverify: bool) -> Self {
+        self.reverify = reverify;
+        self
+    }
+
+    /// When `verify_all` finds an artifact that doesn't verify, removes it and everything else
+    /// this run put in the download directory, so that none of a run that failed is mistaken for
+    /// a good download. Only what was there before and verified stays.
+    pub fn remove_if_unverified(mut self, remove_if_unverified: bool) -> Self {
+        self.remove_if_unverified = remove_if_unverified;
+        self
+    }
+
+    /// Removes the artifacts and keys this run put in the download directory when it fails,
+    /// leaving what was there before it alone, so that the next attempt starts from where this
+    /// one did.
+    pub fn clean_on_failure(mut self, clean_on_failure: bool) -> Self {
+        self.clean_on_failure = clean_on_failure;
+        self
+    }
+
+    /// Limits all the downloads together to this many bytes per second.
+    pub fn max_bandwidth(mut self, bytes_per_sec: u64) -> Self {
+        self.max_bandwidth = Some(bytes_per_sec);
+        self
+    }
+
+    /// Makes no more than this many requests a second to resolve packages and fetch keys. See
+    /// `RateLimitedClient`.
+    pub fn api_rate_limit(mut self, requests_per_sec: u32) -> Self {
+        self.api_rate_limit = Some(requests_per_sec);
+        self
+    }
+
+    pub fn lockfile(mut self, lockfile: LockfileMode<'a>) -> Self {
+        self.lockfile = Some(lockfile);
+        self
+    }
+
+    /// Leaves out whatever the last successful download to the same directory got, going by the
+    /// sync state it left there.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Writes what each package resolved to and the dependencies that brought along to this file,
+    /// as JSON if it ends in `.json` and Graphviz DOT otherwise.
+    pub fn graph(mut self, graph: &'a Path) -> Self {
+        self.graph = Some(graph);
+        self
+    }
+
+    /// Writes the `DownloadReport` to this file as JSON, as well as returning it.
+    pub fn report_path(mut self, report_path: &'a Path) -> Self {
+        self.report_path = Some(report_path);
+        self
+    }
+
+    /// Writes a CycloneDX SBOM of the downloaded artifacts and their licenses to this file.
+    pub fn sbom(mut self, sbom: &'a Path) -> Self {
+        self.sbom = Some(sbom);
+        self
+    }
+
+    /// Writes the ident, target, channel, signer and checksum of each artifact to a JSON file
+    /// next to it.
+    pub fn with_metadata(mut self, with_metadata: bool) -> Self {
+        self.with_metadata = with_metadata;
+        self
+    }
+
+    pub fn checksums(mut self, checksums: ChecksumScope) -> Self {
+        self.checksums = Some(checksums);
+        self
+    }
+
+    pub fn bundle(mut self, bundle: &'a Path) -> Self {
+        self.bundle = Some(bundle);
+        self
+    }
+
+    pub fn prune(mut self, prune: PrunePolicy) -> Self {
+        self.prune = Some(prune);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Gives up on whatever's left once the download has run this long.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn progress_format(mut self, progress_format: ProgressFormat) -> Self {
+        self.progress_format = progress_format;
+        self
+    }
+
+    /// Tells `observer` about each event of the download as it happens. Can be called more than
+    /// once, to register more than one.
+    pub fn observer(mut self, observer: Box<dyn DownloadObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Shows one progress bar for all of the artifacts together, instead of one for each, and
+    /// another while resolving the package identifiers.
+    pub fn overall_progress(mut self, overall_progress: bool) -> Self {
+        self.overall_progress = overall_progress;
+        self
+    }
+
+    /// Prints the report as a JSON document at the end, in place of the summary.
+    pub fn json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// Writes the artifact to stdout as it arrives, instead of to the download directory. There
+    /// has to be exactly one fully qualified ident, and none of its dependencies are wanted. The
+    /// artifact isn't verified and no keys are fetched, so that's left to whatever reads it.
+    pub fn stdout(mut self, stdout: bool) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    pub fn execution_strategy(mut self, execution_strategy: ExecutionStrategy) -> Self {
+        self.execution_strategy = execution_strategy;
+        self
+    }
+
+    /// Reads any ident files and manifests and sets up the clients for Builder, for a download
+    /// that's ready to `execute`.
+    ///
+    /// # Errors
+    ///
+    /// * If there are no packages to download
+    /// * If an ident file or manifest can't be read
+    /// * If any of the idents is malformed, naming every one that is
+    /// * If a client for Builder can't be created
+    pub fn build<U>(self, ui: &mut U) -> Result<DownloadTask<'a>>
+        where U: UIWriter
+    {
+        debug!("Starting download with {:?}", self);
+
+        let download_path = self.download_path
+                                .unwrap_or_else(|| cache_root_path::<PathBuf>(None));
+        let download_path = long_path::for_download(&download_path);
+        debug!("Using download_path {:?}", download_path);
+
+        // A frozen download takes its idents from the lockfile instead
+        let frozen = if let Some(LockfileMode::Frozen(_)) = self.lockfile {
+            true
+        } else {
+            false
+        };
+        // Every bad ident is pointed out before anything goes to Builder, so that they can all
+        // be fixed in one go
+        let mut problems = Vec::new();
+        let mut sources = Vec::new();
+        for ident in self.idents {
+            problems.extend(ident_problem(&ident));
+            sources.push(DownloadSource::from(ident));
+        }
+        for ident_file in self.ident_files {
+            let path = ident_file.to_string_lossy();
+            for (line, ident) in file_into_ident_lines(&path)? {
+                match ident {
+                    Ok(ident) => {
+                        if let Some(problem) = ident_problem(&ident) {
+                            problems.push(format!("{} on line {} of {}", problem, line, path));
+                        }
+                        sources.push(DownloadSource::from(ident));
+                    }
+                    Err(e) => problems.push(e.to_string()),
+                }
+            }
+        }
+        for manifest in self.manifests {
+            let packages = Manifest::from_file(manifest)?.packages;
+            for (i, source) in packages.into_iter().enumerate() {
+                if let Some(problem) = ident_problem(&source.ident) {
+                    problems.push(format!("{} in package {} of {}",
+                                          problem,
+                                          i + 1,
+                                          manifest.display()));
+                }
+                sources.push(source);
+            }
+        }
+        if !problems.is_empty() {
+            return Err(Error::InvalidIdents(problems));
+        }
+        if sources.is_empty() && !frozen {
+            ui.fatal("No package identifers provided. Specify identifiers on the command line, \
+                      or via a input file")?;
+            return Err(CommonError::MissingCLIInputError(String::from("No package identifiers \
+                                                                       found")).into());
+        }
+
+        let (sources, duplicates) = dedupe(sources);
+        for source in duplicates {
+            ui.warn(format!("Ignoring duplicate package ident {}", source))?;
+        }
+        if self.stdout {
+            let single = sources.len() == 1 && sources[0].ident.fully_qualified();
+            let deps = self.build_deps || self.keep_versions > 1 || self.max_depth.is_some();
+            if !single || deps || frozen {
+                return Err(Error::ArgumentError(String::from("Only a single \
+                                                              artifact can be \
+                                                              written to stdout, \
+                                                              for one fully \
+                                                              qualified package \
+                                                              ident")));
+            }
+        }
+
+        // There's no point linking artifacts from the local cache when that's where they're being
+        // downloaded to, and forcing a download means wanting every artifact from Builder
+        let local_cache = cache_artifact_path(None::<PathBuf>);
+        let downloading_to_local_cache =
+            self.channels
+                .iter()
+                .any(|channel| self.layout.artifacts_path(&download_path, channel) == local_cache);
+        let use_local_cache = self.use_local_cache && !self.force && !downloading_to_local_cache;
+        let local_cache = if use_local_cache {
+            Some(local_cache)
+        } else {
+            None
+        };
+
+        // A bundle is checked against its SHA256SUMS, so it needs one that covers everything in it
+        let checksums = if self.bundle.is_some() {
+            Some(ChecksumScope::ArtifactsAndKeys)
+        } else {
+            self.checksums
+        };
+
+        // Requiring signatures is only a guarantee if they're checked, and asking for verification
+        // jobs is asking for verification
+        let verify =
+            self.verify || self.require_signatures || self.verify_jobs.is_some() || self.verify_all;
+        // Verifying everything at the end is what verification jobs do
+        let verify_jobs = if self.verify_all {
+            Some(self.verify_jobs.unwrap_or(1))
+        } else {
+            self.verify_jobs
+        };
+
+        // One client makes every request to Builder for the whole run, from however many workers,
+        // so it keeps its connections open for them to reuse.
+        let workers = self.resolve_jobs.max(self.concurrent_downloads);
+        let client_options = ApiClientOptions { proxy:           self.proxy.map(String::from),
+                                                client_identity: self.client_identity,
+                                                ca_bundle:       self.ca_bundle
+                                                                     .map(Path::to_path_buf),
+                                                user_agent:      self.user_agent.map(String::from),
+                                                keep_alive:      true,
+                                                max_idle:        Some(workers),
+                                                timeout:         Some(self.request_timeout), };
+        // A file:// URL is another way of giving a depot export, for where a URL is all that
+        // can be set, as with HAB_BLDR_URL
+        let url_source = local_depot::path_from_url(self.url);
+        if url_source.is_some() && !self.fallback_urls.is_empty() {
+            return Err(Error::ArgumentError(String::from("A file:// URL can't \
+                                                          have fallback URLs")));
+        }
+        let source = self.source.map(Path::to_path_buf).or(url_source);
+        let api_client = match (self.api_client, source) {
+            (Some(api_client), _) => api_client,
+            (None, Some(source)) => Box::new(LocalDepot::open(&source)?),
+            (None, None) => {
+                // All of the download workers share the one client, and so the one throttle.
+                // Only one Builder is used at a time, so the fallbacks share it too.
+                let throttle = self.max_bandwidth.map(Throttle::new);
+                let mut builders = Vec::with_capacity(1 + self.fallback_urls.len());
+                for url in iter::once(self.url).chain(self.fallback_urls.iter().cloned()) {
+                    // We deliberately use None to specify the default path as this is used for
+                    // cert paths, which we don't want to override.
+                    let api_client = BuilderAPIClient::with_options(url,
+                                                                    self.product,
+                                                                    self.version,
+                                                                    None,
+                                                                    &client_options)?;
+                    let api_client = if self.revalidate {
+                        api_client.revalidating()
+                    } else {
+                        api_client
+                    };
+                    let api_client: BoxedClient = match throttle {
+                        Some(ref throttle) => Box::new(api_client.throttled(throttle.clone())),
+                        None => Box::new(api_client),
+                    };
+                    builders.push((url.to_string(),
+                                   Box::new(api_client) as Box<dyn DownloadClient>));
+                }
+                if builders.len() == 1 {
+                    builders.pop().unwrap().1
+                } else {
+                    Box::new(FailoverClient::new(builders))
+                }
+            }
+        };
+        let api_client: Box<dyn DownloadClient> = match self.api_rate_limit {
+            Some(requests_per_sec) => {
+                Box::new(RateLimitedClient::new(api_client, requests_per_sec))
+            }
+            None => api_client,
+        };
+        let filter_builder = match self.filter_url {
+            Some(filter_url) => {
+                let filter_client = BuilderAPIClient::with_options(filter_url,
+                                                                   self.product,
+                                                                   self.version,
+                                                                   None,
+                                                                   &client_options)?;
+                let filter_client: BoxedClient = Box::new(filter_client);
+                Some((filter_url, Box::new(filter_client) as Box<dyn DownloadClient>))
+            }
+            None => None,
+        };
+        let mut observers = self.observers;
+        if self.progress_format == ProgressFormat::Ndjson {
+            observers.push(Box::new(NdjsonEvents));
+        }
+        Ok(DownloadTask { sources,
+                          target: self.target,
+                          target_detected: !self.target_given,
+                          no_deps: self.no_deps,
+                          build_deps: self.build_deps,
+                          max_depth: self.max_depth,
+                          keep_versions: self.keep_versions,
+                          excludes: self.excludes,
+                          exclude_deps_of: self.exclude_deps_of,
+                          since: self.since,
+                          url: self.url,
+                          api_client,
+                          filter_builder,
+                          token: self.token,
+                          channels: self.channels,
+                          download_path,
+                          layout: self.layout,
+                          verify,
+                          require_signatures: self.require_signatures,
+                          download_keys: self.download_keys,
+                          keys_from: self.keys_from,
+                          trusted_origins: self.trusted_origins,
+                          force: self.force,
+                          revalidate: self.revalidate,
+                          by_checksum: self.by_checksum,
+                          local_cache,
+                          fail_on_unsupported: self.fail_on_unsupported,
+                          warn_on_wrong_target: self.warn_on_wrong_target,
+                          continue_on_error: self.continue_on_error,
+                          check_space: self.check_space,
+                          precheck: self.precheck,
+                          resolve_jobs: self.resolve_jobs,
+                          concurrent_downloads: self.concurrent_downloads,
+                          verify_jobs,
+                          verify_all: self.verify_all,
+                          reverify: self.reverify,
+                          remove_if_unverified: self.remove_if_unverified,
+                          clean_on_failure: self.clean_on_failure,
+                          lockfile: self.lockfile,
+                          incremental: self.incremental,
+                          graph: self.graph,
+                          report_path: self.report_path,
+                          sbom: self.sbom,
+                          with_metadata: self.with_metadata,
+                          checksums,
+                          bundle: self.bundle,
+                          prune: self.prune,
+                          retry_policy: self.retry_policy,
+                          deadline: self.deadline.map(|budget| Instant::now() + budget),
+                          quiet: self.quiet,
+                          observers,
+                          overall_progress: self.overall_progress,
+                          json: self.json,
+                          stdout: self.stdout,
+                          execution_strategy: self.execution_strategy,
+                          interrupted: AtomicBool::new(false),
+                          keys_downloaded: AtomicUsize::new(0),
+                          metadata_cache: Mutex::new(HashMap::new()),
+                          ensured_signers: Mutex::new(HashSet::new()),
+                          signers: Mutex::new(HashMap::new()),
+                          resolved_channels: Mutex::new(HashMap::new()),
+                          created: Mutex::new(Vec::new()) })
+    }
+}
+
+/// Download a Habitat package, as `download` is set up to.
+///
+/// If an `PackageIdent` is given, we retrieve the package from the specified Builder
+/// `url`. Providing a fully-qualified identifer will result in that exact package being downloaded
+/// (regardless of `channels`). Providing a partially-qualified identifier will result in the
+/// installation of latest appropriate release from the first of the given `channels` that has
+/// a release.
+///
+/// Any dependencies of will be retrieved from Builder (if they're not already downloaded locally).
+///
+/// At the end of this function, the specified package and all its
+/// dependencies will be downloaded on the system in the
+/// <download_path>/artifacts directory. Any signing keys will also be
+/// downloaded and put in the <download_path/keys> directory. With the `Flat`
+/// `layout`, both go in <download_path> itself.
+///
+/// If a `filter_url` is given, anything that the Builder there already has is left out. That
+/// greatly optimizes the 'sync' to on prem builder case, as we can point to that and only fetch
+/// what we don't already have.
+pub fn start<U>(ui: &mut U, download: DownloadBuilder<'_>) -> Result<DownloadReport>
+    where U: UIWriter
+{
+    // Ctrl-C stops the download once what's underway is done, instead of killing it outright
+    let _trap = interrupt::trap();
+    download.build(ui)?.execute(ui)
+}
+
+/// Verifies every artifact already in a download directory against the keys alongside it,
+/// without making any network calls. All of the artifacts are checked, and then this fails if
+/// any of them didn't verify.
+pub fn verify_only<U>(ui: &mut U,
+                      download_path: Option<&PathBuf>,
+                      layout: DownloadLayout)
+                      -> Result<()>
+    where U: UIWriter
+{
+    let download_path_default = &cache_root_path::<PathBuf>(None); // Satisfy E0716
+    let download_path = download_path.unwrap_or(download_path_default);
+    let keys_path = layout.keys_path(download_path);
+
+    ui.begin(format!("Verifying the artifacts in download directory {:?}",
+                     download_path))?;
+
+    let mut artifacts = Vec::new();
+    for artifacts_path in layout.existing_artifacts_paths(download_path)? {
+        for entry in fs::read_dir(&artifacts_path)? {
+            let path = entry?.path();
+            if path.extension().and_then(OsStr::to_str) == Some("hart") {
+                artifacts.push(path);
+            }
+        }
+    }
+    artifacts.sort();
+
+    let mut failed = 0;
+    for path in &artifacts {
+        let name = path.file_name().unwrap_or_else(|| path.as_os_str());
+        match PackageArchive::new(path).verify(&keys_path) {
+            Ok((signer, _)) => {
+                debug!("Verified {} signed by {}", path.display(), signer);
+                ui.status(Status::Verified, name.to_string_lossy())?;
+            }
+            Err(e) => {
+                failed += 1;
+                ui.status(Status::Custom(Glyph::ErrorX, String::from("Failed")),
+                          format!("{}: {}", name.to_string_lossy(), e))?;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::CryptoCLI(format!("{} of {} artifacts in {} \
+                                             failed verification",
+                                            failed,
+                                            artifacts.len(),
+                                            download_path.display())));
+    }
+    ui.end(format!("Verified {} artifacts", artifacts.len()))?;
+    Ok(())
+}
+
+/// A download that's ready to run, from `DownloadBuilder::build`.
+pub struct DownloadTask<'a> {
+    sources:              Vec<DownloadSource>,
+    /// The target for sources that don't name one
+    target:               PackageTarget,
+    /// Whether `target` is this host's, since none was given
+    target_detected:      bool,
+    /// Download only the packages asked for, and none of their dependencies
+    no_deps:              bool,
+    /// Also download the packages' build dependencies, and what those need to run
+    build_deps:           bool,
+    /// How many levels of dependencies to download, when not all of them
+    max_depth:            Option<usize>,
+    /// How many of the latest releases of each source to download, with what they depend on
+    keep_versions:        usize,
+    /// Packages to leave out, even when something else depends on them
+    excludes:             Vec<PackageIdent>,
+    /// Packages asked for whose dependencies are to be left out, unless something else needs
+    /// them
+    exclude_deps_of:      Vec<PackageIdent>,
+    /// Leave out packages released before this
+    since:                Option<DateTime<Utc>>,
+    url:                  &'a str,
+    api_client:           Box<dyn DownloadClient>,
+    /// The Builder being synced to, and a client for it
+    filter_builder:       Option<(&'a str, Box<dyn DownloadClient>)>,
+    token:                Option<&'a str>,
+    channels:             Vec<ChannelIdent>,
+    download_path:        PathBuf,
+    layout:               DownloadLayout,
+    verify:               bool,
+    require_signatures:   bool,
+    download_keys:        bool,
+    /// Trusted public keys to use instead of any from Builder
+    keys_from:            Option<&'a Path>,
+    /// The origins, or origin keys, whose artifacts are accepted. Any are when there are none
+    trusted_origins:      Vec<String>,
+    force:                bool,
+    /// Ask again for artifacts that have an ETag, rather than taking them as they are
+    revalidate:           bool,
+    /// Match artifacts in the download directory on their checksum from Builder's metadata,
+    /// rather than on their file name
+    by_checksum:          bool,
+    /// The local Habitat artifact cache, to take artifacts from rather than downloading them
+    local_cache:          Option<PathBuf>,
+    fail_on_unsupported:  bool,
+    /// Only warn about an artifact from Builder that's for some other target than asked for
+    warn_on_wrong_target: bool,
+    /// Record artifacts that can't be downloaded and carry on, rather than stopping at the first
+    continue_on_error:    bool,
+    check_space:          bool,
+    /// Make sure Builder has every artifact before downloading any
+    precheck:             bool,
+    resolve_jobs:         usize,
+    concurrent_downloads: usize,
+    /// Verify the downloaded artifacts with this many workers once they're all down, rather
+    /// than each one as it lands
+    verify_jobs:          Option<usize>,
+    /// Leave verifying cached artifacts to verify_artifacts too, and have it check every
+    /// artifact before failing
+    verify_all:           bool,
+    /// Verify artifacts even when the record next to them says they already have been
+    reverify:             bool,
+    remove_if_unverified: bool,
+    clean_on_failure:     bool,
+    lockfile:             Option<LockfileMode<'a>>,
+    /// Leave out what the sync state says the last successful run got
+    incremental:          bool,
+    /// Where to write the dependency graph of what was resolved
+    graph:                Option<&'a Path>,
+    report_path:          Option<&'a Path>,
+    /// Where to write the bill of materials, with the licenses of everything downloaded
+    sbom:                 Option<&'a Path>,
+    /// Write a JSON file of what's known about each artifact next to it
+    with_metadata:        bool,
+    checksums:            Option<ChecksumScope>,
+    /// A tarball to pack the download directory into, once it's all there
+    bundle:               Option<&'a Path>,
+    prune:                Option<PrunePolicy>,
+    retry_policy:         RetryPolicy,
+    /// When to stop starting anything new, and give up on whatever's left
+    deadline:             Option<Instant>,
+    /// Leave out the status lines about individual idents and artifacts
+    quiet:                bool,
+    /// Told about each event of the download, such as an artifact being downloaded
+    observers:            Vec<Box<dyn DownloadObserver>>,
+    /// Show one progress bar for the whole download in place of one for each artifact
+    overall_progress:     bool,
+    /// Print the report as JSON in place of the summary
+    json:                 bool,
+    /// Write the one artifact to stdout, and nothing else
+    stdout:               bool,
+    execution_strategy:   ExecutionStrategy,
+    /// Set once the download has been interrupted, so that nothing more gets started
+    interrupted:          AtomicBool,
+    /// How many public keys this run has fetched from Builder
+    keys_downloaded:      AtomicUsize,
+    /// The package metadata already fetched for an ident in a channel, so that asking again
+    /// doesn't go back to Builder
+    metadata_cache:       Mutex<MetadataCache>,
+    /// The signers whose public keys are already where verification will look for them
+    ensured_signers:      Mutex<HashSet<String>>,
+    /// The signer of each artifact already read from its header, by path
+    signers:              Mutex<HashMap<PathBuf, String>>,
+    /// The channel each artifact was found in, for the by-channel layout. A dependency is in
+    /// the channel of the first package found to need it.
+    resolved_channels:    Mutex<HashMap<(PackageIdent, PackageTarget), ChannelIdent>>,
+    /// The artifacts and keys this run has put in the download directory, for
+    /// `clean_on_failure` and `remove_if_unverified` to remove
+    created:              Mutex<Vec<PathBuf>>,
+}
+
+impl<'a> DownloadTask<'a> {
+    /// Returns what each ident resolved to, and what happened to each artifact, including which
+    /// key signed it. A dry run doesn't download anything, so its report only has the
+    /// resolution.
+    pub fn execute<T>(&self, ui: &mut T) -> Result<DownloadReport>
+        where T: UIWriter
+    {
+        let executed = self.run_download(ui);
+        if executed.is_err() && self.clean_on_failure {
+            self.remove_created(ui)?;
+        }
+        executed
+    }
+
+    fn run_download<T>(&self, ui: &mut T) -> Result<DownloadReport>
+        where T: UIWriter
+    {
+        // This was written intentionally with an eye towards data parallelism
+        // Any or all of these phases should naturally fit a fork-join model
+
+        let started = Instant::now();
+
+        if self.stdout {
+            return self.stream_artifact(&mut io::stdout());
+        }
+        if let Some(LockfileMode::Frozen(lockfile)) = self.lockfile {
+            ui.begin(format!("Using the packages locked in {}", lockfile.display()))?;
+        } else {
+            ui.begin(format!("Resolving dependencies for {} package idents",
+                             self.sources.len()))?;
+        }
+        ui.begin(format!("Using channel {} from {}",
+                         channel_list(&self.channels),
+                         self.builder_url()))?;
+        if !self.target_detected {
+            ui.begin(format!("Using target {}", self.target))?;
+        } else if self.sources.iter().any(|source| source.target.is_none()) {
+            // Worth pointing out only if there's something it's the target for
+            ui.begin(format!("Using target {}, this host's, since none was given",
+                             self.target))?;
+        }
+        ui.begin(format!("Storing in download directory {:?} ", self.download_path))?;
+
+        // A dry run only talks to Builder, so it leaves the download directory alone
+        if let ExecutionStrategy::Run = self.execution_strategy {
+            self.verify_and_prepare_download_directory(ui)?;
+        }
+
+        let last_sync = if self.incremental {
+            self.read_sync_state(ui)?
+        } else {
+            None
+        };
+
+        // Phase 1: Expand to fully qualified deps and TDEPS
+        let resolving = Instant::now();
+        let mut report = DownloadReport::default();
+        let wanted_idents = if let Some(LockfileMode::Frozen(lockfile)) = self.lockfile {
+            self.expand_locked_sources(ui, lockfile)?
+        } else {
+            self.check_channels_exist()?;
+            let resolved_packages = self.resolve_sources(ui)?;
+            if let Some(LockfileMode::Write(lockfile)) = self.lockfile {
+                ui.status(Status::Creating, format!("lockfile {}", lockfile.display()))?;
+                Lockfile::new(&resolved_packages).write_to(lockfile)?;
+            }
+            if let Some(graph) = self.graph {
+                ui.status(Status::Creating,
+                          format!("dependency graph {}", graph.display()))?;
+                write_graph(graph, &resolved_packages)?;
+            }
+            report.resolved =
+                resolved_packages.iter()
+                                 .map(|(requested, target, package)| {
+                                     ResolvedIdent { requested: requested.clone(),
+                                                     target:    *target,
+                                                     resolved:  package.ident.clone(), }
+                                 })
+                                 .collect();
+            self.expand_sources(ui, resolved_packages)?
+        };
+        // Anything excluded, or that the filter Builder has, is still wanted as far as pruning
+        // goes
+        let expanded_idents = self.exclude(ui, wanted_idents.clone())?;
+        let expanded_idents = self.skip_older(ui, expanded_idents)?;
+        let expanded_idents = self.filter_existing(ui, expanded_idents)?;
+        let expanded_idents = skip_synced(ui, last_sync.as_ref(), expanded_idents)?;
+        let expanded_idents = if self.precheck {
+            self.check_existence(ui, &mut report, expanded_idents)?
+        } else {
+            expanded_idents
+        };
+        if self.by_checksum {
+            if let ExecutionStrategy::Run = self.execution_strategy {
+                self.match_by_checksum(ui, &expanded_idents)?;
+            }
+        }
+        report.timings.resolve_secs = resolving.elapsed().as_secs_f64();
+        logging::log_duration("resolve", report.timings.resolve_secs);
+
+        if let ExecutionStrategy::DryRun = self.execution_strategy {
+            print_resolved_idents(ui, &expanded_idents)?;
+            self.prune(ui, &wanted_idents)?;
+            ui.end(format!("Would download {} artifacts (Dry run)",
+                           expanded_idents.len()))?;
+            if self.json {
+                print_document(&mut report, started.elapsed())?;
+            }
+            return Ok(report);
+        }
+
+        let expected_bytes = if self.check_space {
+            self.check_available_space(ui, &expanded_idents)?
+        } else {
+            None
+        };
+
+        // Phase 2: Download artifacts
+        self.check_deadline(|| artifact_names(&expanded_idents))?;
+        self.check_interrupted(|| artifact_names(&expanded_idents))?;
+        let downloading = Instant::now();
+        let downloaded_artifacts =
+            self.download_artifacts(ui, &expanded_idents, expected_bytes, &mut report);
+        report.timings.download_secs = downloading.elapsed().as_secs_f64();
+        logging::log_duration("download", report.timings.download_secs);
+        if let Err(Error::Interrupted(_)) = downloaded_artifacts {
+            // Everything that was finished is in the download directory, for the next run to use
+            report.keys_downloaded = self.keys_downloaded.load(Ordering::Relaxed);
+            self.print_report(ui, &mut report, started.elapsed())?;
+        }
+        let downloaded_artifacts = downloaded_artifacts?;
+        debug!("Downloaded {} artifacts", downloaded_artifacts.len());
+        if let Some(jobs) = self.verify_jobs {
+            let fetching_keys = Instant::now();
+            self.fetch_signer_keys(ui, &report)?;
+            let key_fetch_secs = fetching_keys.elapsed().as_secs_f64();
+            logging::log_duration("key-fetch", key_fetch_secs);
+            report.timings.key_fetch_secs = Some(key_fetch_secs);
+            let verifying = Instant::now();
+            let verified = self.verify_artifacts(ui, jobs, &report);
+            let verify_secs = verifying.elapsed().as_secs_f64();
+            logging::log_duration("verify", verify_secs);
+            report.timings.verify_secs = Some(verify_secs);
+            verified?;
+        }
+        self.reconcile(&expanded_idents, &report)?;
+        report.keys_downloaded = self.keys_downloaded.load(Ordering::Relaxed);
+
+        if let Some(report_path) = self.report_path {
+            ui.status(Status::Creating,
+                      format!("download report {}", report_path.display()))?;
+            report.write_to(report_path)?;
+        }
+        if let Some(sbom) = self.sbom {
+            ui.status(Status::Creating, format!("SBOM {}", sbom.display()))?;
+            for unlicensed in write_sbom(sbom, &self.paths_for_artifacts()?, &report)? {
+                ui.warn(format!("{} doesn't say what its license is", unlicensed))?;
+            }
+        }
+        for skipped in &report.skipped {
+            ui.warn(format!("Skipped {} for {}: the depot doesn't support that platform",
+                            skipped.ident, skipped.target))?;
+        }
+
+        // Phase 3: Remove what we no longer want
+        self.prune(ui, &wanted_idents)?;
+
+        // Phase 4: Checksum what's in the download directory now
+        if let Some(scope) = self.checksums {
+            let mut files = Vec::new();
+            for dir in self.paths_for_artifacts()? {
+                files.extend(files_with_extension(&dir, "hart")?);
+            }
+            if let ChecksumScope::ArtifactsAndKeys = scope {
+                files.extend(files_with_extension(&self.path_for_keys(), "pub")?);
+            }
+            let sums = write_sha256sums(&self.download_path, &files)?;
+            ui.status(Status::Created, format!("{}", sums.display()))?;
+        }
+
+        // Phase 5: Pack it all up
+        if let Some(bundle) = self.bundle {
+            write_bundle(bundle,
+                         &self.download_path,
+                         &self.paths_for_artifacts()?,
+                         &self.path_for_keys(),
+                         &|| self.is_interrupted())?;
+            ui.status(Status::Created, format!("bundle {}", bundle.display()))?;
+        }
+
+        self.print_report(ui, &mut report, started.elapsed())?;
+        if !report.failed.is_empty() {
+            for failed in &report.failed {
+                ui.warn(format!("Couldn't download {} for {}: {}",
+                                failed.ident, failed.target, failed.error))?;
+            }
+            let failed = report.failed
+                               .iter()
+                               .map(|f| format!("{} for {}", f.ident, f.target))
+                               .collect();
+            return Err(Error::DownloadsFailed(failed));
+        }
+
+        // Phase 6: Remember what's been got, for the next incremental run
+        let mut synced: HashSet<_> = report.artifacts
+                                           .iter()
+                                           .map(|a| (a.ident.clone(), a.target))
+                                           .collect();
+        if let Some(last_sync) = last_sync {
+            synced.extend(last_sync.synced());
+        }
+        SyncState::new(&synced).write_to(&self.download_path)?;
+        Ok(report)
+    }
+
+    /// Prints the summary of the download, or with `json` the whole report.
+    fn print_report<T>(&self,
+                       ui: &mut T,
+                       report: &mut DownloadReport,
+                       elapsed: Duration)
+                       -> Result<()>
+        where T: UIWriter
+    {
+        if self.json {
+            print_document(report, elapsed)
+        } else {
+            print_summary(ui,
+                          self.sources.len(),
+                          &self.download_path,
+                          &self.paths_for_artifacts()?,
+                          report,
+                          elapsed)
+        }
+    }
+
+    fn read_sync_state<T>(&self, ui: &mut T) -> Result<Option<SyncState>>
+        where T: UIWriter
+    {
+        let state = SyncState::read(&self.download_path)?;
+        match state {
+            Some(ref state) => {
+                ui.status(Status::Using,
+                          format!("the sync state from {}, with {} artifacts",
+                                  state.last_sync.to_rfc3339(),
+                                  state.artifacts.len()))?
+            }
+            None => {
+                ui.warn(format!("There's no sync state in {} to download incrementally from, so \
+                                 everything will be downloaded",
+                                self.download_path.display()))?
+            }
+        }
+        Ok(state)
+    }
+
+    /// Fails once, up front, if none of the channels a package ident is to be looked for in are in
+    /// its origin, rather than every ident failing on its own for not being found. Origins whose
+    /// channels can't be listed are left for resolving to find out about.
+    fn check_channels_exist(&self) -> Result<()> {
+        let mut listed = HashMap::new();
+        for source in &self.sources {
+            let origin = source.ident.origin.as_str();
+            let existing = listed.entry(origin)
+                                 .or_insert_with(|| self.channels_in(origin));
+            if let Some(ref existing) = *existing {
+                let channels = self.channels_for(source);
+                if !channels.iter()
+                            .any(|channel| existing.iter().any(|e| e == channel.as_str()))
+                {
+                    return Err(Error::ChannelNotFound(channel_list(channels), origin.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The channels in `origin`, if they can be listed.
+    fn channels_in(&self, origin: &str) -> Option<Vec<String>> {
+        match self.api_client.list_channels(origin) {
+            Ok(channels) => channels,
+            Err(e) => {
+                debug!("Couldn't list the channels in origin {}, so not checking them: {}",
+                       origin, e);
+                None
+            }
+        }
+    }
+
+    // For each source, use the builder/depot to expand it to a fully qualifed form
+    // The same call gives us the TDEPS, which expand_sources adds as well.
+    fn resolve_sources<T>(&self, ui: &mut T) -> Result<Vec<(PackageIdent, PackageTarget, Package)>>
+        where T: UIWriter
+    {
+        let mut resolved_packages = Vec::<(PackageIdent, PackageTarget, Package)>::new();
+
+        let sources = self.expand_wildcards(ui)?;
+        debug!("Resolving {} idents with up to {} workers",
+               sources.len(),
+               self.resolve_jobs);
+
+        // Only the Builder lookups run on the workers; each result is reported from this thread
+        // as it arrives, so the status output stays line-oriented.
+        let mut left: HashSet<_> = sources.iter().collect();
+        let mut overall = if self.overall_progress && !sources.is_empty() {
+            Some(OverallProgress::resolving(sources.len()))
+        } else {
+            None
+        };
+        for_each_parallel(self.resolve_jobs,
+                          &sources,
+                          |source| {
+                              let target = self.target_for(source);
+                              let channels = self.channels_for(source);
+                              logging::with_artifact(&source.ident, target, "resolve", || {
+                                  let latest =
+                                      self.fetch_latest_package_in_channels_for(&source.ident,
+                                                                                target,
+                                                                                channels,
+                                                                                self.token);
+                                  let older = match latest {
+                                      Ok((ref package, channel)) => {
+                                          self.fetch_older_releases(&source.ident,
+                                                                    &package.ident,
+                                                                    target,
+                                                                    channel)
+                                      }
+                                      Err(_) => Ok(Vec::new()),
+                                  };
+                                  (latest, older)
+                              })
+                          },
+                          |source, (fetched, older)| {
+                              let target = self.target_for(source);
+                              let outcome = match fetched {
+                                  // Not everything in an origin is released for every target in
+                                  // every channel
+                                  Err(APIError(StatusCode::NOT_FOUND, _))
+                                      if source.from_wildcard =>
+                                  {
+                                      let channels = channel_list(self.channels_for(source));
+                                      self.status(ui,
+                                                  Status::Skipping,
+                                                  format!("{} for {}: not in channel {}",
+                                                          source.ident, target, channels))
+                                  }
+                                  fetched => {
+                                      let channel = fetched.as_ref()
+                                                           .ok()
+                                                           .map(|(_, channel)| (*channel).clone());
+                                      let package =
+                                          self.determine_latest_from_ident(ui,
+                                                                           &source.ident,
+                                                                           target,
+                                                                           self.channels_for(source),
+                                                                           fetched)?;
+                                      self.record_channel(&package, target, channel.as_ref());
+                                      resolved_packages.push((source.ident.clone(),
+                                                              target,
+                                                              package));
+                                      for package in older? {
+                                          self.status(ui,
+                                                      Status::Using,
+                                                      format!("{} as well", package.ident))?;
+                                          self.record_channel(&package, target, channel.as_ref());
+                                          resolved_packages.push((source.ident.clone(),
+                                                                  target,
+                                                                  package));
+                                      }
+                                      Ok(())
+                                  }
+                              };
+                              left.remove(&source);
+                              if let Some(ref mut overall) = overall {
+                                  overall.update(sources.len() - left.len(), 0);
+                              }
+                              outcome?;
+                              self.check_deadline(|| {
+                                      left.iter().map(ToString::to_string).collect()
+                                  })?;
+                              self.check_interrupted(|| {
+                                      left.iter().map(ToString::to_string).collect()
+                                  })
+                          })?;
+
+        if let Some(ref mut overall) = overall {
+            overall.finish();
+        }
+        Ok(resolved_packages)
+    }
+
+    // Replaces each `origin/*` source with one for every package in that origin. Builder lists
+    // package names without regard to target or channel, so resolving them sorts that out. A
+    // package that's also given by name keeps what its own entry says.
+    fn expand_wildcards<T>(&self, ui: &mut T) -> Result<Vec<DownloadSource>>
+        where T: UIWriter
+    {
+        let mut named: HashSet<_> = self.sources
+                                        .iter()
+                                        .filter(|source| !source.is_wildcard())
+                                        .map(|source| {
+                                            (source.ident.origin.clone(),
+                                             source.ident.name.clone(),
+                                             self.target_for(source))
+                                        })
+                                        .collect();
+        let mut sources = Vec::new();
+        for source in &self.sources {
+            if !source.is_wildcard() {
+                sources.push(source.clone());
+                continue;
+            }
+            let origin = &source.ident.origin;
+            let packages = self.retry_policy
+                               .run(|| self.api_client.list_origin_packages(origin, self.token))
+                               .map_err(last_attempt_error)?;
+            ui.status(Status::Found,
+                      format!("{} packages in origin {}", packages.len(), origin))?;
+            let target = self.target_for(source);
+            for ident in packages {
+                if named.insert((ident.origin.clone(), ident.name.clone(), target)) {
+                    sources.push(DownloadSource { ident,
+                                                  from_wildcard: true,
+                                                  ..source.clone() });
+                }
+            }
+        }
+        Ok(sources)
+    }
+
+    // Collect all the expanded deps into one structure
+    // Done separately because it's not as easy to parallelize
+    fn expand_sources<T>(&self,
+                         ui: &mut T,
+                         resolved_packages: Vec<(PackageIdent, PackageTarget, Package)>)
+                         -> Result<HashSet<(PackageIdent, PackageTarget)>>
+        where T: UIWriter
+    {
+        let mut expanded_idents = HashSet::<(PackageIdent, PackageTarget)>::new();
+        let mut left_out = HashMap::new();
+
+        for (_, target, package) in resolved_packages {
+            if self.no_deps {
+                // Only the package itself
+            } else if self.exclude_deps_of
+                          .iter()
+                          .any(|e| e.satisfies(&package.ident))
+            {
+                for ident in self.dependencies_of(&package) {
+                    left_out.entry((ident.clone(), target))
+                            .or_insert_with(|| package.ident.clone());
+                }
+            } else if let Some(max_depth) = self.max_depth {
+                for ident in self.dependencies_to_depth(&package, target, max_depth)? {
+                    expanded_idents.insert((ident, target));
+                }
+            } else {
+                // Builder's metadata only has idents for the dependencies, with no target of
+                // their own. A package is built against dependencies for the same target, so
+                // that's the one to fetch; if Builder doesn't have it, `download_failed` says so.
+                for ident in self.dependencies_of(&package) {
+                    expanded_idents.insert((ident.clone(), target));
+                }
+            }
+            expanded_idents.insert((package.ident.clone(), target));
+        }
+        self.report_left_out_deps(ui, &expanded_idents, left_out)?;
+
+        ui.status(Status::Found,
+                  format!("{} artifacts", expanded_idents.len()))?;
+
+        Ok(expanded_idents)
+    }
+
+    /// Everything `package` depends on: the transitive closure of its dependencies and, with
+    /// `build_deps`, its build dependencies as well as their closure. Builder has both of those,
+    /// but an older one has neither, in which case there's nothing to add.
+    fn dependencies_of<'p>(&self, package: &'p Package) -> Vec<&'p PackageIdent> {
+        let mut deps: Vec<_> = package.tdeps.iter().collect();
+        if self.build_deps {
+            deps.extend(package.build_deps.iter().chain(&package.build_tdeps));
+        }
+        deps
+    }
+
+    /// Says which dependencies of the packages in `exclude_deps_of` were left out, being
+    /// dependencies of nothing else in `expanded_idents`. `left_out` has each one with a package
+    /// it's a dependency of.
+    fn report_left_out_deps<T>(&self,
+                               ui: &mut T,
+                               expanded_idents: &HashSet<(PackageIdent, PackageTarget)>,
+                               left_out: HashMap<(PackageIdent, PackageTarget), PackageIdent>)
+                               -> Result<()>
+        where T: UIWriter
+    {
+        let mut left_out: Vec<_> = left_out.into_iter()
+                                           .filter(|(dep, _)| !expanded_idents.contains(dep))
+                                           .map(|((ident, target), dependent)| {
+                                               format!("{} for {} (a dependency of {})",
+                                                       ident, target, dependent)
+                                           })
+                                           .collect();
+        if left_out.is_empty() {
+            return Ok(());
+        }
+        left_out.sort();
+        for dep in &left_out {
+            self.status(ui, Status::Skipping, dep)?;
+        }
+        ui.warn(format!("Left out {} dependencies; what depends on them won't be installable \
+                         from the download directory alone",
+                        left_out.len()))?;
+        Ok(())
+    }
+
+    /// The dependencies of `package` no more than `max_depth` levels down. Builder's metadata
+    /// only has the whole closure besides what a package depends on directly, so each level
+    /// takes asking for the metadata of every package found on the one above it. With
+    /// `build_deps`, what `package` was built with counts as what it depends on directly.
+    fn dependencies_to_depth(&self,
+                             package: &Package,
+                             target: PackageTarget,
+                             max_depth: usize)
+                             -> Result<Vec<PackageIdent>> {
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        let mut level = vec![package.clone()];
+        for depth in 1..=max_depth {
+            let mut next = Vec::new();
+            for dependent in &level {
+                let mut deps = dependent.deps.iter().collect::<Vec<_>>();
+                if self.build_deps && depth == 1 {
+                    deps.extend(&dependent.build_deps);
+                }
+                for ident in deps {
+                    if seen.insert(ident.clone()) {
+                        next.push(ident.clone());
+                    }
+                }
+            }
+            found.extend(next.iter().cloned());
+            if depth == max_depth || next.is_empty() {
+                break;
+            }
+            level = next.iter()
+                        .map(|ident| {
+                            self.fetch_latest_package_in_channels_for(ident,
+                                                                      target,
+                                                                      &self.channels,
+                                                                      self.token)
+                                .map(|(package, _)| package)
+                        })
+                        .collect::<api_client::Result<_>>()?;
+        }
+        debug!("Found {} dependencies of {} for {} within {} levels",
+               found.len(),
+               package.ident,
+               target,
+               max_depth);
+        Ok(found)
+    }
+
+    // Everything in a lockfile is already fully qualified, so there's nothing to resolve. We do
+    // make sure that Builder still has all of it up front, so that a missing package fails the
+    // download rather than it quietly fetching something else.
+    fn expand_locked_sources<T>(&self,
+                                ui: &mut T,
+                                lockfile: &Path)
+                                -> Result<HashSet<(PackageIdent, PackageTarget)>>
+        where T: UIWriter
+    {
+        ui.status(Status::Using, format!("lockfile {}", lockfile.display()))?;
+        let expanded_idents = Lockfile::from_file(lockfile)?.expanded_idents(!self.no_deps);
+
+        // Anything already in the download directory doesn't need Builder at all
+        let uncached_idents: Vec<_> =
+            expanded_idents.iter()
+                           .filter(|(ident, target)| !self.is_cached(ident, *target))
+                           .cloned()
+                           .collect();
+        let mut missing_idents = artifact_names(&self.missing_artifacts(ui, &uncached_idents)?);
+
+        if !missing_idents.is_empty() {
+            missing_idents.sort();
+            for missing in &missing_idents {
+                ui.warn(format!("Locked package {} doesn't exist on Builder ({})",
+                                missing,
+                                self.builder_url()))?;
+            }
+            return Err(CommonError::PackageNotFound(format!("{} locked in {}",
+                                                            missing_idents.join(", "),
+                                                            lockfile.display())).into());
+        }
+
+        ui.status(Status::Found,
+                  format!("{} artifacts", expanded_idents.len()))?;
+
+        Ok(expanded_idents)
+    }
+
+    // Asks Builder whether it has each of `idents`, returning those it doesn't. Anything else
+    // going wrong while asking fails the lot.
+    fn missing_artifacts<T>(&self,
+                            ui: &mut T,
+                            idents: &[(PackageIdent, PackageTarget)])
+                            -> Result<Vec<(PackageIdent, PackageTarget)>>
+        where T: UIWriter
+    {
+        let mut missing = Vec::new();
+        for_each_parallel(self.resolve_jobs,
+                          idents,
+                          |(ident, target)| {
+                              self.retry_policy
+                                  .run(|| {
+                                      self.api_client.check_package((ident, *target), self.token)
+                                  })
+                                  .map_err(last_attempt_error)
+                          },
+                          |(ident, target), checked| {
+                              self.check_interrupted(|| artifact_names(idents))?;
+                              match checked {
+                                  Ok(()) => Ok(()),
+                                  Err(APIError(StatusCode::NOT_FOUND, _)) => {
+                                      missing.push((ident.clone(), *target));
+                                      Ok(())
+                                  }
+                                  Err(e) => {
+                                      debug!("Error checking ident {} for target {}: {:?}",
+                                             ident, target, e);
+                                      ui.warn(format!("Error checking ident {} for target {}",
+                                                      ident, target))?;
+                                      Err(e.into())
+                                  }
+                              }
+                          })?;
+        Ok(missing)
+    }
+
+    // Makes sure Builder has an artifact for everything about to be downloaded, before any of
+    // them is, and reports every one it doesn't have at once. With `continue_on_error` those are
+    // failures and the rest are still downloaded; otherwise nothing is.
+    fn check_existence<T>(&self,
+                          ui: &mut T,
+                          report: &mut DownloadReport,
+                          expanded_idents: HashSet<(PackageIdent, PackageTarget)>)
+                          -> Result<HashSet<(PackageIdent, PackageTarget)>>
+        where T: UIWriter
+    {
+        let (cached, uncached): (Vec<_>, Vec<_>) =
+            expanded_idents.into_iter()
+                           .partition(|(ident, target)| self.is_cached(ident, *target));
+        ui.status(Status::Determining,
+                  format!("whether {} has all {} artifacts",
+                          self.builder_url(),
+                          uncached.len()))?;
+        let mut missing = self.missing_artifacts(ui, &uncached)?;
+        if missing.is_empty() {
+            return Ok(cached.into_iter().chain(uncached).collect());
+        }
+
+        missing.sort_by_key(|(ident, target)| (ident.to_string(), target.to_string()));
+        let names = artifact_names(&missing);
+        for name in &names {
+            ui.warn(format!("{} doesn't have {}", self.builder_url(), name))?;
+        }
+        if !self.continue_on_error {
+            return Err(CommonError::PackageNotFound(names.join(", ")).into());
+        }
+        for (ident, target) in &missing {
+            let error = format!("Not on {}", self.builder_url());
+            self.error_event(Phase::Download, ident, *target, error.clone());
+            report.failed.push(FailedArtifact { ident: ident.clone(),
+                                                target: *target,
+                                                error });
+        }
+        Ok(cached.into_iter()
+                 .chain(uncached.into_iter().filter(|a| !missing.contains(a)))
+                 .collect())
+    }
+
+    // Leaves out whatever matches one of the excludes. They may well be dependencies of what's
+    // left, so the download directory can end up without everything it needs to install.
+    fn exclude<T>(&self,
+                  ui: &mut T,
+                  expanded_idents: HashSet<(PackageIdent, PackageTarget)>)
+                  -> Result<HashSet<(PackageIdent, PackageTarget)>>
+        where T: UIWriter
+    {
+        let (excluded, remaining): (HashSet<_>, HashSet<_>) =
+            expanded_idents.into_iter().partition(|(ident, _)| {
+                                           self.excludes.iter().any(|e| e.satisfies(ident))
+                                       });
+        if excluded.is_empty() {
+            return Ok(remaining);
+        }
+
+        let mut excluded: Vec<String> =
+            excluded.iter()
+                    .map(|(ident, target)| format!("{} for {}", ident, target))
+                    .collect();
+        excluded.sort();
+        for ident in &excluded {
+            self.status(ui, Status::Skipping, format!("{} (excluded)", ident))?;
+        }
+        ui.warn(format!("Excluded {} artifacts; anything that depends on them won't be \
+                         installable from the download directory alone",
+                        excluded.len()))?;
+        Ok(remaining)
+    }
+
+    // Leaves out whatever was released before `since`. A release is the time the package was
+    // built, so one that doesn't parse as a timestamp can't be told to be older, and is kept.
+    fn skip_older<T>(&self,
+                     ui: &mut T,
+                     expanded_idents: HashSet<(PackageIdent, PackageTarget)>)
+                     -> Result<HashSet<(PackageIdent, PackageTarget)>>
+        where T: UIWriter
+    {
+        let since = match self.since {
+            Some(since) => since,
+            None => return Ok(expanded_idents),
+        };
+        let (older, remaining): (HashSet<_>, HashSet<_>) =
+            expanded_idents.into_iter()
+                           .partition(|(ident, _)| released_at(ident).map_or(false, |r| r < since));
+        if !older.is_empty() {
+            ui.status(Status::Skipping,
+                      format!("{} artifacts released before {}",
+                              older.len(),
+                              since.to_rfc3339()))?;
+        }
+        Ok(remaining)
+    }
+
+    // Asks the filter Builder, if there is one, which artifacts it already has and leaves those
+    // out. We don't send our token there, so anything it can't show us is downloaded anyway.
+    fn filter_existing<T>(&self,
+                          ui: &mut T,
+                          expanded_idents: HashSet<(PackageIdent, PackageTarget)>)
+                          -> Result<HashSet<(PackageIdent, PackageTarget)>>
+        where T: UIWriter
+    {
+        let (filter_url, filter_client) = match self.filter_builder {
+            Some((filter_url, ref filter_client)) => (filter_url, filter_client),
+            None => return Ok(expanded_idents),
+        };
+
+        ui.status(Status::Determining,
+                  format!("which of {} artifacts {} already has",
+                          expanded_idents.len(),
+                          filter_url))?;
+        let candidates: Vec<_> = expanded_idents.into_iter().collect();
+        let mut remaining = HashSet::new();
+
+        for_each_parallel(self.resolve_jobs,
+                          &candidates,
+                          |(ident, target)| {
+                              self.retry_policy
+                                  .run(|| filter_client.check_package((ident, *target), None))
+                                  .map_err(last_attempt_error)
+                          },
+                          |(ident, target), checked| {
+                              self.check_interrupted(|| artifact_names(&candidates))?;
+                              match checked {
+                                  Ok(()) => {
+                                      debug!("{} already has {} for {}", filter_url, ident, target)
+                                  }
+                                  Err(APIError(StatusCode::NOT_FOUND, _)) => {
+                                      remaining.insert((ident.clone(), *target));
+                                  }
+                                  Err(e) => {
+                                      debug!("Error checking {} for {} on {}: {:?}",
+                                             ident, target, filter_url, e);
+                                      ui.warn(format!("Couldn't tell whether {} already has {} \
+                                                       for {}, so it will be downloaded",
+                                                      filter_url, ident, target))?;
+                                      remaining.insert((ident.clone(), *target));
+                                  }
+                              }
+                              Ok(())
+                          })?;
+
+        ui.status(Status::Skipping,
+                  format!("{} artifacts already on {}",
+                          candidates.len() - remaining.len(),
+                          filter_url))?;
+        Ok(remaining)
+    }
+
+    // Removes the artifacts in the download directory that aren't among `wanted_idents`, and
+    // optionally any public keys that only those artifacts needed.
+    fn prune<T>(&self,
+                ui: &mut T,
+                wanted_idents: &HashSet<(PackageIdent, PackageTarget)>)
+                -> Result<()>
+        where T: UIWriter
+    {
+        let policy = match self.prune {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let dry_run = match self.execution_strategy {
+            ExecutionStrategy::DryRun => true,
+            ExecutionStrategy::Run => policy.dry_run,
+        };
+
+        let wanted =
+            wanted_idents.iter()
+                         .map(|(ident, target)| self.downloaded_artifact_path(ident, *target))
+                         .collect();
+        let dirs = self.paths_for_artifacts()?;
+        let mut stale = Vec::new();
+        for dir in &dirs {
+            stale.extend(stale_artifacts(dir, &wanted)?);
+        }
+        if policy.keys {
+            let mut signers = HashSet::new();
+            for dir in &dirs {
+                for path in files_with_extension(dir, "hart")? {
+                    if !stale.contains(&path) {
+                        signers.insert(self.artifact_signer(&path)?);
+                    }
+                }
+            }
+            stale.extend(stale_keys(&self.path_for_keys(), &signers)?);
+        }
+
+        for (i, path) in stale.iter().enumerate() {
+            self.check_interrupted(|| {
+                    stale[i..].iter().map(|p| p.display().to_string()).collect()
+                })?;
+            if dry_run {
+                self.status(ui, Status::DryRunDeleting, path.display())?;
+            } else {
+                self.status(ui, Status::Deleting, path.display())?;
+                remove_with_sidecars(path)?;
+            }
+        }
+        if !dry_run {
+            ui.status(Status::Deleted,
+                      format!("{} files no longer in the resolved set", stale.len()))?;
+        }
+        Ok(())
+    }
+
+    /// Removes what this run put in the download directory, after it's failed. Artifacts and keys
+    /// are only ever there once they're complete, so there's nothing half-written to count; an
+    /// artifact that didn't finish is left in its `.part` file, for the next attempt to resume.
+    fn remove_created<T>(&self, ui: &mut T) -> Result<()>
+        where T: UIWriter
+    {
+        let created: Vec<_> = self.created().drain(..).collect();
+        if created.is_empty() {
+            return Ok(());
+        }
+        ui.warn(format!("Removing the {} files this run put in {}, since it failed",
+                        created.len(),
+                        self.download_path.display()))?;
+        for path in &created {
+            match remove_with_sidecars(path) {
+                // As it is when it didn't verify and was removed already
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                removed => removed?,
+            }
+        }
+        Ok(())
+    }
+
+    // Estimates how much space the artifacts that aren't in the download directory yet will take,
+    // and fails before downloading any of them if there isn't that much free. Builder is asked
+    // for the sizes; any it can't tell us are left out of the estimate. Returns the estimate when
+    // there was nothing left out of it.
+    fn check_available_space<T>(&self,
+                                ui: &mut T,
+                                expanded_idents: &HashSet<(PackageIdent, PackageTarget)>)
+                                -> Result<Option<u64>>
+        where T: UIWriter
+    {
+        let uncached: Vec<_> =
+            expanded_idents.iter()
+                           .filter(|(ident, target)| !self.is_cached(ident, *target))
+                           .cloned()
+                           .collect();
+        if uncached.is_empty() {
+            return Ok(Some(0));
+        }
+
+        ui.status(Status::Determining,
+                  format!("the space needed for {} artifacts", uncached.len()))?;
+        let mut expected = 0;
+        let mut unknown = 0;
+        for_each_parallel(self.resolve_jobs,
+                          &uncached,
+                          |(ident, target)| {
+                              self.retry_policy
+                                  .run(|| {
+                                      self.api_client.package_size((ident, *target), self.token)
+                                  })
+                                  .map_err(last_attempt_error)
+                          },
+                          |(ident, target), size| {
+                              self.check_interrupted(|| artifact_names(expanded_idents))?;
+                              match size {
+                                  Ok(Some(size)) => expected += size,
+                                  Ok(None) => unknown += 1,
+                                  Err(e) => {
+                                      debug!("Error getting the size of {} for {}: {:?}",
+                                             ident, target, e);
+                                      unknown += 1;
+                                  }
+                              }
+                              Ok(())
+                          })?;
+        if unknown > 0 {
+            ui.warn(format!("Couldn't get the size of {} artifacts, so the space they'll take \
+                             isn't accounted for",
+                            unknown))?;
+        }
+
+        let needed = expected + expected * SPACE_MARGIN_PERCENT / 100;
+        let available = filesystem::available_space(&self.download_path)?;
+        debug!("Expecting to need {} bytes in {}, which has {} available",
+               needed,
+               self.download_path.display(),
+               available);
+        if needed > available {
+            return Err(Error::InsufficientSpace(self.download_path.to_path_buf(),
+                                                needed,
+                                                available));
+        }
+        Ok(if unknown == 0 { Some(expected) } else { None })
+    }
+
+    /// Gets every artifact into the download directory. `expected_bytes` is how much is expected
+    /// to be fetched from Builder, if that's known, for the overall progress bar.
+    fn download_artifacts<T>(&self,
+                             ui: &mut T,
+                             expanded_idents: &HashSet<(PackageIdent, PackageTarget)>,
+                             expected_bytes: Option<u64>,
+                             report: &mut DownloadReport)
+                             -> Result<Vec<PackageArchive>>
+        where T: UIWriter
+    {
+        let mut downloaded_artifacts = Vec::<PackageArchive>::new();
+        let expanded_idents: Vec<_> = expanded_idents.iter().cloned().collect();
+
+        ui.status(Status::Downloading,
+                  format!("Downloading {} artifacts (and their signing keys)",
+                          expanded_idents.len()))?;
+
+        let mut overall = if self.overall_progress && !expanded_idents.is_empty() {
+            Some(OverallProgress::new(expanded_idents.len(), expected_bytes))
+        } else {
+            None
+        };
+
+        if self.concurrent_downloads > 1 {
+            debug!("Downloading with {} workers", self.concurrent_downloads);
+            // Per-artifact progress bars would trample each other, so the workers fetch without
+            // them and each artifact is reported once it lands. Signing keys are still fetched
+            // and checked one artifact at a time, here on this thread.
+            let mut left: HashSet<_> = expanded_idents.iter().cloned().collect();
+            for_each_parallel(self.concurrent_downloads,
+                              &expanded_idents,
+                              |(ident, target)| {
+                                  logging::with_artifact(ident, *target, "download", || {
+                                      if self.is_cached(ident, *target) {
+                                          Ok(ArtifactSource::Cache)
+                                      } else if self.link_from_local_cache(ident, *target) {
+                                          Ok(ArtifactSource::LocalCache)
+                                      } else {
+                                          self.event(EventKind::Downloading,
+                                                     Phase::Download,
+                                                     ident,
+                                                     *target);
+                                          self.fetch_artifact_with_retries(ident, *target, || None)
+                                      }
+                                  })
+                              },
+                              |(ident, target), fetched| {
+                                  if let Ok(ArtifactSource::Builder) = fetched {
+                                      self.status(ui,
+                                                  Status::Custom(Glyph::CheckMark,
+                                                                 String::from("Downloaded")),
+                                                  ident)?;
+                                  }
+                                  let archive = self.finish_downloaded_archive(ui, report, ident,
+                                                                               *target, fetched);
+                                  let archive = self.check_downloaded_archive(ui, report, ident,
+                                                                              *target, archive)?;
+                                  downloaded_artifacts.extend(archive);
+                                  left.remove(&(ident.clone(), *target));
+                                  if let Some(ref mut overall) = overall {
+                                      overall.update(expanded_idents.len() - left.len(),
+                                                     report.bytes_transferred());
+                                  }
+                                  self.check_deadline(|| artifact_names(&left))?;
+                                  self.check_interrupted(|| artifact_names(&left))
+                              })?;
+        } else {
+            for (i, (ident, target)) in expanded_idents.iter().enumerate() {
+                self.check_deadline(|| artifact_names(&expanded_idents[i..]))?;
+                self.check_interrupted(|| artifact_names(&expanded_idents[i..]))?;
+                let archive = self.get_downloaded_archive(ui, report, ident, *target);
+                let archive = self.check_downloaded_archive(ui, report, ident, *target, archive)?;
+                downloaded_artifacts.extend(archive);
+                if let Some(ref mut overall) = overall {
+                    overall.update(i + 1, report.bytes_transferred());
+                }
+            }
+        }
+
+        if let Some(ref mut overall) = overall {
+            overall.finish();
+        }
+        Ok(downloaded_artifacts)
+    }
+
+    /// Reports an artifact that couldn't be downloaded. With `continue_on_error` it's added to
+    /// the report's failures and the download goes on without it.
+    fn check_downloaded_archive<T>(&self,
+                                   ui: &mut T,
+                                   report: &mut DownloadReport,
+                                   ident: &PackageIdent,
+                                   target: PackageTarget,
+                                   archive: Result<Option<PackageArchive>>)
+                                   -> Result<Option<PackageArchive>>
+        where T: UIWriter
+    {
+        match archive {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                // Is this the right status? Or should this be a debug message?
+                debug!("Error fetching archive {} for {}: {:?}", ident, target, e);
+                ui.status(Status::Missing,
+                          format!("Error fetching archive {} for {}", ident, target))?;
+                self.error_event(Phase::Download, ident, target, e.to_string());
+                if self.continue_on_error {
+                    report.failed.push(FailedArtifact { ident: ident.clone(),
+                                                        target,
+                                                        error: e.to_string() });
+                    Ok(None)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Reports the outcome of looking up the latest package for `ident`, translating a missing
+    /// package into a friendlier error.
+    fn determine_latest_from_ident<T>(&self,
+                                      ui: &mut T,
+                                      ident: &PackageIdent,
+                                      target: PackageTarget,
+                                      channels: &[ChannelIdent],
+                                      fetched: api_client::Result<(Package, &ChannelIdent)>)
+                                      -> Result<Package>
+        where T: UIWriter
+    {
+        // Unlike in the install command, we always hit the online
+        // depot; our purpose is to sync with latest, and falling back
+        // to a local package would defeat that. The latest package in
+        // the first of the proper channels that has it has already been fetched from Builder API.
+        self.status(ui,
+                    Status::Determining,
+                    format!("latest version of {}", ident))?;
+        self.event(EventKind::Resolving, Phase::Resolve, ident, target);
+        match fetched {
+            Ok((latest_package, channel)) => {
+                self.event(EventKind::Resolved,
+                           Phase::Resolve,
+                           &latest_package.ident,
+                           target);
+                self.status(ui,
+                            Status::Using,
+                            format!("{} from the '{}' channel", latest_package.ident, channel))?;
+                Ok(latest_package)
+            }
+            Err(APIError(StatusCode::NOT_FOUND, _)) => {
+                // In install we attempt to recommend a channel to look in. That's a bit of a
+                // heavyweight process, and probably a bad idea in the context of
+                // what's a normally a batch process. Falling back to other channels is left to
+                // the caller, who can list them in order; if none of them has it, error.
+                self.error_event(Phase::Resolve,
+                                 ident,
+                                 target,
+                                 format!("not found in channel {}", channel_list(channels)));
+                ui.warn(format!("No packages matching ident {} for {} exist in channel {}. \
+                                 Check the package ident, target, channel and Builder url ({}) \
+                                 for correctness",
+                                ident,
+                                target,
+                                channel_list(channels),
+                                self.builder_url()))?;
+                Err(CommonError::PackageNotFound(format!("{} for {} in channel {}",
+                                                         ident,
+                                                         target,
+                                                         channel_list(channels))).into())
+            }
+            Err(e) => {
+                debug!("Error fetching ident {} for target {}: {:?}",
+                       ident, target, e);
+                self.error_event(Phase::Resolve, ident, target, e.to_string());
+                ui.warn(format!("Error fetching ident {} for target {}", ident, target))?;
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Writes the one artifact there is to `out`, straight from Builder. Nothing is printed, so
+    /// as not to get mixed up with it, and there's nothing to report. A download that fails
+    /// partway through isn't retried, as what's been written can't be taken back.
+    fn stream_artifact(&self, out: &mut dyn Write) -> Result<DownloadReport> {
+        let source = &self.sources[0];
+        let target = self.target_for(source);
+        debug!("Writing {} for {} to stdout", source.ident, target);
+        let written = match self.api_client
+                                .stream_package((&source.ident, target), self.token, out)
+        {
+            Err(APIError(StatusCode::NOT_FOUND, _)) => {
+                let artifact = format!("{} for {}", source.ident, target);
+                return Err(CommonError::PackageNotFound(artifact).into());
+            }
+            written => written?,
+        };
+        out.flush()?;
+        debug!("Wrote {} bytes of {} for {}", written, source.ident, target);
+        Ok(DownloadReport::default())
+    }
+
+    /// This ensures the identified package is in the local download directory,
+    /// verifies it, and returns a handle to the package's metadata.
+    fn get_downloaded_archive<T>(&self,
+                                 ui: &mut T,
+                                 report: &mut DownloadReport,
+                                 ident: &PackageIdent,
+                                 target: PackageTarget)
+                                 -> Result<Option<PackageArchive>>
+        where T: UIWriter
+    {
+        logging::with_artifact(ident, target, "download", || {
+            let fetched = if self.is_cached(ident, target) {
+                Ok(ArtifactSource::Cache)
+            } else if self.link_from_local_cache(ident, target) {
+                Ok(ArtifactSource::LocalCache)
+            } else {
+                self.status(ui, Status::Downloading, ident)?;
+                self.event(EventKind::Downloading, Phase::Download, ident, target);
+                self.fetch_artifact_with_retries(ident, target, || self.progress(ui))
+            };
+            self.finish_downloaded_archive(ui, report, ident, target, fetched)
+        })
+    }
+
+    /// Takes the outcome of getting an artifact into the download directory, and fetches its
+    /// signing key and verifies it if that succeeded. The artifact is added to `report` once it
+    /// has been dealt with. There's no artifact if Builder doesn't support its platform.
+    fn finish_downloaded_archive<T>(&self,
+                                    ui: &mut T,
+                                    report: &mut DownloadReport,
+                                    ident: &PackageIdent,
+                                    target: PackageTarget,
+                                    fetched: FetchResult)
+                                    -> Result<Option<PackageArchive>>
+        where T: UIWriter
+    {
+        let source = match fetched {
+            Ok(ArtifactSource::Cache) => {
+                if self.cached_artifact_verifies(ui, ident, target)? {
+                    debug!("Found {} in download directory, skipping remote download",
+                           ident);
+                    self.status(ui,
+                                Status::Custom(Glyph::Elipses, String::from("Using cached")),
+                                ident)?;
+                    self.event(EventKind::CacheHit, Phase::Download, ident, target);
+                    ArtifactSource::Cache
+                } else {
+                    self.status(ui, Status::Downloading, ident)?;
+                    self.event(EventKind::Downloading, Phase::Download, ident, target);
+                    self.fetch_artifact_with_retries(ident, target, || self.progress(ui))
+                        .map_err(|err| self.download_failed(ident, target, err))?
+                }
+            }
+            Ok(ArtifactSource::LocalCache) => {
+                self.status(ui,
+                            Status::Custom(Glyph::Elipses, String::from("Using local cache")),
+                            ident)?;
+                self.event(EventKind::CacheHit, Phase::Download, ident, target);
+                ArtifactSource::LocalCache
+            }
+            Ok(source) => source,
+            Err(err) => return Err(self.download_failed(ident, target, err)),
+        };
+        if source == ArtifactSource::Unsupported {
+            self.status(ui,
+                        Status::Skipping,
+                        format!("{} for {}: not supported by the depot", ident, target))?;
+            report.skipped.push(SkippedArtifact { ident: ident.clone(),
+                                                  target });
+            return Ok(None);
+        }
+
+        // At this point the artifact is in the download directory...
+        let mut artifact = PackageArchive::new(self.downloaded_artifact_path(ident, target));
+        if source.is_new() {
+            self.check_artifact_target(ui, ident, target, &mut artifact)?;
+        }
+        // ...and if it was cached and we're verifying, it's been verified already. Otherwise it's
+        // verified now, unless that's been left to verify_artifacts.
+        let signer = if self.verified_at_the_end(source) {
+            // Its key is fetched along with everyone else's by fetch_signer_keys
+            let signer = self.artifact_signer(&artifact.path);
+            let signer = signer.map_err(|e| self.signature_error(ident, target, e))?;
+            self.check_signer_trusted(ident, target, &artifact.path, &signer)?;
+            signer
+        } else {
+            let verify = self.verify && source.is_new();
+            self.fetch_keys_and_verify_artifact(ui, ident, target, &mut artifact, verify)?
+        };
+
+        if self.with_metadata {
+            let channel = self.resolved_channels()
+                              .get(&(ident.clone(), target))
+                              .cloned();
+            let metadata = ArtifactMetadata { ident,
+                                              target,
+                                              channel: channel.as_ref(),
+                                              signer: &signer,
+                                              checksum: artifact.checksum()? };
+            metadata::write(&artifact.path, &metadata)?;
+        }
+
+        let size = std::fs::metadata(&artifact.path)?.len();
+        let bytes_transferred = if source == ArtifactSource::Builder {
+            size
+        } else {
+            report.bytes_saved += size;
+            0
+        };
+        report.artifacts.push(ArtifactReport { ident: ident.clone(),
+                                               target,
+                                               artifact:
+                                                   ident.archive_name_with_target(target)?,
+                                               source,
+                                               signer,
+                                               bytes_transferred,
+                                               verified: self.verify });
+        Ok(Some(artifact))
+    }
+
+    /// Builder should only ever hand out an artifact for the target it was asked for, but if it
+    /// doesn't, the artifact mustn't pass for one that is. So it's removed, unless
+    /// `warn_on_wrong_target` says to keep it.
+    fn check_artifact_target<T>(&self,
+                                ui: &mut T,
+                                ident: &PackageIdent,
+                                target: PackageTarget,
+                                artifact: &mut PackageArchive)
+                                -> Result<()>
+        where T: UIWriter
+    {
+        let actual = artifact.target()?;
+        if actual == target {
+            return Ok(());
+        }
+        if self.warn_on_wrong_target {
+            ui.warn(format!("The artifact Builder sent for {} for {} is for {}",
+                            ident, target, actual))?;
+            return Ok(());
+        }
+        fs::remove_file(&artifact.path)?;
+        Err(Error::WrongArtifactTarget(ident.clone(), target, actual))
+    }
+
+    fn download_failed(&self,
+                       ident: &PackageIdent,
+                       target: PackageTarget,
+                       err: RetryError<api_client::Error>)
+                       -> Error {
+        match err {
+            RetryError::Operation { error: APIError(StatusCode::NOT_FOUND, _),
+                                    .. } => {
+                CommonError::PackageNotFound(format!("Builder has no {} artifact for {}. If it's \
+                                                      a dependency, note that dependencies are \
+                                                      always downloaded for the target of the \
+                                                      package that depends on them",
+                                                     ident, target)).into()
+            }
+            RetryError::Operation { ref error, .. } if !is_retryable(error) => {
+                CommonError::DownloadFailed(format!("Could not download {} for {}: {}",
+                                                    ident, target, error)).into()
+            }
+            err => CommonError::DownloadFailed(format!("We tried {} times but could not \
+                                                        download {} for {}. Last error was: {}",
+                                                       self.retry_policy.retries,
+                                                       ident,
+                                                       target,
+                                                       err)).into(),
+        }
+    }
+
+    /// A cached artifact may have been left truncated or corrupt by an earlier run, and just
+    /// being there doesn't mean it's any good. So when we're verifying anyway, a cached artifact
+    /// is verified up front; if it's bad, it's deleted so that it will be downloaded again.
+    /// Returns whether the cached artifact can be used.
+    fn cached_artifact_verifies<T>(&self,
+                                   ui: &mut T,
+                                   ident: &PackageIdent,
+                                   target: PackageTarget)
+                                   -> Result<bool>
+        where T: UIWriter
+    {
+        // With verify_all, a bad cached artifact fails the run along with everything else
+        if !self.verify || self.verify_all {
+            return Ok(true);
+        }
+
+        let path = self.downloaded_artifact_path(ident, target);
+        if self.verified_already(&path) {
+            debug!("{} for {} has verified before, and hasn't changed since",
+                   ident, target);
+            self.event(EventKind::Verified, Phase::Verify, ident, target);
+            return Ok(true);
+        }
+        let mut artifact = PackageArchive::new(&path);
+        let verified = match self.artifact_signer(&path) {
+            Ok(signer) => {
+                // Not being able to get the key is a problem with the key, not the artifact
+                self.ensure_public_key(ui, ident, target, &signer)?;
+                self.status(ui, Status::Verifying, format!("cached {}", ident))?;
+                artifact.ident()
+                        .and_then(|_| artifact.verify(&self.path_for_verification_keys()))
+                        .map(|_| ())
+            }
+            Err(e) => Err(e),
+        };
+
+        match verified {
+            Ok(()) => {
+                verified::record(&path)?;
+                self.event(EventKind::Verified, Phase::Verify, ident, target);
+                Ok(true)
+            }
+            Err(e) => {
+                debug!("Cached {} for {} failed verification: {:?}",
+                       ident, target, e);
+                ui.warn(format!("The cached {} for {} is corrupt ({}); it will be downloaded \
+                                 again",
+                                ident, target, e))?;
+                fs::remove_file(&path)?;
+                // What replaces it may well be signed by someone else
+                self.signers().remove(&path);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Fetches the artifact from Builder, retrying on failure. An attempt that fails partway
+    /// through leaves what it got behind, and the next attempt resumes from there. `progress` is
+    /// called for each attempt to get a fresh progress bar. This writes nothing to the UI, so
+    /// download workers can call it.
+    fn fetch_artifact_with_retries<F>(&self,
+                                      ident: &PackageIdent,
+                                      target: PackageTarget,
+                                      progress: F)
+                                      -> FetchResult
+        where F: Fn() -> Option<Box<dyn DisplayProgress>>
+    {
+        self.retry_policy
+            .run(|| self.fetch_artifact(ident, target, progress()))
+    }
+
+    /// Retrieve the identified package from the depot, ensuring that
+    /// the artifact is downloaded. A depot that doesn't support the target's platform has
+    /// nothing to download, which is only an error with `fail_on_unsupported`.
+    fn fetch_artifact(&self,
+                      ident: &PackageIdent,
+                      target: PackageTarget,
+                      progress: Option<Box<dyn DisplayProgress>>)
+                      -> api_client::Result<ArtifactSource> {
+        // One that's already there, as it is with `force`, is only overwritten, which doesn't make
+        // it this run's to remove
+        let existed = self.downloaded_artifact_path(ident, target).is_file();
+        let record = |artifact: PackageArchive| {
+            if !existed {
+                self.created().push(artifact.path);
+            }
+        };
+        let fetched = match self.api_client.fetch_package((ident, target),
+                                                          self.token,
+                                                          &self.path_for_artifact_of(ident, target),
+                                                          progress)
+        {
+            Err(APIError(StatusCode::NOT_MODIFIED, _)) => {
+                debug!("{} for {} hasn't changed since it was downloaded",
+                       ident, target);
+                return Ok(ArtifactSource::Cache);
+            }
+            fetched => fetched,
+        };
+        if self.fail_on_unsupported {
+            return fetched.map(|artifact| {
+                              record(artifact);
+                              ArtifactSource::Builder
+                          });
+        }
+        match fetch::supported(fetched)? {
+            Some(artifact) => {
+                record(artifact);
+                Ok(ArtifactSource::Builder)
+            }
+            None => {
+                debug!("Skipping {} for {}", ident, target);
+                Ok(ArtifactSource::Unsupported)
+            }
+        }
+    }
+
+    fn fetch_origin_key<T>(&self,
+                           ui: &mut T,
+                           name_with_rev: &str,
+                           token: Option<&str>)
+                           -> Result<()>
+        where T: UIWriter
+    {
+        let (name, rev) = parse_name_with_rev(&name_with_rev)?;
+        let key = self.retry_policy
+                      .run(|| {
+                          self.api_client.fetch_origin_key(&name,
+                                                           &rev,
+                                                           token,
+                                                           &self.path_for_keys(),
+                                                           self.progress(ui))
+                      })
+                      .map_err(last_attempt_error)?;
+        self.created().push(key);
+        self.keys_downloaded.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Makes sure the public key of each signer of an artifact this run downloaded is in place,
+    /// once per signer no matter how many artifacts it signed.
+    fn fetch_signer_keys<T>(&self, ui: &mut T, report: &DownloadReport) -> Result<()>
+        where T: UIWriter
+    {
+        let mut signers = HashSet::new();
+        for artifact in &report.artifacts {
+            if self.verified_at_the_end(artifact.source) && signers.insert(&artifact.signer) {
+                self.ensure_public_key(ui, &artifact.ident, artifact.target, &artifact.signer)
+                    .map_err(|e| self.signature_error(&artifact.ident, artifact.target, e))?;
+            }
+        }
+        debug!("Fetched keys for {} signers", signers.len());
+        Ok(())
+    }
+
+    /// Whether the artifact at `path` can be taken as verified, having verified before and not
+    /// changed since. With `reverify`, nothing can.
+    fn verified_already(&self, path: &Path) -> bool { !self.reverify && verified::is_current(path) }
+
+    /// Whether an artifact from `source` is verified once everything is downloaded, rather than
+    /// as it's put in the download directory.
+    fn verified_at_the_end(&self, source: ArtifactSource) -> bool {
+        self.verify_jobs.is_some() && (self.verify_all || source.is_new())
+    }
+
+    /// Verifies every artifact this run downloaded from Builder, `jobs` at a time, and with
+    /// `verify_all` the cached ones as well. Their keys have all been fetched by now, and any
+    /// other cached artifacts were verified before they were used. The first artifact that
+    /// doesn't verify is the error, except with `verify_all`, where they all are.
+    fn verify_artifacts<T>(&self, ui: &mut T, jobs: usize, report: &DownloadReport) -> Result<()>
+        where T: UIWriter
+    {
+        let artifacts: Vec<_> = report.artifacts
+                                      .iter()
+                                      .filter(|a| self.verified_at_the_end(a.source))
+                                      .collect();
+        if self.verify_all {
+            ui.status(Status::Verifying,
+                      format!("all {} artifacts", artifacts.len()))?;
+        } else {
+            ui.status(Status::Verifying,
+                      format!("{} downloaded artifacts", artifacts.len()))?;
+        }
+
+        let keys_path = self.path_for_verification_keys();
+        let mut failed = Vec::new();
+        let mut unverified = Vec::new();
+        let mut left: HashSet<_> = artifacts.iter()
+                                            .map(|a| (a.ident.clone(), a.target))
+                                            .collect();
+        for_each_parallel(jobs,
+                          &artifacts,
+                          |artifact| {
+                              let (ident, target) = (&artifact.ident, artifact.target);
+                              logging::with_artifact(ident, target, "verify", || {
+                                  let path = self.downloaded_artifact_path(ident, target);
+                                  if self.verified_already(&path) {
+                                      return Ok(());
+                                  }
+                                  PackageArchive::new(&path).verify(&keys_path)?;
+                                  verified::record(&path)
+                              })
+                          },
+                          |artifact, verified| {
+                              let (ident, target) = (&artifact.ident, artifact.target);
+                              left.remove(&(ident.clone(), target));
+                              let outcome = match verified {
+                                  Ok(_) => {
+                                      debug!("Verified {} for {} signed by {}",
+                                             ident, target, artifact.signer);
+                                      self.status(ui,
+                                                  Status::Custom(Glyph::CheckMark,
+                                                                 String::from("Verified")),
+                                                  ident)?;
+                                      self.event(EventKind::Verified, Phase::Verify, ident, target);
+                                      Ok(())
+                                  }
+                                  Err(e) if self.verify_all => {
+                                      self.error_event(Phase::Verify, ident, target, e.to_string());
+                                      ui.warn(format!("{} for {} doesn't verify: {}",
+                                                      ident, target, e))?;
+                                      failed.push(format!("{} for {}", ident, target));
+                                      unverified.push(self.downloaded_artifact_path(ident, target));
+                                      Ok(())
+                                  }
+                                  Err(e) => {
+                                      self.error_event(Phase::Verify, ident, target, e.to_string());
+                                      Err(self.signature_error(ident, target, e))
+                                  }
+                              };
+                              outcome?;
+                              self.check_interrupted(|| artifact_names(&left))
+                          })?;
+        if failed.is_empty() {
+            return Ok(());
+        }
+        if self.remove_if_unverified {
+            for path in &unverified {
+                self.status(ui, Status::Deleting, path.display())?;
+                remove_with_sidecars(path)?;
+            }
+            // What did verify is as much a part of the failed run as what didn't
+            self.remove_created(ui)?;
+        }
+        Err(Error::VerificationFailed(failed))
+    }
+
+    fn fetch_keys_and_verify_artifact<T>(&self,
+                                         ui: &mut T,
+                                         ident: &PackageIdent,
+                                         target: PackageTarget,
+                                         artifact: &mut PackageArchive,
+                                         verify: bool)
+                                         -> Result<String>
+        where T: UIWriter
+    {
+        // We need to look at the artifact to know the signing keys to fetch
+        // Once we have them, it's the natural time to verify.
+        // Otherwise, it might make sense to take this fetch out of the verification code.
+        if verify {
+            self.status(ui, Status::Verifying, artifact.ident()?)?;
+        }
+        let keys = self.path_for_verification_keys();
+        let keys = if verify { Some(keys.as_path()) } else { None };
+        let path = artifact.path.clone();
+        let signer = self.artifact_signer(&path)
+                         .map_err(|e| self.signature_error(ident, target, e))?;
+        fetch::ensure_key_and_verify(artifact, &signer, keys, |signer| {
+            self.check_signer_trusted(ident, target, &path, signer)?;
+            self.ensure_public_key(ui, ident, target, signer)
+        }).map_err(|e| self.signature_error(ident, target, e))?;
+        if verify {
+            verified::record(&path)?;
+            debug!("Verified {} for {} signed by {}", ident, target, &signer);
+            self.event(EventKind::Verified, Phase::Verify, ident, target);
+        }
+        Ok(signer)
+    }
+
+    /// Rejects an artifact whose signer isn't one of the trusted origins or origin keys, before
+    /// its key is fetched. The artifact is removed from the download directory, so that it can't
+    /// pass for a trusted one later.
+    fn check_signer_trusted(&self,
+                            ident: &PackageIdent,
+                            target: PackageTarget,
+                            path: &Path,
+                            signer: &str)
+                            -> Result<()> {
+        if self.trusted_origins.is_empty() {
+            return Ok(());
+        }
+        let origin = parse_name_with_rev(signer).map(|(origin, _)| origin)?;
+        if self.trusted_origins
+               .iter()
+               .any(|trusted| *trusted == origin || trusted == signer)
+        {
+            return Ok(());
+        }
+        fs::remove_file(path)?;
+        Err(Error::UntrustedSigner(format!("{} for {}", ident, target), signer.to_string()))
+    }
+
+    /// With `require_signatures`, a problem with an artifact's signature is reported as such,
+    /// naming the artifact.
+    fn signature_error<E>(&self, ident: &PackageIdent, target: PackageTarget, err: E) -> Error
+        where E: Into<Error>
+    {
+        let err = err.into();
+        if self.require_signatures {
+            Error::CryptoCLI(format!("Signatures are required, but the one on {} for {} couldn't \
+                                      be verified: {}",
+                                     ident, target, err))
+        } else {
+            err
+        }
+    }
+
+    /// Makes sure the public key for `signer` is in the download directory, if it's wanted.
+    /// With `keys_from`, the key has to be one of the trusted keys there, and is copied in from
+    /// there; nothing is fetched from Builder, which could otherwise serve up a bad artifact
+    /// along with a key to match.
+    fn ensure_public_key<T>(&self,
+                            ui: &mut T,
+                            ident: &PackageIdent,
+                            target: PackageTarget,
+                            signer: &str)
+                            -> Result<()>
+        where T: UIWriter
+    {
+        // Every artifact from an origin tends to have the same signer, so most calls can stop here
+        if self.ensured_signers().contains(signer) {
+            return Ok(());
+        }
+        self.ensure_public_key_uncached(ui, ident, target, signer)?;
+        self.ensured_signers().insert(signer.to_string());
+        Ok(())
+    }
+
+    fn ensure_public_key_uncached<T>(&self,
+                                     ui: &mut T,
+                                     ident: &PackageIdent,
+                                     target: PackageTarget,
+                                     signer: &str)
+                                     -> Result<()>
+        where T: UIWriter
+    {
+        if let Some(keys_from) = self.keys_from {
+            let trusted = SigKeyPair::get_public_key_path(signer, keys_from).map_err(|_| {
+                              CoreError::CryptoError(format!("The public key for signer {} of {} \
+                                                              for {} isn't one of the trusted \
+                                                              keys in {}",
+                                                             signer,
+                                                             ident,
+                                                             target,
+                                                             keys_from.display()))
+                          })?;
+            let file_name = trusted.file_name()
+                                   .expect("public key path has a file name");
+            // Whatever is already in the download directory may not be the trusted key
+            fs::copy(&trusted, self.path_for_keys().join(file_name))?;
+            return Ok(());
+        }
+
+        if SigKeyPair::get_public_key_path(signer, &self.path_for_keys()).is_err() {
+            if self.download_keys {
+                self.status(ui,
+                            Status::Downloading,
+                            format!("public key for signer {:?}", signer))?;
+                self.event(EventKind::KeyFetch, Phase::Download, ident, target);
+                self.fetch_origin_key(ui, signer, self.token)?;
+            } else if self.verify {
+                return Err(CoreError::CryptoError(format!("Can't verify {} for {}: the public \
+                                                           key for signer {} isn't in {} and \
+                                                           downloading keys is turned off",
+                                                          ident,
+                                                          target,
+                                                          signer,
+                                                          self.path_for_keys().display())).into());
+            } else {
+                debug!("Not downloading public key for signer {}", signer);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every artifact there was to download is in the download directory now. What
+    /// was skipped as unsupported or failed to download is accounted for already, so anything
+    /// else that's missing would otherwise only come to light when installing from it.
+    fn reconcile(&self,
+                 expanded_idents: &HashSet<(PackageIdent, PackageTarget)>,
+                 report: &DownloadReport)
+                 -> Result<()> {
+        let accounted_for: HashSet<(&PackageIdent, PackageTarget)> =
+            report.skipped
+                  .iter()
+                  .map(|s| (&s.ident, s.target))
+                  .chain(report.failed.iter().map(|f| (&f.ident, f.target)))
+                  .collect();
+        let mut missing: Vec<String> =
+            expanded_idents.iter()
+                           .filter(|(ident, target)| !accounted_for.contains(&(ident, *target)))
+                           .filter(|(ident, target)| {
+                               !self.downloaded_artifact_path(ident, *target).is_file()
+                           })
+                           .map(|(ident, target)| format!("{} for {}", ident, target))
+                           .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort();
+        Err(Error::IncompleteDownload(missing))
+    }
+
+    /// The Builder being downloaded from, which with fallbacks is whichever answered last.
+    fn builder_url(&self) -> &str { self.api_client.endpoint().unwrap_or(self.url) }
+
+    /// Returns the path to the location this package would exist at in
+    /// the local package cache. It does not mean that the package is
+    /// actually *in* the package download directory, though.
+    fn downloaded_artifact_path(&self, ident: &PackageIdent, target: PackageTarget) -> PathBuf {
+        fetch::artifact_path(&self.path_for_artifact_of(ident, target), ident, target)
+    }
+
+    /// Whether the artifact is already in the download directory, and can be used as it is.
+    /// With `force`, nothing is, and with `revalidate` nothing that Builder can be asked about
+    /// again is.
+    fn is_cached(&self, ident: &PackageIdent, target: PackageTarget) -> bool {
+        let path = self.downloaded_artifact_path(ident, target);
+        !self.force && path.is_file() && !(self.revalidate && etag_path(&path).is_file())
+    }
+
+    /// Makes sure that what's in the download directory under each artifact's name is that
+    /// artifact, going by the checksum Builder has for it. A file that doesn't match is removed,
+    /// to be downloaded again, and one that does match under some other name is put in its place.
+    /// An artifact Builder has no checksum for is left to go by its name as usual. Builder is
+    /// asked for the checksums `resolve_jobs` at a time.
+    fn match_by_checksum<T>(&self,
+                            ui: &mut T,
+                            idents: &HashSet<(PackageIdent, PackageTarget)>)
+                            -> Result<()>
+        where T: UIWriter
+    {
+        // Every artifact in the download directory is hashed once, whatever it's called
+        let mut checksums = HashMap::new();
+        for dir in self.paths_for_artifacts()? {
+            if dir.is_dir() {
+                for path in files_with_extension(&dir, "hart")? {
+                    let checksum = hash::hash_file(&path)?;
+                    checksums.insert(path, checksum);
+                }
+            }
+        }
+        let mut by_checksum = HashMap::new();
+        for (path, checksum) in &checksums {
+            by_checksum.entry(checksum.clone()).or_insert(path);
+        }
+
+        let idents: Vec<_> = idents.iter().cloned().collect();
+        let mut expected_checksums = Vec::new();
+        for_each_parallel(self.resolve_jobs,
+                          &idents,
+                          |(ident, target)| self.expected_checksum(ident, *target),
+                          |(ident, target), expected| {
+                              self.check_interrupted(|| artifact_names(&idents))?;
+                              if let Some(expected) = expected {
+                                  expected_checksums.push((ident.clone(), *target, expected));
+                              }
+                              Ok(())
+                          })?;
+        // Whatever can be linked into an empty place goes first, while every file is still where
+        // it was found. A file removed below for not matching may be the source of another link,
+        // which then fails, leaving that artifact to be downloaded as it would have been anyway.
+        expected_checksums.sort_by_key(|(ident, target, _)| {
+                              checksums.contains_key(&self.downloaded_artifact_path(ident, *target))
+                          });
+
+        for (ident, target, expected) in expected_checksums {
+            let path = self.downloaded_artifact_path(&ident, target);
+            let existed = match checksums.get(&path) {
+                Some(checksum) if *checksum == expected => continue,
+                Some(_) => {
+                    ui.warn(format!("{} doesn't match the checksum of {} for {}, so it will be \
+                                     downloaded again",
+                                    path.display(),
+                                    ident,
+                                    target))?;
+                    fs::remove_file(&path)?;
+                    let etag = etag_path(&path);
+                    if etag.is_file() {
+                        fs::remove_file(etag)?;
+                    }
+                    self.signers().remove(&path);
+                    true
+                }
+                None => false,
+            };
+            if let Some(src) = by_checksum.get(&expected) {
+                if let Err(e) =
+                    fs::hard_link(src, &path).or_else(|_| fs::copy(src, &path).map(|_| ()))
+                {
+                    debug!("Couldn't take {} for {} from {}, downloading it instead: {}",
+                           ident,
+                           target,
+                           src.display(),
+                           e);
+                    continue;
+                }
+                self.status(ui,
+                            Status::Using,
+                            format!("{} for {} for {}", src.display(), ident, target))?;
+                if !existed {
+                    self.created().push(path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The checksum of the artifact for `ident` for `target` in Builder's metadata, if it has
+    /// one. It's asked for unless it came along with resolving the package.
+    fn expected_checksum(&self, ident: &PackageIdent, target: PackageTarget) -> Option<String> {
+        let cached = self.metadata_cache()
+                         .iter()
+                         .find(|((_, t, _), package)| *t == target && package.ident == *ident)
+                         .map(|(_, package)| package.checksum.clone());
+        let checksum = match cached {
+            Some(checksum) => checksum,
+            None => {
+                match self.fetch_latest_package_in_channels_for(ident,
+                                                                target,
+                                                                &self.channels,
+                                                                self.token)
+                {
+                    Ok((package, _)) => package.checksum,
+                    Err(e) => {
+                        debug!("Going by the file name of {} for {}, since there's no checksum \
+                                for it: {}",
+                               ident, target, e);
+                        return None;
+                    }
+                }
+            }
+        };
+        Some(checksum).filter(|checksum| !checksum.is_empty())
+    }
+
+    /// Puts the artifact in the download directory from the local artifact cache, if it's there,
+    /// hardlinking it where possible. If that doesn't work out it's downloaded as usual, so
+    /// errors are only logged.
+    fn link_from_local_cache(&self, ident: &PackageIdent, target: PackageTarget) -> bool {
+        let local_cache = match self.local_cache {
+            Some(ref local_cache) => local_cache,
+            None => return false,
+        };
+        let dest = self.downloaded_artifact_path(ident, target);
+        let src = local_cache.join(dest.file_name().expect("artifact path has a file name"));
+        if !src.is_file() {
+            return false;
+        }
+        match fs::hard_link(&src, &dest).or_else(|_| fs::copy(&src, &dest).map(|_| ())) {
+            Ok(()) => {
+                debug!("Took {} for {} from {}", ident, target, src.display());
+                self.created().push(dest);
+                true
+            }
+            Err(e) => {
+                debug!("Couldn't take {} for {} from {}, downloading it instead: {}",
+                       ident,
+                       target,
+                       src.display(),
+                       e);
+                // Don't leave half a copy behind to be mistaken for the artifact
+                let _ = fs::remove_file(&dest);
+                false
+            }
+        }
+    }
+
+    /// Looks for the latest package matching `ident` in each channel in turn, returning the
+    /// first one found along with the channel it came from.
+    fn fetch_latest_package_in_channels_for<'c>(
+        &self,
+        ident: &PackageIdent,
+        target: PackageTarget,
+        channels: &'c [ChannelIdent],
+        token: Option<&str>)
+        -> api_client::Result<(Package, &'c ChannelIdent)> {
+        let mut not_found = None;
+        for channel in channels {
+            let key = (ident.clone(), target, channel.clone());
+            if let Some(package) = self.metadata_cache().get(&key) {
+                debug!("Using the metadata already fetched for {} for {} in the '{}' channel",
+                       ident, target, channel);
+                return Ok((package.clone(), channel));
+            }
+            match self.retry_policy
+                      .run(|| {
+                          self.api_client
+                              .show_package_metadata((&ident, target), channel, token)
+                      })
+                      .map_err(last_attempt_error)
+            {
+                Ok(package) => {
+                    self.metadata_cache().insert(key, package.clone());
+                    return Ok((package, channel));
+                }
+                Err(err @ APIError(StatusCode::NOT_FOUND, _)) => {
+                    debug!("{} for {} isn't in the '{}' channel",
+                           ident, target, channel);
+                    not_found = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(not_found.expect("at least one channel to download from"))
+    }
+
+    /// With `keep_versions`, the releases of `ident` in `channel` that come before the `latest`
+    /// one, newest first, for as many as there are to keep besides it.
+    fn fetch_older_releases(&self,
+                            ident: &PackageIdent,
+                            latest: &PackageIdent,
+                            target: PackageTarget,
+                            channel: &ChannelIdent)
+                            -> api_client::Result<Vec<Package>> {
+        if self.keep_versions <= 1 || ident.fully_qualified() {
+            return Ok(Vec::new());
+        }
+        let mut releases = self.retry_policy
+                               .run(|| {
+                                   self.api_client
+                                       .list_package_releases((ident, target), channel, self.token)
+                               })
+                               .map_err(last_attempt_error)?;
+        releases.retain(|release| release < latest);
+        releases.sort_by(|a, b| b.cmp(a));
+        releases.truncate(self.keep_versions - 1);
+        debug!("Keeping {} older releases of {} for {}",
+               releases.len(),
+               ident,
+               target);
+        let mut packages = Vec::with_capacity(releases.len());
+        for release in &releases {
+            let (package, _) = self.fetch_latest_package_in_channels_for(release,
+                                                                         target,
+                                                                         slice::from_ref(channel),
+                                                                         self.token)?;
+            packages.push(package);
+        }
+        Ok(packages)
+    }
+
+    // The resolve workers share the cache. Two of them may both miss on the same key and fetch
+    // it, which costs a request but is otherwise harmless.
+    fn metadata_cache(&self) -> MutexGuard<'_, MetadataCache> {
+        self.metadata_cache
+            .lock()
+            .expect("Metadata cache lock is poisoned")
+    }
+
+    /// Fails with what's `left` to do once the deadline has passed, so that nothing more gets
+    /// started. Work that's already underway is left to finish, and a later download picks up
+    /// whatever of it made it into the download directory.
+    fn check_deadline<F>(&self, left: F) -> Result<()>
+        where F: FnOnce() -> Vec<String>
+    {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                let mut left = left();
+                if left.is_empty() {
+                    return Ok(());
+                }
+                left.sort();
+                Err(Error::DeadlineExceeded(left))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with what's `left` to do once Ctrl-C has been pressed, so that nothing more gets
+    /// started. As with the deadline, the artifacts already being downloaded are finished first,
+    /// rather than left half-written.
+    fn check_interrupted<F>(&self, left: F) -> Result<()>
+        where F: FnOnce() -> Vec<String>
+    {
+        if !self.is_interrupted() {
+            return Ok(());
+        }
+        let mut left = left();
+        if left.is_empty() {
+            return Ok(());
+        }
+        left.sort();
+        Err(Error::Interrupted(left))
+    }
+
+    /// Whether Ctrl-C has been pressed since the download started.
+    fn is_interrupted(&self) -> bool {
+        if interrupt::interrupted() {
+            self.interrupted.store(true, Ordering::SeqCst);
+        }
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    /// The signer of the artifact at `path`, which is only read from the artifact the first time
+    /// it's asked for.
+    fn artifact_signer(&self, path: &Path) -> std::result::Result<String, CoreError> {
+        if let Some(signer) = self.signers().get(path) {
+            return Ok(signer.clone());
+        }
+        let signer = artifact::artifact_signer(path)?;
+        self.signers().insert(path.to_path_buf(), signer.clone());
+        Ok(signer)
+    }
+
+    fn signers(&self) -> MutexGuard<'_, HashMap<PathBuf, String>> {
+        self.signers.lock().expect("Signers lock is poisoned")
+    }
+
+    fn created(&self) -> MutexGuard<'_, Vec<PathBuf>> {
+        self.created.lock().expect("Created files lock is poisoned")
+    }
+
+    fn ensured_signers(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.ensured_signers
+            .lock()
+            .expect("Ensured signers lock is poisoned")
+    }
+
+    /// Writes a status line about a single ident or artifact, unless running quietly.
+    fn status<T, M>(&self, ui: &mut T, status: Status, message: M) -> Result<()>
+        where T: UIWriter,
+              M: fmt::Display
+    {
+        if !self.quiet {
+            ui.status(status, message)?;
+        }
+        Ok(())
+    }
+
+    /// A progress bar for a single download, unless running quietly or there's one for the whole
+    /// download.
+    fn progress<T>(&self, ui: &T) -> Option<Box<dyn DisplayProgress>>
+        where T: UIWriter
+    {
+        if self.quiet || self.overall_progress {
+            None
+        } else {
+            ui.progress()
+        }
+    }
+
+    /// Tells the observers about a lifecycle event for an ident.
+    fn event(&self, kind: EventKind, phase: Phase, ident: &PackageIdent, target: PackageTarget) {
+        let event = ProgressEvent::new(kind, phase, ident, target);
+        for observer in &self.observers {
+            notify(observer.as_ref(), &event);
+        }
+    }
+
+    fn error_event(&self,
+                   phase: Phase,
+                   ident: &PackageIdent,
+                   target: PackageTarget,
+                   message: String) {
+        let event = ProgressEvent::error(phase, ident, target, message);
+        for observer in &self.observers {
+            notify(observer.as_ref(), &event);
+        }
+    }
+
+    /// The channels to resolve `source` in, in order.
+    fn channels_for<'s>(&'s self, source: &'s DownloadSource) -> &'s [ChannelIdent] {
+        match source.channel {
+            Some(ref channel) => slice::from_ref(channel),
+            None => &self.channels,
+        }
+    }
+
+    fn target_for(&self, source: &DownloadSource) -> PackageTarget {
+        source.target.unwrap_or(self.target)
+    }
+
+    fn path_for_keys(&self) -> PathBuf { self.layout.keys_path(&self.download_path) }
+
+    /// Where the keys that artifacts are verified against are.
+    fn path_for_verification_keys(&self) -> PathBuf {
+        self.keys_from
+            .map_or_else(|| self.path_for_keys(), Path::to_path_buf)
+    }
+
+    /// Where artifacts go. In the by-channel layout that's the first channel's directory, for
+    /// artifacts that weren't found in any channel in particular.
+    fn path_for_artifact(&self) -> PathBuf {
+        let channel = self.channels
+                          .first()
+                          .cloned()
+                          .unwrap_or_else(ChannelIdent::stable);
+        self.layout.artifacts_path(&self.download_path, &channel)
+    }
+
+    /// Where the artifact for `ident` for `target` goes, which in the by-channel layout depends
+    /// on where it was found.
+    fn path_for_artifact_of(&self, ident: &PackageIdent, target: PackageTarget) -> PathBuf {
+        let resolved_channels = self.resolved_channels();
+        match resolved_channels.get(&(ident.clone(), target)) {
+            Some(channel) => self.layout.artifacts_path(&self.download_path, channel),
+            None => self.path_for_artifact(),
+        }
+    }
+
+    /// Every directory artifacts go in: the one, or in the by-channel layout those for all of
+    /// the channels this download looks in and any others already in the download directory.
+    fn paths_for_artifacts(&self) -> Result<Vec<PathBuf>> {
+        if self.layout != DownloadLayout::ByChannel {
+            return Ok(vec![self.path_for_artifact()]);
+        }
+        let mut paths = Vec::new();
+        if self.download_path.is_dir() {
+            paths = self.layout.existing_artifacts_paths(&self.download_path)?;
+        }
+        let channels = self.channels
+                           .iter()
+                           .chain(self.sources.iter().filter_map(|s| s.channel.as_ref()));
+        for channel in channels {
+            paths.push(self.layout.artifacts_path(&self.download_path, channel));
+        }
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Remembers which channel `package` and, unless they've been found already, its
+    /// dependencies were found in, to put their artifacts there and say so in their metadata.
+    fn record_channel(&self,
+                      package: &Package,
+                      target: PackageTarget,
+                      channel: Option<&ChannelIdent>) {
+        let channel = match channel {
+            Some(channel) if self.layout == DownloadLayout::ByChannel || self.with_metadata => {
+                channel
+            }
+            _ => return,
+        };
+        let deps = package.tdeps
+                          .iter()
+                          .chain(&package.build_deps)
+                          .chain(&package.build_tdeps);
+        let mut resolved_channels = self.resolved_channels();
+        resolved_channels.insert((package.ident.clone(), target), channel.clone());
+        for ident in deps {
+            resolved_channels.entry((ident.clone(), target))
+                             .or_insert_with(|| channel.clone());
+        }
+    }
+
+    fn resolved_channels(
+        &self)
+        -> MutexGuard<'_, HashMap<(PackageIdent, PackageTarget), ChannelIdent>> {
+        self.resolved_channels
+            .lock()
+            .expect("Resolved channels lock is poisoned")
+    }
+
+    /// Sanity check the download directory tree. The errors from the api around permissions are
+    /// opaque; this validates the directory in advance to help provide useful feedback.
+    fn verify_and_prepare_download_directory<T>(&self, ui: &mut T) -> Result<()>
+        where T: UIWriter
+    {
+        let mut system_paths = vec![self.download_path.clone(), self.path_for_keys()];
+        system_paths.extend(self.paths_for_artifacts()?);
+
+        ui.status(Status::Verifying,
+                  format!("the download directory \"{}\"",
+                          self.download_path.display()))?;
+
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+
+        // Create directories if they don't exist
+        for dir in &system_paths {
+            builder.create(dir).map_err(|e| {
+                                    mk_perm_error(format!("Can't create directory {:?} needed \
+                                                           for download: {}",
+                                                          dir, e))
+                                })?
+        }
+
+        // Check permissions of directories. A read-only attribute on a directory doesn't stop
+        // anything being written to it on Windows, so the only way to know is to try.
+        for dir in &system_paths {
+            let metadata = std::fs::metadata(dir).map_err(|e| {
+                                                     mk_perm_error(format!("Can't read {}: {}",
+                                                                           dir.display(),
+                                                                           e))
+                                                 })?;
+            if !metadata.is_dir() {
+                return Err(mk_perm_error(format!("{} isn't a directory, needed for \
+                                                  download",
+                                                 dir.display())));
+            }
+            check_writeable(dir)?;
+        }
+
+        // Everything is written to a temporary file and renamed into place once it's complete,
+        // so an interrupted run can leave those behind. Keys are written with an `AtomicWriter`,
+        // whose temp files are of no further use. Artifacts go to a `.part` file, which is kept
+        // so that the download can pick up where it left off.
+        let mut dirs = vec![self.path_for_keys()];
+        dirs.extend(self.paths_for_artifacts()?);
+        dirs.dedup();
+        for dir in &dirs {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if is_leftover_temp_file(&path) {
+                    debug!("Removing leftover temp file {}", path.display());
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Leaves out whatever the last sync got. Unlike `is_cached`, that doesn't need it to still be in
+// the download directory, so one that's emptied onto a mirror after each run stays incremental.
+fn skip_synced<T>(ui: &mut T,
+                  last_sync: Option<&SyncState>,
+                  expanded_idents: HashSet<(PackageIdent, PackageTarget)>)
+                  -> Result<HashSet<(PackageIdent, PackageTarget)>>
+    where T: UIWriter
+{
+    let synced = match last_sync {
+        Some(last_sync) => last_sync.synced(),
+        None => return Ok(expanded_idents),
+    };
+    let (already, remaining): (HashSet<_>, HashSet<_>) =
+        expanded_idents.into_iter()
+                       .partition(|a| synced.contains(a));
+    if !already.is_empty() {
+        ui.status(Status::Skipping,
+                  format!("{} artifacts the last sync got", already.len()))?;
+    }
+    Ok(remaining)
+}
+
+/// When a package was released, going by its release, which is the time it was built in UTC.
+fn released_at(ident: &PackageIdent) -> Option<DateTime<Utc>> {
+    ident.release
+         .as_ref()
+         .and_then(|release| Utc.datetime_from_str(release, "%Y%m%d%H%M%S").ok())
+}
+
+/// Whether `path` is a temp file an `AtomicWriter` didn't get to rename or clean up.
+fn is_leftover_temp_file(path: &Path) -> bool {
+    path.is_file()
+    && path.file_name()
+           .and_then(OsStr::to_str)
+           .map_or(false, |name| name.starts_with(".tmp"))
+}
+
+/// Whether a directory can be written to is down to more than its permission bits (the effective
+/// user, ACLs, a read-only or full filesystem), so the only reliable way to find out is to write
+/// something. The file is named like an `AtomicWriter` temp file, so that if it can't be removed
+/// it's cleaned up next time.
+fn check_writeable(dir: &Path) -> Result<()> {
+    let probe = dir.join(format!(".tmp-write-test-{}", std::process::id()));
+    fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe))
+                          .map_err(|e| {
+                              mk_perm_error(format!("{} isn't writeable, needed for download: {}",
+                                                    dir.display(),
+                                                    e))
+                          })
+}
+
+fn mk_perm_error(msg: String) -> Error { CoreError::PermissionFailed(msg).into() }
+
+/// The cache_*_path functions in fs don't let you override a path base with Some(base)
+/// So we have to build our own paths.
+fn keys_path(download_path: &Path) -> PathBuf { download_path.join("keys") }
+
+fn artifacts_path(download_path: &Path) -> PathBuf { download_path.join("artifacts") }
+
+/// Unwraps the error from the last attempt at an operation run by `RetryPolicy::run`.
+fn last_attempt_error(err: RetryError<api_client::Error>) -> api_client::Error {
+    match err {
+        RetryError::Operation { error, .. } => error,
+        RetryError::Internal(msg) => {
+            api_client::Error::IO(io::Error::new(io::ErrorKind::Other, msg))
+        }
+    }
+}
+
+/// Whether a failed request could succeed if it were made again. Things like a missing package
+/// or a bad token aren't going to fix themselves, so there's no point waiting around for them.
+fn is_retryable(err: &api_client::Error) -> bool {
+    match *err {
+        // Asking again won't make the depot support a platform it doesn't
+        APIError(StatusCode::NOT_IMPLEMENTED, _) => false,
+        APIError(status, _) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        api_client::Error::ReqwestError(_)
+        | api_client::Error::HabitatHttpClient(_)
+        | api_client::Error::IO(_)
+        | api_client::Error::IncompleteDownload(..)
+        | api_client::Error::BadResponseBody(_) => true,
+        _ => false,
+    }
+}
+
+/// What's wrong with an ident that would only come out once Builder is asked about it, if
+/// anything. An `origin/*` wildcard is fine.
+fn ident_problem(ident: &PackageIdent) -> Option<String> {
+    let is_name = |name: &str| {
+        !name.is_empty()
+        && name.chars()
+               .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    let is_release =
+        |release: &str| release.len() == 14 && release.chars().all(|c| c.is_ascii_digit());
+    let is_wildcard = ident.name == "*" && ident.version.is_none();
+    let empty_version = ident.version
+                             .as_ref()
+                             .map_or(false, |v| v.trim().is_empty());
+    // The version is part of the artifact's file name, which can't have these on Windows
+    let unnameable_version = cfg!(windows)
+                             && ident.version
+                                     .as_ref()
+                                     .map_or(false, |v| v.contains(WINDOWS_RESERVED_CHARS));
+    let problem = if !ident::is_valid_origin_name(&ident.origin) {
+        "has an origin that isn't lowercase letters, numbers, - and _"
+    } else if !is_name(&ident.name) && !is_wildcard {
+        "has a name that isn't letters, numbers, - and _"
+    } else if empty_version {
+        "has an empty version"
+    } else if unnameable_version {
+        "has a version with a character that can't be in a file name on Windows"
+    } else if !ident.release.as_ref().map_or(true, |r| is_release(r)) {
+        "has a release that isn't a timestamp like 20190319155852"
+    } else {
+        return None;
+    };
+    Some(format!("'{}' {}", ident, problem))
+}
+
+/// The characters Windows doesn't allow in a file name, besides the path separators
+const WINDOWS_RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Removes repeated idents, keeping the first of each in order. The repeats are returned too, so
+/// they can be pointed out.
+fn dedupe<T>(items: Vec<T>) -> (Vec<T>, Vec<T>)
+    where T: Clone + Eq + Hash
+{
+    let mut seen = HashSet::new();
+    let (unique, duplicates) = items.into_iter()
+                                    .partition(|item| seen.insert(item.clone()));
+    (unique, duplicates)
+}
+
+/// The channels to look in, for messages.
+fn channel_list(channels: &[ChannelIdent]) -> String {
+    channels.iter()
+            .map(|channel| format!("'{}'", channel))
+            .collect::<Vec<_>>()
+            .join(", then ")
+}
+
+/// Removes the file at `path`, along with what's kept next to it about it.
+fn remove_with_sidecars(path: &Path) -> io::Result<()> {
+    fs::remove_file(path)?;
+    for sidecar in &[etag_path(path),
+                     verified::sidecar_path(path),
+                     metadata::sidecar_path(path)]
+    {
+        if sidecar.is_file() {
+            fs::remove_file(sidecar)?;
+        }
+    }
+    Ok(())
+}
+
+/// How an artifact is named in messages about several of them.
+fn artifact_names<'i, I>(idents: I) -> Vec<String>
+    where I: IntoIterator<Item = &'i (PackageIdent, PackageTarget)>
+{
+    idents.into_iter()
+          .map(|(ident, target)| format!("{} for {}", ident, target))
+          .collect()
+}
+
+/// Tells the operator what the run did: how much was downloaded against what was already here,
+/// where it went, and how long it took.
+fn print_summary<T>(ui: &mut T,
+                    resolved: usize,
+                    download_path: &Path,
+                    artifacts_dirs: &[PathBuf],
+                    report: &DownloadReport,
+                    elapsed: Duration)
+                    -> Result<()>
+    where T: UIWriter
+{
+    if !report.artifacts.is_empty() || !report.skipped.is_empty() || !report.failed.is_empty() {
+        let color = ui.out().supports_color();
+        let width = ui.out_width();
+        let size = |artifact: &str| {
+            let path = find_file(artifacts_dirs, artifact)?;
+            Some(fs::metadata(path).ok()?.len())
+        };
+        write_table(ui.out(), report, size, width, color)?;
+    }
+    let cached = report.artifacts_from(ArtifactSource::Cache);
+    let local = report.artifacts_from(ArtifactSource::LocalCache);
+    ui.end(format!("Downloaded {} artifacts ({} from cache) for {} package idents to {} in \
+                    {:.1}s",
+                   report.artifacts.len(),
+                   cached + local,
+                   resolved,
+                   download_path.display(),
+                   elapsed.as_secs_f64()))?;
+    ui.info(format!("  {} already in the download directory, {} from the local cache, {} \
+                     downloaded ({} bytes)",
+                    cached,
+                    local,
+                    report.artifacts.len() - cached - local,
+                    report.bytes_transferred()))?;
+    if let Some(ratio) = report.cache_hit_ratio() {
+        ui.info(format!("  {:.0}% from cache, saving about {}",
+                        ratio * 100.0,
+                        human_size(report.bytes_saved)))?;
+    }
+    ui.info(format!("  {} public keys downloaded", report.keys_downloaded))?;
+    if !report.skipped.is_empty() {
+        ui.info(format!("  {} skipped as unsupported", report.skipped.len()))?;
+    }
+    if !report.failed.is_empty() {
+        ui.info(format!("  {} failed", report.failed.len()))?;
+    }
+    ui.info(format!("  {}", phase_timings(&report.timings)))?;
+    Ok(())
+}
+
+/// How long each phase took, for the summary.
+fn phase_timings(timings: &PhaseTimings) -> String {
+    let mut phases = vec![format!("{:.1}s resolving", timings.resolve_secs),
+                          format!("{:.1}s downloading", timings.download_secs)];
+    if let Some(secs) = timings.key_fetch_secs {
+        phases.push(format!("{:.1}s fetching keys", secs));
+    }
+    if let Some(secs) = timings.verify_secs {
+        phases.push(format!("{:.1}s verifying", secs));
+    }
+    phases.join(", ")
+}
+
+/// Writes out one fully qualified ident and target per line, sorted so that the output of two
+/// runs can be diffed.
+fn print_resolved_idents<T>(ui: &mut T,
+                            expanded_idents: &HashSet<(PackageIdent, PackageTarget)>)
+                            -> Result<()>
+    where T: UIWriter
+{
+    let mut resolved: Vec<String> =
+        expanded_idents.iter()
+                       .map(|(ident, target)| format!("{} {}", ident, target))
+                       .collect();
+    resolved.sort();
+    for line in resolved {
+        ui.info(line)?;
+    }
+    Ok(())
+}
+
+/// Runs `work` over every item on at most `jobs` scoped worker threads, handing each result to
+/// `complete` on the calling thread as soon as it is ready. Anything that touches the UI belongs
+/// in `complete`. Once `complete` returns an error no new work is started, and that error is
+/// returned after the in-flight work drains.
+fn for_each_parallel<I, R, W, C>(jobs: usize, items: &[I], work: W, mut complete: C) -> Result<()>
+    where I: Sync,
+          R: Send,
+          W: Fn(&I) -> R + Sync,
+          C: FnMut(&I, R) -> Result<()>
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1).min(items.len()) {
+            let tx = tx.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            s.spawn(move |_| {
+                 while !stop.load(Ordering::SeqCst) {
+                     let i = next.fetch_add(1, Ordering::SeqCst);
+                     if i >= items.len() || tx.send((i, work(&items[i]))).is_err() {
+                         break;
+                     }
+                 }
+             });
+        }
+        // Only the workers hold senders now, so the loop below ends when they do.
+        drop(tx);
+
+        let mut outcome = Ok(());
+        for (i, result) in rx {
+            if outcome.is_ok() {
+                outcome = complete(&items[i], result);
+                if outcome.is_err() {
+                    stop.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+        outcome
+    }).expect("Download worker thread panicked")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ui::UI;
+    use std::{str::FromStr,
+              sync::Arc};
+    use tempfile::TempDir;
+
+    const REDIS: &str = "core/redis/4.0.14/20190319155852";
+
+    /// Stands in for Builder, knowing about at most one package, and any older `releases` of it
+    /// with the same metadata. It has no keys to give out, only has an artifact to when it
+    /// `serves_artifacts`, and counts how often it's asked for one.
+    struct MockClient {
+        package:          Option<Package>,
+        releases:         Vec<PackageIdent>,
+        channels:         Option<Vec<String>>,
+        fetches:          Arc<AtomicUsize>,
+        serves_artifacts: bool,
+    }
+
+    impl MockClient {
+        fn with_package(ident: &str) -> Self {
+            let ident = PackageIdent::from_str(ident).unwrap();
+            let package = Package { ident,
+                                    checksum: String::new(),
+                                    manifest: String::new(),
+                                    deps: Vec::new(),
+                                    tdeps: Vec::new(),
+                                    build_deps: Vec::new(),
+                                    build_tdeps: Vec::new(),
+                                    exposes: Vec::new(),
+                                    config: String::new() };
+            MockClient { package:          Some(package),
+                         releases:         Vec::new(),
+                         channels:         None,
+                         fetches:          Arc::new(AtomicUsize::new(0)),
+                         serves_artifacts: false, }
+        }
+
+        fn empty() -> Self {
+            MockClient { package:          None,
+                         releases:         Vec::new(),
+                         channels:         None,
+                         fetches:          Arc::new(AtomicUsize::new(0)),
+                         serves_artifacts: false, }
+        }
+    }
+
+    fn not_found<T>() -> api_client::Result<T> {
+        Err(APIError(StatusCode::NOT_FOUND, String::new()))
+    }
+
+    impl DownloadClient for MockClient {
+        fn fetch_package(&self,
+                         (ident, target): (&PackageIdent, PackageTarget),
+                         _: Option<&str>,
+                         dst_path: &Path,
+                         _: Option<Box<dyn DisplayProgress>>)
+                         -> api_client::Result<PackageArchive> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            if !self.serves_artifacts {
+                return not_found();
+            }
+            let path = fetch::artifact_path(dst_path, ident, target);
+            fs::write(&path, "HART-1\n").unwrap();
+            Ok(PackageArchive::new(path))
+        }
+
+        fn stream_package(&self,
+                          _: (&PackageIdent, PackageTarget),
+                          _: Option<&str>,
+                          _: &mut dyn Write)
+                          -> api_client::Result<u64> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            not_found()
+        }
+
+        fn check_package(&self,
+                         _: (&PackageIdent, PackageTarget),
+                         _: Option<&str>)
+                         -> api_client::Result<()> {
+            not_found()
+        }
+
+        fn package_size(&self,
+                        _: (&PackageIdent, PackageTarget),
+                        _: Option<&str>)
+                        -> api_client::Result<Option<u64>> {
+            Ok(None)
+        }
+
+        fn show_package_metadata(&self,
+                                 (ident, _): (&PackageIdent, PackageTarget),
+                                 _: &ChannelIdent,
+                                 _: Option<&str>)
+                                 -> api_client::Result<Package> {
+            match self.package {
+                Some(ref package) if self.releases.contains(ident) => {
+                    Ok(Package { ident: ident.clone(),
+                                 ..package.clone() })
+                }
+                Some(ref package) => Ok(package.clone()),
+                None => not_found(),
+            }
+        }
+
+        fn list_package_releases(&self,
+                                 _: (&PackageIdent, PackageTarget),
+                                 _: &ChannelIdent,
+                                 _: Option<&str>)
+                                 -> api_client::Result<Vec<PackageIdent>> {
+            let latest = self.package.iter().map(|package| package.ident.clone());
+            Ok(latest.chain(self.releases.iter().cloned()).collect())
+        }
+
+        fn list_origin_packages(&self,
+                                _: &str,
+                                _: Option<&str>)
+                                -> api_client::Result<Vec<PackageIdent>> {
+            Ok(Vec::new())
+        }
+
+        fn list_channels(&self, _: &str) -> api_client::Result<Option<Vec<String>>> {
+            Ok(self.channels.clone())
+        }
+
+        fn fetch_origin_key(&self,
+                            _: &str,
+                            _: &str,
+                            _: Option<&str>,
+                            _: &Path,
+                            _: Option<Box<dyn DisplayProgress>>)
+                            -> api_client::Result<PathBuf> {
+            not_found()
+        }
+    }
+
+    fn test_download<'a>(download_path: &Path, client: MockClient) -> DownloadBuilder<'a> {
+        DownloadBuilder::new("https://bldr.example.com", "hab", "0.0.0")
+            .api_client(Box::new(client))
+            .ident(PackageIdent::from_str("core/redis").unwrap())
+            .target(PackageTarget::from_str("x86_64-linux").unwrap())
+            .download_path(download_path)
+            .download_keys(false)
+            .use_local_cache(false)
+            .check_space(false)
+            .quiet(true)
+    }
+
+    /// A download directory that's removed at the end of the test, and a UI that discards what's
+    /// written to it.
+    struct Fixture {
+        dir: TempDir,
+        ui:  UI,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture { dir: TempDir::new().unwrap(),
+                      ui:  UI::with_sinks(), }
+        }
+
+        fn path(&self) -> &Path { self.dir.path() }
+
+        /// A download of `core/redis` from `client` to the fixture's directory. See
+        /// `test_download`.
+        fn download<'a>(&self, client: MockClient) -> DownloadBuilder<'a> {
+            test_download(self.dir.path(), client)
+        }
+    }
+
+    /// `REDIS` and the target the tests download it for.
+    fn redis() -> (PackageIdent, PackageTarget) {
+        (PackageIdent::from_str(REDIS).unwrap(), PackageTarget::from_str("x86_64-linux").unwrap())
+    }
+
+    /// Puts an artifact for `REDIS` in the download directory that names `signer`, but whose
+    /// signature is garbage.
+    fn write_cached_artifact(download_path: &Path, signer: &str) {
+        let artifacts = artifacts_path(download_path);
+        fs::create_dir_all(&artifacts).unwrap();
+        let (ident, target) = redis();
+        fs::write(artifacts.join(ident.archive_name_with_target(target).unwrap()),
+                  format!("HART-1\n{}\nBLAKE2b\nsignature\n\n", signer)).unwrap();
+    }
+
+    /// Remembers which artifacts it's told were cache hits, and which kinds of events it heard of.
+    #[derive(Default)]
+    struct Recorder {
+        cache_hits: Mutex<Vec<String>>,
+        kinds:      Mutex<Vec<EventKind>>,
+    }
+
+    impl DownloadObserver for Arc<Recorder> {
+        fn event(&self, event: &ProgressEvent<'_>) { self.kinds.lock().unwrap().push(event.event); }
+
+        fn cache_hit(&self, ident: &PackageIdent, _target: PackageTarget) {
+            self.cache_hits.lock().unwrap().push(ident.to_string());
+        }
+    }
+
+    #[test]
+    fn fixed_retry_delays() {
+        let policy = RetryPolicy { retries: 3,
+                                   wait:    Duration::from_millis(500),
+                                   backoff: RetryBackoff::Fixed, };
+        assert_eq!(policy.delays().collect::<Vec<_>>(),
+                   vec![Duration::from_millis(500); 3]);
+    }
+
+    #[test]
+    fn exponential_retry_delays_are_capped() {
+        let policy = RetryPolicy { retries: 10,
+                                   wait:    Duration::from_secs(10),
+                                   backoff: RetryBackoff::Exponential, };
+        let delays: Vec<_> = policy.delays().collect();
+        assert_eq!(delays.len(), 10);
+        assert!(delays.iter().all(|d| *d <= MAX_RETRY_WAIT));
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        assert!(is_retryable(&APIError(StatusCode::SERVICE_UNAVAILABLE, String::new())));
+        assert!(is_retryable(&APIError(StatusCode::TOO_MANY_REQUESTS, String::new())));
+        assert!(is_retryable(&api_client::Error::IncompleteDownload(PathBuf::new(), 2, 1)));
+        assert!(!is_retryable(&APIError(StatusCode::NOT_FOUND, String::new())));
+        assert!(!is_retryable(&APIError(StatusCode::NOT_IMPLEMENTED, String::new())));
+        assert!(!is_retryable(&APIError(StatusCode::UNAUTHORIZED, String::new())));
+        assert!(!is_retryable(&api_client::Error::IdentNotFullyQualified));
+    }
+
+    #[test]
+    fn duplicate_idents_are_dropped_in_order() {
+        let idents: Vec<PackageIdent> =
+            ["core/redis", "core/nginx", "core/redis"].iter()
+                                                      .map(|i| i.parse().unwrap())
+                                                      .collect();
+        let (unique, duplicates) = dedupe(idents.clone());
+        assert_eq!(unique, idents[..2].to_vec());
+        assert_eq!(duplicates, vec![idents[2].clone()]);
+    }
+
+    #[test]
+    fn only_atomic_writer_temp_files_are_leftovers() {
+        let fx = Fixture::new();
+        let temp = fx.path().join(".tmpA1b2C3");
+        let part = fx.path()
+                     .join("core-redis-3.0.1-20190101000000-x86_64-linux.hart.part");
+        let key = fx.path().join("core-20180119235000.pub");
+        for path in &[&temp, &part, &key] {
+            fs::write(path, "").unwrap();
+        }
+
+        assert!(is_leftover_temp_file(&temp));
+        assert!(!is_leftover_temp_file(&part));
+        assert!(!is_leftover_temp_file(&key));
+    }
+
+    #[test]
+    fn packages_builder_doesnt_have_are_not_found() {
+        let mut fx = Fixture::new();
+        let dry_run = fx.download(MockClient::empty())
+                        .execution_strategy(ExecutionStrategy::DryRun);
+        let task = dry_run.build(&mut fx.ui).unwrap();
+
+        match task.execute(&mut fx.ui) {
+            Err(Error::HabitatCommon(CommonError::PackageNotFound(_))) => {}
+            other => panic!("Expected PackageNotFound, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn cached_artifacts_are_not_fetched_again() {
+        let mut fx = Fixture::new();
+        write_cached_artifact(fx.path(), "core-20180119235000");
+
+        let client = MockClient::with_package(REDIS);
+        let fetches = Arc::clone(&client.fetches);
+        let report = fx.download(client)
+                       .build(&mut fx.ui)
+                       .unwrap()
+                       .execute(&mut fx.ui)
+                       .unwrap();
+
+        assert_eq!(fetches.load(Ordering::Relaxed), 0);
+        assert_eq!(report.artifacts.len(), 1);
+        assert_eq!(report.artifacts[0].source, ArtifactSource::Cache);
+        assert_eq!(report.artifacts[0].signer, "core-20180119235000");
+        let cached = "HART-1\ncore-20180119235000\nBLAKE2b\nsignature\n\n";
+        assert_eq!(report.bytes_saved, cached.len() as u64);
+        assert_eq!(report.cache_hit_ratio(), Some(1.0));
+    }
+
+    #[test]
+    fn failed_downloads_are_collected() {
+        let mut fx = Fixture::new();
+        let task = fx.download(MockClient::with_package(REDIS))
+                     .continue_on_error(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+
+        match task.execute(&mut fx.ui) {
+            Err(Error::DownloadsFailed(failed)) => {
+                assert_eq!(failed, vec![format!("{} for x86_64-linux", REDIS)]);
+            }
+            other => panic!("Expected DownloadsFailed, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn verify_all_removes_everything_the_run_downloaded_when_anything_doesnt_verify() {
+        let mut fx = Fixture::new();
+        let keys = keys_path(fx.path());
+        fs::create_dir_all(&keys).unwrap();
+        let pair = SigKeyPair::generate_pair_for_origin("core").unwrap();
+        pair.to_pair_files(&keys).unwrap();
+        write_cached_artifact(fx.path(), &pair.name_with_rev());
+        let task = fx.download(MockClient::with_package(REDIS))
+                     .verify_all(true)
+                     .remove_if_unverified(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        let (redis, target) = redis();
+        let tree = PackageIdent::from_str("core/tree/1.7.0/20180609045201").unwrap();
+        // Downloaded by this run, and recorded as having verified already
+        let downloaded = task.downloaded_artifact_path(&tree, target);
+        fs::write(&downloaded, "HART-1\n").unwrap();
+        verified::record(&downloaded).unwrap();
+        task.created().push(downloaded.clone());
+        let artifact = |ident: &PackageIdent, source| {
+            ArtifactReport { ident: ident.clone(),
+                             target,
+                             artifact: ident.archive_name_with_target(target).unwrap(),
+                             source,
+                             signer: pair.name_with_rev(),
+                             bytes_transferred: 0,
+                             verified: false }
+        };
+        let mut report = DownloadReport::default();
+        report.artifacts = vec![artifact(&redis, ArtifactSource::Cache),
+                                artifact(&tree, ArtifactSource::Builder)];
+
+        match task.verify_artifacts(&mut fx.ui, 1, &report) {
+            Err(Error::VerificationFailed(failed)) => {
+                assert_eq!(failed, vec![format!("{} for x86_64-linux", REDIS)]);
+            }
+            other => panic!("Expected VerificationFailed, got {:?}", other),
+        }
+        assert!(!task.downloaded_artifact_path(&redis, target).exists());
+        assert!(!downloaded.exists());
+        assert!(!verified::sidecar_path(&downloaded).exists());
+        // It was there before the run
+        assert!(keys.join(format!("{}.pub", pair.name_with_rev())).is_file());
+    }
+
+    #[test]
+    fn malformed_idents_are_pointed_out() {
+        let problem = |ident: &str| ident_problem(&PackageIdent::from_str(ident).unwrap());
+        assert_eq!(problem(REDIS), None);
+        assert_eq!(problem("core/*"), None);
+        assert!(problem("Core/redis").is_some());
+        assert!(problem("core/re dis").is_some());
+        assert!(problem("core/redis//20190319155852").is_some());
+        assert!(problem("core/redis/4.0.14/latest").is_some());
+    }
+
+    #[test]
+    fn every_malformed_ident_is_reported() {
+        let mut fx = Fixture::new();
+        let ident_file = fx.path().join("idents.txt");
+        fs::write(&ident_file, "core/redis\ncore/re dis\ncore\n").unwrap();
+        let builder = fx.download(MockClient::empty())
+                        .ident(PackageIdent::from_str("core/redis/4.0.14/latest").unwrap())
+                        .ident_file(&ident_file);
+
+        match builder.build(&mut fx.ui) {
+            Err(Error::InvalidIdents(problems)) => assert_eq!(problems.len(), 3),
+            other => panic!("Expected InvalidIdents, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn build_deps_are_only_expanded_when_asked_for() {
+        let ident = |ident: &str| PackageIdent::from_str(ident).unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let mut package = MockClient::with_package(REDIS).package.unwrap();
+        package.tdeps = vec![ident("core/glibc/2.27/20190115002733")];
+        package.build_deps = vec![ident("core/gcc/8.2.0/20190115004042")];
+        package.build_tdeps = vec![ident("core/binutils/2.31.1/20190115003743")];
+
+        let mut fx = Fixture::new();
+        let mut expanded = |build_deps: bool| {
+            let task = fx.download(MockClient::empty())
+                         .build_deps(build_deps)
+                         .build(&mut fx.ui)
+                         .unwrap();
+            task.expand_sources(&mut fx.ui,
+                                vec![(package.ident.clone(), target, package.clone())])
+                .unwrap()
+                .len()
+        };
+        assert_eq!(expanded(false), 2);
+        assert_eq!(expanded(true), 4);
+    }
+
+    #[test]
+    fn releases_are_times() {
+        let released = |ident: &str| released_at(&PackageIdent::from_str(ident).unwrap());
+        assert_eq!(released(REDIS),
+                   Some(Utc.ymd(2019, 3, 19).and_hms(15, 58, 52)));
+        assert_eq!(released("core/redis/4.0.14/latest"), None);
+        assert_eq!(released("core/redis"), None);
+    }
+
+    #[test]
+    fn missing_artifacts_are_found() {
+        let mut fx = Fixture::new();
+        let task = fx.download(MockClient::empty()).build(&mut fx.ui).unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let mut expanded = HashSet::new();
+        expanded.insert((PackageIdent::from_str(REDIS).unwrap(), target));
+        let report = DownloadReport::default();
+
+        match task.reconcile(&expanded, &report) {
+            Err(Error::IncompleteDownload(missing)) => {
+                assert_eq!(missing, vec![format!("{} for x86_64-linux", REDIS)]);
+            }
+            other => panic!("Expected IncompleteDownload, got {:?}", other),
+        }
+        write_cached_artifact(fx.path(), "core-20180119235000");
+        assert!(task.reconcile(&expanded, &report).is_ok());
+    }
+
+    #[test]
+    fn a_flat_layout_keeps_everything_in_the_download_directory() {
+        let mut fx = Fixture::new();
+        let flat = fx.download(MockClient::empty())
+                     .layout(DownloadLayout::Flat);
+        let task = flat.build(&mut fx.ui).unwrap();
+
+        assert_eq!(task.path_for_artifact(), fx.path());
+        assert_eq!(task.path_for_keys(), fx.path());
+        task.verify_and_prepare_download_directory(&mut fx.ui)
+            .unwrap();
+        assert!(!artifacts_path(fx.path()).exists());
+        assert!(!keys_path(fx.path()).exists());
+    }
+
+    #[test]
+    fn an_interrupted_download_starts_nothing_new() {
+        let mut fx = Fixture::new();
+        let client = MockClient::with_package(REDIS);
+        let fetches = Arc::clone(&client.fetches);
+        let task = fx.download(client).build(&mut fx.ui).unwrap();
+        task.interrupted.store(true, Ordering::SeqCst);
+
+        match task.execute(&mut fx.ui) {
+            Err(Error::Interrupted(left)) => {
+                assert_eq!(left, vec![format!("{} for x86_64-linux", REDIS)]);
+            }
+            other => panic!("Expected Interrupted, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn observers_hear_about_each_event() {
+        let mut fx = Fixture::new();
+        write_cached_artifact(fx.path(), "core-20180119235000");
+
+        let recorder = Arc::new(Recorder::default());
+        fx.download(MockClient::with_package(REDIS))
+          .observer(Box::new(Arc::clone(&recorder)))
+          .build(&mut fx.ui)
+          .unwrap()
+          .execute(&mut fx.ui)
+          .unwrap();
+
+        assert_eq!(*recorder.cache_hits.lock().unwrap(),
+                   vec![REDIS.to_string()]);
+        assert_eq!(*recorder.kinds.lock().unwrap(),
+                   vec![EventKind::Resolving, EventKind::Resolved]);
+    }
+
+    #[test]
+    fn keep_versions_resolves_the_latest_releases() {
+        let mut fx = Fixture::new();
+        let mut client = MockClient::with_package(REDIS);
+        client.releases = vec![PackageIdent::from_str("core/redis/3.2.4/20170514150022").unwrap(),
+                               PackageIdent::from_str("core/redis/4.0.10/20180801003001").unwrap()];
+        let report = fx.download(client)
+                       .keep_versions(2)
+                       .execution_strategy(ExecutionStrategy::DryRun)
+                       .build(&mut fx.ui)
+                       .unwrap()
+                       .execute(&mut fx.ui)
+                       .unwrap();
+
+        let resolved: Vec<String> = report.resolved
+                                          .iter()
+                                          .map(|r| r.resolved.to_string())
+                                          .collect();
+        assert_eq!(resolved, vec![REDIS, "core/redis/4.0.10/20180801003001"]);
+    }
+
+    #[test]
+    fn a_precheck_finds_missing_artifacts_before_downloading() {
+        let mut fx = Fixture::new();
+        let client = MockClient::with_package(REDIS);
+        let fetches = Arc::clone(&client.fetches);
+        let task = fx.download(client)
+                     .precheck(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+
+        match task.execute(&mut fx.ui) {
+            Err(Error::HabitatCommon(CommonError::PackageNotFound(missing))) => {
+                assert_eq!(missing, format!("{} for x86_64-linux", REDIS));
+            }
+            other => panic!("Expected PackageNotFound, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 0);
+
+        let client = MockClient::with_package(REDIS);
+        let fetches = Arc::clone(&client.fetches);
+        let task = fx.download(client)
+                     .precheck(true)
+                     .continue_on_error(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        match task.execute(&mut fx.ui) {
+            Err(Error::DownloadsFailed(failed)) => {
+                assert_eq!(failed, vec![format!("{} for x86_64-linux", REDIS)]);
+            }
+            other => panic!("Expected DownloadsFailed, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn revalidating_asks_again_for_artifacts_with_an_etag() {
+        let mut fx = Fixture::new();
+        let task = fx.download(MockClient::empty())
+                     .revalidate(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        write_cached_artifact(fx.path(), "core-20180119235000");
+        let (ident, target) = redis();
+        assert!(task.is_cached(&ident, target));
+
+        let artifact = task.downloaded_artifact_path(&ident, target);
+        fs::write(etag_path(&artifact), "\"3f80f-1b6-3e1cb03b\"").unwrap();
+        assert!(!task.is_cached(&ident, target));
+    }
+
+    #[test]
+    fn artifacts_from_untrusted_origins_are_rejected() {
+        let mut fx = Fixture::new();
+        write_cached_artifact(fx.path(), "core-20180119235000");
+        let task = fx.download(MockClient::with_package(REDIS))
+                     .trusted_origin("chef")
+                     .trusted_origin("core-20160810182414")
+                     .build(&mut fx.ui)
+                     .unwrap();
+
+        match task.execute(&mut fx.ui) {
+            Err(Error::UntrustedSigner(artifact, signer)) => {
+                assert_eq!(artifact, format!("{} for x86_64-linux", REDIS));
+                assert_eq!(signer, "core-20180119235000");
+            }
+            other => panic!("Expected UntrustedSigner, got {:?}", other.map(|_| ())),
+        }
+        let (ident, target) = redis();
+        let artifact = task.downloaded_artifact_path(&ident, target);
+        assert!(!artifact.exists());
+
+        write_cached_artifact(fx.path(), "core-20180119235000");
+        let task = fx.download(MockClient::with_package(REDIS))
+                     .trusted_origin("core")
+                     .build(&mut fx.ui)
+                     .unwrap();
+        task.check_signer_trusted(&ident, target, &artifact, "core-20180119235000")
+            .unwrap();
+        assert!(artifact.is_file());
+    }
+
+    #[test]
+    fn an_artifacts_signer_is_only_read_once() {
+        let mut fx = Fixture::new();
+        let task = fx.download(MockClient::empty()).build(&mut fx.ui).unwrap();
+        write_cached_artifact(fx.path(), "core-20180119235000");
+        let (ident, target) = redis();
+        let artifact = task.downloaded_artifact_path(&ident, target);
+        assert_eq!(task.artifact_signer(&artifact).unwrap(),
+                   "core-20180119235000");
+
+        write_cached_artifact(fx.path(), "core-20190101000000");
+        assert_eq!(task.artifact_signer(&artifact).unwrap(),
+                   "core-20180119235000");
+    }
+
+    #[test]
+    fn a_by_channel_layout_puts_artifacts_under_the_channel_they_were_found_in() {
+        let mut fx = Fixture::new();
+        let by_channel = fx.download(MockClient::empty())
+                           .layout(DownloadLayout::ByChannel)
+                           .channels(vec![ChannelIdent::stable(), ChannelIdent::unstable()]);
+        let task = by_channel.build(&mut fx.ui).unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let mut package = MockClient::with_package(REDIS).package.unwrap();
+        let dep = PackageIdent::from_str("core/glibc/2.27/20190115002733").unwrap();
+        package.tdeps.push(dep.clone());
+        let other = PackageIdent::from_str("core/tree/1.7.0/20180609045201").unwrap();
+
+        task.record_channel(&package, target, Some(&ChannelIdent::unstable()));
+        let unstable = fx.path().join("unstable").join("artifacts");
+        assert_eq!(task.path_for_artifact_of(&package.ident, target), unstable);
+        assert_eq!(task.path_for_artifact_of(&dep, target), unstable);
+        assert_eq!(task.path_for_artifact_of(&other, target),
+                   fx.path().join("stable").join("artifacts"));
+        assert_eq!(task.path_for_keys(), keys_path(fx.path()));
+    }
+
+    #[test]
+    fn artifacts_are_matched_on_their_checksum_rather_than_their_name() {
+        let mut fx = Fixture::new();
+        let artifacts = artifacts_path(fx.path());
+        fs::create_dir_all(&artifacts).unwrap();
+        let renamed = artifacts.join("redis.hart");
+        fs::write(&renamed,
+                  "HART-1\ncore-20180119235000\nBLAKE2b\nsignature\n\n").unwrap();
+        let mut client = MockClient::with_package(REDIS);
+        client.package.as_mut().unwrap().checksum = hash::hash_file(&renamed).unwrap();
+        let task = fx.download(client)
+                     .by_checksum(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        let (ident, target) = redis();
+        let artifact = task.downloaded_artifact_path(&ident, target);
+        fs::write(&artifact,
+                  "HART-1\nrogue-20190101000000\nBLAKE2b\nsignature\n\n").unwrap();
+
+        let idents = vec![(ident.clone(), target)].into_iter().collect();
+        task.match_by_checksum(&mut fx.ui, &idents).unwrap();
+        assert_eq!(fs::read(&artifact).unwrap(), fs::read(&renamed).unwrap());
+        assert!(task.is_cached(&ident, target));
+    }
+
+    #[test]
+    fn dependencies_are_only_expanded_as_deep_as_asked_for() {
+        let ident = |ident: &str| PackageIdent::from_str(ident).unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let glibc = ident("core/glibc/2.27/20190115002733");
+        let headers = ident("core/linux-headers/4.17.12/20190115002705");
+        let mut package = MockClient::with_package(REDIS).package.unwrap();
+        package.deps = vec![glibc.clone()];
+        package.tdeps = vec![glibc.clone(), headers.clone()];
+
+        let mut fx = Fixture::new();
+        let mut expanded = |max_depth: usize| {
+            // Builder knows glibc, which depends on the headers
+            let mut client = MockClient::with_package(&glibc.to_string());
+            client.package.as_mut().unwrap().deps = vec![headers.clone()];
+            let task = fx.download(client)
+                         .max_depth(max_depth)
+                         .build(&mut fx.ui)
+                         .unwrap();
+            task.expand_sources(&mut fx.ui,
+                                vec![(package.ident.clone(), target, package.clone())])
+                .unwrap()
+                .len()
+        };
+        assert_eq!(expanded(0), 1);
+        assert_eq!(expanded(1), 2);
+        assert_eq!(expanded(2), 3);
+    }
+
+    #[test]
+    fn the_target_is_this_hosts_unless_given() {
+        let mut fx = Fixture::new();
+        let task = DownloadBuilder::new("https://bldr.example.com", "hab", "0.0.0")
+            .api_client(Box::new(MockClient::empty()))
+            .ident(PackageIdent::from_str("core/redis").unwrap())
+            .download_path(fx.path())
+            .build(&mut fx.ui)
+            .unwrap();
+        assert_eq!(task.target, PackageTarget::active_target());
+        assert!(task.target_detected);
+
+        let task = fx.download(MockClient::empty()).build(&mut fx.ui).unwrap();
+        assert!(!task.target_detected);
+    }
+
+    #[test]
+    fn only_a_single_fully_qualified_artifact_goes_to_stdout() {
+        let mut fx = Fixture::new();
+        let unqualified = fx.download(MockClient::empty()).stdout(true);
+        assert!(unqualified.build(&mut fx.ui).is_err());
+
+        let client = MockClient::empty();
+        let fetches = Arc::clone(&client.fetches);
+        let task = DownloadBuilder::new("https://bldr.example.com", "hab", "0.0.0")
+            .api_client(Box::new(client))
+            .ident(PackageIdent::from_str(REDIS).unwrap())
+            .download_path(fx.path())
+            .stdout(true)
+            .build(&mut fx.ui)
+            .unwrap();
+        let mut out = Vec::new();
+        match task.stream_artifact(&mut out) {
+            Err(Error::HabitatCommon(CommonError::PackageNotFound(_))) => {}
+            other => panic!("Expected the artifact not to be found, got {:?}", other),
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn phases_of_their_own_are_only_timed_when_there_were_any() {
+        let mut timings = PhaseTimings { resolve_secs: 1.2,
+                                         download_secs: 30.0,
+                                         ..PhaseTimings::default() };
+        assert_eq!(phase_timings(&timings), "1.2s resolving, 30.0s downloading");
+
+        timings.key_fetch_secs = Some(0.5);
+        timings.verify_secs = Some(4.0);
+        assert_eq!(phase_timings(&timings),
+                   "1.2s resolving, 30.0s downloading, 0.5s fetching keys, 4.0s verifying");
+    }
+
+    #[test]
+    fn a_failed_run_only_removes_what_it_put_there() {
+        let mut fx = Fixture::new();
+        write_cached_artifact(fx.path(), "core-20180119235000");
+        let task = fx.download(MockClient::with_package(REDIS))
+                     .clean_on_failure(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        let (ident, target) = redis();
+        let cached = task.downloaded_artifact_path(&ident, target);
+        let downloaded =
+            artifacts_path(fx.path()).join("core-tree-1.7.0-20180609045201-x86_64-linux.hart");
+        fs::write(&downloaded, "HART-1\n").unwrap();
+        fs::write(verified::sidecar_path(&downloaded), "").unwrap();
+        task.created().push(downloaded.clone());
+
+        task.remove_created(&mut fx.ui).unwrap();
+        assert!(cached.is_file());
+        assert!(!downloaded.exists());
+        assert!(!verified::sidecar_path(&downloaded).exists());
+        // Nothing's left to remove a second time
+        assert!(task.created().is_empty());
+    }
+
+    #[test]
+    fn a_channel_the_origin_doesnt_have_fails_before_resolving() {
+        let mut fx = Fixture::new();
+        let mut client = MockClient::with_package(REDIS);
+        client.channels = Some(vec![String::from("stable"), String::from("unstable")]);
+        let task = fx.download(client)
+                     .channels(vec![ChannelIdent::from("stabel")])
+                     .build(&mut fx.ui)
+                     .unwrap();
+
+        match task.execute(&mut fx.ui) {
+            Err(Error::ChannelNotFound(channels, origin)) => {
+                assert_eq!(channels, "'stabel'");
+                assert_eq!(origin, "core");
+            }
+            other => panic!("Expected ChannelNotFound, got {:?}", other.map(|_| ())),
+        }
+        // A fallback channel that is there is enough
+        let mut client = MockClient::with_package(REDIS);
+        client.channels = Some(vec![String::from("stable")]);
+        let channels = vec![ChannelIdent::from("stabel"), ChannelIdent::stable()];
+        let task = fx.download(client)
+                     .channels(channels)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        assert!(task.check_channels_exist().is_ok());
+    }
+
+    #[test]
+    fn the_deps_of_a_package_can_be_left_out_unless_something_else_needs_them() {
+        let ident = |ident: &str| PackageIdent::from_str(ident).unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let glibc = ident("core/glibc/2.27/20190115002733");
+        let headers = ident("core/linux-headers/4.17.12/20190115002705");
+        let mut redis = MockClient::with_package(REDIS).package.unwrap();
+        redis.tdeps = vec![glibc.clone(), headers];
+        let mut tree = MockClient::with_package(REDIS).package.unwrap();
+        tree.ident = ident("core/tree/1.7.0/20180609045201");
+        tree.tdeps = vec![glibc.clone()];
+
+        let mut fx = Fixture::new();
+        let task = fx.download(MockClient::empty())
+                     .exclude_deps_of(ident("core/redis"))
+                     .build(&mut fx.ui)
+                     .unwrap();
+        let expanded = task.expand_sources(&mut fx.ui,
+                                           vec![(redis.ident.clone(), target, redis.clone()),
+                                                (tree.ident.clone(), target, tree.clone())])
+                           .unwrap();
+
+        let expected: HashSet<_> =
+            vec![(redis.ident, target), (tree.ident, target), (glibc, target)].into_iter()
+                                                                              .collect();
+        assert_eq!(expanded, expected);
+    }
+
+    #[test]
+    fn a_download_interrupted_while_resolving_stops_there() {
+        let mut fx = Fixture::new();
+        let task = fx.download(MockClient::with_package(REDIS))
+                     .ident(PackageIdent::from_str("core/tree").unwrap())
+                     .resolve_jobs(1)
+                     .execution_strategy(ExecutionStrategy::DryRun)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        task.interrupted.store(true, Ordering::SeqCst);
+
+        // A dry run never gets as far as downloading, so this can only have come from resolving
+        match task.execute(&mut fx.ui) {
+            Err(Error::Interrupted(left)) => assert_eq!(left, vec!["core/tree"]),
+            other => panic!("Expected Interrupted, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn a_forced_download_doesnt_count_what_it_overwrote_as_its_own() {
+        let mut fx = Fixture::new();
+        write_cached_artifact(fx.path(), "core-20180119235000");
+        let mut client = MockClient::with_package(REDIS);
+        client.serves_artifacts = true;
+        let task = fx.download(client).force(true).build(&mut fx.ui).unwrap();
+        let (redis, target) = redis();
+        let tree = PackageIdent::from_str("core/tree/1.7.0/20180609045201").unwrap();
+
+        task.fetch_artifact(&redis, target, None).unwrap();
+        assert!(task.created().is_empty());
+
+        task.fetch_artifact(&tree, target, None).unwrap();
+        let tree_path = task.downloaded_artifact_path(&tree, target);
+        assert_eq!(*task.created(), vec![tree_path]);
+    }
+
+    #[test]
+    fn an_artifact_under_the_wrong_name_is_linked_to_its_own_before_being_removed() {
+        let mut fx = Fixture::new();
+        let artifacts = artifacts_path(fx.path());
+        fs::create_dir_all(&artifacts).unwrap();
+        let (redis, target) = redis();
+        let mut tree = MockClient::with_package(REDIS).package.unwrap();
+        tree.ident = PackageIdent::from_str("core/tree/1.7.0/20180609045201").unwrap();
+        tree.checksum = String::from("0123456789abcdef");
+        let misplaced = artifacts.join(tree.ident.archive_name_with_target(target).unwrap());
+        fs::write(&misplaced,
+                  "HART-1\ncore-20180119235000\nBLAKE2b\nsignature\n\n").unwrap();
+        let mut client = MockClient::with_package(REDIS);
+        client.package.as_mut().unwrap().checksum = hash::hash_file(&misplaced).unwrap();
+        let task = fx.download(client)
+                     .by_checksum(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        let contents = fs::read(&misplaced).unwrap();
+        task.metadata_cache()
+            .insert((tree.ident.clone(), target, ChannelIdent::stable()),
+                    tree.clone());
+
+        let idents = vec![(redis.clone(), target), (tree.ident.clone(), target)].into_iter()
+                                                                                .collect();
+        task.match_by_checksum(&mut fx.ui, &idents).unwrap();
+        assert_eq!(fs::read(task.downloaded_artifact_path(&redis, target)).unwrap(),
+                   contents);
+        assert!(!misplaced.exists());
+    }
+
+    #[test]
+    fn direct_build_deps_count_even_when_builder_leaves_them_out_of_build_tdeps() {
+        let ident = |ident: &str| PackageIdent::from_str(ident).unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let glibc = ident("core/glibc/2.27/20190115002733");
+        let gcc = ident("core/gcc/8.2.0/20190115004042");
+        let binutils = ident("core/binutils/2.31.1/20190115003743");
+        let mut package = MockClient::with_package(REDIS).package.unwrap();
+        package.tdeps = vec![glibc.clone()];
+        package.build_deps = vec![gcc.clone()];
+        package.build_tdeps = vec![binutils.clone()];
+
+        let mut fx = Fixture::new();
+        let task = fx.download(MockClient::empty())
+                     .build_deps(true)
+                     .build(&mut fx.ui)
+                     .unwrap();
+        // What's left out for `exclude_deps_of` is exactly what's otherwise downloaded
+        assert_eq!(task.dependencies_of(&package),
+                   vec![&glibc, &gcc, &binutils]);
+        let expanded = task.expand_sources(&mut fx.ui,
+                                           vec![(package.ident.clone(), target, package.clone())])
+                           .unwrap();
+        let expected: HashSet<_> = vec![(package.ident, target),
+                                        (glibc, target),
+                                        (gcc, target),
+                                        (binutils, target)].into_iter()
+                                                           .collect();
+        assert_eq!(expanded, expected);
+    }
+}
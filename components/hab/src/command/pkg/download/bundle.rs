@@ -0,0 +1,154 @@
+//! Packs a download directory into a single tarball, for carrying a sync to a host that can't
+//! reach Builder. Inside, it's laid out just like the download directory, so unpacking it gives
+//! one back, and its `SHA256SUMS` checks everything else in it:
+//!
+//! ```text
+//! SHA256SUMS
+//! artifacts/core-redis-4.0.14-20190319155852-x86_64-linux.hart
+//! keys/core-20180119235000.pub
+//! ```
+//!
+//! A download laid out by channel keeps each channel's `<channel>/artifacts/` directory.
+
+use std::{ffi::OsStr,
+          fs::{self,
+               File},
+          io::Write,
+          path::{Path,
+                 PathBuf}};
+
+use flate2::{write::GzEncoder,
+             Compression};
+
+use super::{checksums::SHA256SUMS,
+            prune::files_with_extension};
+use crate::error::{Error,
+                   Result};
+
+/// Writes the artifacts and public keys in the download directory `root`, along with its
+/// `SHA256SUMS` if there is one, to a tarball at `dest`. The tarball is gzipped if `dest` ends in
+/// `.gz` or `.tgz`. Anything else in the directories, such as a partial download, is left out.
+///
+/// Once `interrupted` says so, no more files are added, and the unfinished tarball is removed
+/// rather than left to be mistaken for a whole one.
+pub fn write_bundle(dest: &Path,
+                    root: &Path,
+                    artifacts_dirs: &[PathBuf],
+                    keys_dir: &Path,
+                    interrupted: &dyn Fn() -> bool)
+                    -> Result<()> {
+    let written = write_tarball(dest, root, artifacts_dirs, keys_dir, interrupted);
+    if written.is_err() {
+        if let Err(e) = fs::remove_file(dest) {
+            debug!("Couldn't remove the unfinished bundle {}: {}",
+                   dest.display(),
+                   e);
+        }
+    }
+    written
+}
+
+fn write_tarball(dest: &Path,
+                 root: &Path,
+                 artifacts_dirs: &[PathBuf],
+                 keys_dir: &Path,
+                 interrupted: &dyn Fn() -> bool)
+                 -> Result<()> {
+    let file = File::create(dest)?;
+    match dest.extension().and_then(OsStr::to_str) {
+        Some("gz") | Some("tgz") => {
+            let encoder = GzEncoder::new(file, Compression::default());
+            append_all(encoder, root, artifacts_dirs, keys_dir, interrupted)?.finish()?;
+        }
+        _ => {
+            append_all(file, root, artifacts_dirs, keys_dir, interrupted)?;
+        }
+    }
+    Ok(())
+}
+
+fn append_all<W>(writer: W,
+                 root: &Path,
+                 artifacts_dirs: &[PathBuf],
+                 keys_dir: &Path,
+                 interrupted: &dyn Fn() -> bool)
+                 -> Result<W>
+    where W: Write
+{
+    let mut tar = tar::Builder::new(writer);
+    let sums = root.join(SHA256SUMS);
+    if sums.is_file() {
+        tar.append_path_with_name(&sums, SHA256SUMS)?;
+    }
+    let mut files = Vec::new();
+    for dir in artifacts_dirs {
+        files.extend(files_with_extension(dir, "hart")?);
+    }
+    files.extend(files_with_extension(keys_dir, "pub")?);
+    for (i, path) in files.iter().enumerate() {
+        if interrupted() {
+            let left = files[i..].iter().map(|p| p.display().to_string()).collect();
+            return Err(Error::Interrupted(left));
+        }
+        let name = path.strip_prefix(root).unwrap_or(&path);
+        tar.append_path_with_name(&path, name)?;
+    }
+    Ok(tar.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::{fs,
+              path::PathBuf};
+    use tempfile::TempDir;
+
+    #[test]
+    fn bundles_keep_the_download_layout() {
+        let root = TempDir::new().unwrap();
+        let artifacts = root.path().join("artifacts");
+        let keys = root.path().join("keys");
+        fs::create_dir_all(&artifacts).unwrap();
+        fs::create_dir_all(&keys).unwrap();
+        let hart = "artifacts/core-redis-4.0.14-20190319155852-x86_64-linux.hart";
+        let partial = "artifacts/core-redis-4.0.15-20190901000000-x86_64-linux.hart.part";
+        let key = "keys/core-20180119235000.pub";
+        for name in &[hart, partial, key, SHA256SUMS] {
+            fs::write(root.path().join(name), "").unwrap();
+        }
+
+        let out = TempDir::new().unwrap();
+        let dest = out.path().join("sync.tar.gz");
+        write_bundle(&dest, root.path(), &[artifacts], &keys, &|| false).unwrap();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(&dest).unwrap()));
+        let names: Vec<PathBuf> = archive.entries()
+                                         .unwrap()
+                                         .map(|e| e.unwrap().path().unwrap().into_owned())
+                                         .collect();
+        assert_eq!(names,
+                   vec![PathBuf::from(SHA256SUMS),
+                        PathBuf::from(hart),
+                        PathBuf::from(key)]);
+    }
+
+    #[test]
+    fn an_interrupted_bundle_is_removed() {
+        let root = TempDir::new().unwrap();
+        let artifacts = root.path().join("artifacts");
+        let keys = root.path().join("keys");
+        fs::create_dir_all(&artifacts).unwrap();
+        fs::create_dir_all(&keys).unwrap();
+        let hart = artifacts.join("core-redis-4.0.14-20190319155852-x86_64-linux.hart");
+        fs::write(&hart, "").unwrap();
+
+        let out = TempDir::new().unwrap();
+        let dest = out.path().join("sync.tar");
+        match write_bundle(&dest, root.path(), &[artifacts], &keys, &|| true) {
+            Err(Error::Interrupted(left)) => assert_eq!(left, vec![hart.display().to_string()]),
+            other => panic!("Expected Interrupted, got {:?}", other),
+        }
+        assert!(!dest.exists());
+    }
+}
@@ -0,0 +1,89 @@
+//! Writes a `SHA256SUMS` file for a download directory, in the format `sha256sum -c` reads, so a
+//! copy of the directory can be checked wherever it ends up:
+//!
+//! ```bash
+//! $ cd download && sha256sum -c SHA256SUMS
+//! ```
+
+use std::{fs::File,
+          io::{Read,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+use sha2::{Digest,
+           Sha256};
+
+use crate::error::Result;
+
+pub const SHA256SUMS: &str = "SHA256SUMS";
+
+/// Checksums each of `files`, all somewhere under `root`, and writes them to `root/SHA256SUMS`,
+/// returning the path written. Paths in the file are relative to `root`, and sorted.
+pub fn write_sha256sums(root: &Path, files: &[PathBuf]) -> Result<PathBuf> {
+    let mut sums = Vec::new();
+    for file in files {
+        let relative = file.strip_prefix(root).unwrap_or(file);
+        sums.push((relative_name(relative), sha256(file)?));
+    }
+    sums.sort();
+
+    let path = root.join(SHA256SUMS);
+    let mut file = File::create(&path)?;
+    for (name, sum) in sums {
+        // Two spaces marks the file as one to be read in text mode, which is what `sha256sum`
+        // itself writes on unix
+        writeln!(file, "{}  {}", sum, name)?;
+    }
+    Ok(path)
+}
+
+/// `sha256sum` expects forward slashes, whatever platform we're on.
+fn relative_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.input(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.result()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn sha256sums_are_sorted_and_relative() {
+        let root = TempDir::new().unwrap();
+        let artifacts = root.path().join("artifacts");
+        fs::create_dir_all(&artifacts).unwrap();
+        fs::write(artifacts.join("b.hart"), "hello").unwrap();
+        fs::write(artifacts.join("a.hart"), "").unwrap();
+
+        let files = [artifacts.join("b.hart"), artifacts.join("a.hart")];
+        let path = write_sha256sums(root.path(), &files).unwrap();
+
+        let expected = vec!["e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  \
+                             artifacts/a.hart",
+                            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  \
+                             artifacts/b.hart"];
+        assert_eq!(fs::read_to_string(path).unwrap()
+                                           .lines()
+                                           .collect::<Vec<_>>(),
+                   expected);
+    }
+}
@@ -0,0 +1,144 @@
+//! The part of the Builder API a download uses. A `DownloadTask` only talks to Builder through
+//! this, so that tests can hand it something that answers from memory instead.
+
+use std::{fmt,
+          io::Write,
+          path::{Path,
+                 PathBuf}};
+
+use crate::{api_client::{BoxedClient,
+                         DisplayProgress,
+                         Package,
+                         Result},
+            hcore::{package::{PackageArchive,
+                              PackageIdent,
+                              PackageTarget},
+                    ChannelIdent}};
+
+pub trait DownloadClient: Sync + Send {
+    fn fetch_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>,
+                     dst_path: &Path,
+                     progress: Option<Box<dyn DisplayProgress>>)
+                     -> Result<PackageArchive>;
+
+    /// Writes the artifact to `out` instead of a directory, returning how many bytes it was.
+    fn stream_package(&self,
+                      ident_and_target: (&PackageIdent, PackageTarget),
+                      token: Option<&str>,
+                      out: &mut dyn Write)
+                      -> Result<u64>;
+
+    fn check_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>)
+                     -> Result<()>;
+
+    fn package_size(&self,
+                    ident_and_target: (&PackageIdent, PackageTarget),
+                    token: Option<&str>)
+                    -> Result<Option<u64>>;
+
+    fn show_package_metadata(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Package>;
+
+    /// Every release of a package that's in `channel`, in no particular order.
+    fn list_package_releases(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Vec<PackageIdent>>;
+
+    fn list_origin_packages(&self, origin: &str, token: Option<&str>) -> Result<Vec<PackageIdent>>;
+
+    /// The channels in `origin`, including sandbox channels, for clients that can list them.
+    fn list_channels(&self, _origin: &str) -> Result<Option<Vec<String>>> { Ok(None) }
+
+    fn fetch_origin_key(&self,
+                        origin: &str,
+                        revision: &str,
+                        token: Option<&str>,
+                        dst_path: &Path,
+                        progress: Option<Box<dyn DisplayProgress>>)
+                        -> Result<PathBuf>;
+
+    /// The URL of the Builder being talked to, for clients that can say which one that is
+    /// right now.
+    fn endpoint(&self) -> Option<&str> { None }
+}
+
+// So that a builder holding one can still be debug-printed
+impl fmt::Debug for dyn DownloadClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str("DownloadClient") }
+}
+
+impl DownloadClient for BoxedClient {
+    fn fetch_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>,
+                     dst_path: &Path,
+                     progress: Option<Box<dyn DisplayProgress>>)
+                     -> Result<PackageArchive> {
+        (**self).fetch_package(ident_and_target, token, dst_path, progress)
+    }
+
+    fn stream_package(&self,
+                      ident_and_target: (&PackageIdent, PackageTarget),
+                      token: Option<&str>,
+                      out: &mut dyn Write)
+                      -> Result<u64> {
+        (**self).stream_package(ident_and_target, token, out)
+    }
+
+    fn check_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>)
+                     -> Result<()> {
+        (**self).check_package(ident_and_target, token)
+    }
+
+    fn package_size(&self,
+                    ident_and_target: (&PackageIdent, PackageTarget),
+                    token: Option<&str>)
+                    -> Result<Option<u64>> {
+        (**self).package_size(ident_and_target, token)
+    }
+
+    fn show_package_metadata(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Package> {
+        (**self).show_package_metadata(ident_and_target, channel, token)
+    }
+
+    fn list_package_releases(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Vec<PackageIdent>> {
+        (**self).list_package_releases(ident_and_target, channel, token)
+    }
+
+    fn list_origin_packages(&self, origin: &str, token: Option<&str>) -> Result<Vec<PackageIdent>> {
+        (**self).list_origin_packages(origin, token)
+    }
+
+    fn list_channels(&self, origin: &str) -> Result<Option<Vec<String>>> {
+        (**self).list_channels(origin, true).map(Some)
+    }
+
+    fn fetch_origin_key(&self,
+                        origin: &str,
+                        revision: &str,
+                        token: Option<&str>,
+                        dst_path: &Path,
+                        progress: Option<Box<dyn DisplayProgress>>)
+                        -> Result<PathBuf> {
+        (**self).fetch_origin_key(origin, revision, token, dst_path, progress)
+    }
+}
@@ -0,0 +1,183 @@
+//! What happens to each ident and artifact over the course of a download, for tools that want
+//! more than terminal text. Code running a download registers a `DownloadObserver` to hear about
+//! it, and `hab pkg download --progress-format ndjson` writes each event to stderr as one JSON
+//! object per line:
+//!
+//! ```json
+//! {"event":"downloading","phase":"download","ident":"core/redis/4.0.14/20190319155852","target":"x86_64-linux"}
+//! ```
+
+use std::{fmt,
+          io::{self,
+               Write}};
+
+use crate::hcore::{package::{PackageIdent,
+                             PackageTarget},
+                   util::serde_string};
+
+/// The part of the download an event happened in.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Resolve,
+    Download,
+    Verify,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    /// A package ident is being resolved to the latest release in a channel
+    Resolving,
+    /// A package ident resolved to this fully qualified one
+    Resolved,
+    Downloading,
+    /// The artifact was already in the download directory
+    CacheHit,
+    /// The public key the artifact is signed with is being fetched
+    KeyFetch,
+    Verified,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProgressEvent<'a> {
+    pub event:   EventKind,
+    pub phase:   Phase,
+    #[serde(with = "serde_string")]
+    pub ident:   &'a PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target:  PackageTarget,
+    /// What went wrong, for errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl<'a> ProgressEvent<'a> {
+    pub fn new(event: EventKind,
+               phase: Phase,
+               ident: &'a PackageIdent,
+               target: PackageTarget)
+               -> Self {
+        ProgressEvent { event,
+                        phase,
+                        ident,
+                        target,
+                        message: None }
+    }
+
+    pub fn error(phase: Phase,
+                 ident: &'a PackageIdent,
+                 target: PackageTarget,
+                 message: String)
+                 -> Self {
+        ProgressEvent { message: Some(message),
+                        ..Self::new(EventKind::Error, phase, ident, target) }
+    }
+
+    /// Writes the event to stderr as a single line. Events can come from any download thread, so
+    /// the line is written while holding the lock. Not being able to report progress is no
+    /// reason to fail the download, so errors are only logged.
+    pub fn emit(&self) {
+        let result = serde_json::to_string(self).map_err(io::Error::from)
+                                                .and_then(|line| {
+                                                    let stderr = io::stderr();
+                                                    let mut stderr = stderr.lock();
+                                                    writeln!(stderr, "{}", line)
+                                                });
+        if let Err(e) = result {
+            debug!("Couldn't write progress event {:?}: {}", self, e);
+        }
+    }
+}
+
+/// Hears about each event of a download as it happens. Every method but `event` is for one kind
+/// of event, and by default hands it on to `event`, so an observer can take each event as it
+/// comes or only the kinds it cares about. Events come from the download threads as well as the
+/// one running the download, and go to the observers in the order they were registered.
+pub trait DownloadObserver: Send + Sync {
+    fn event(&self, _event: &ProgressEvent<'_>) {}
+
+    fn resolving(&self, ident: &PackageIdent, target: PackageTarget) {
+        self.event(&ProgressEvent::new(EventKind::Resolving, Phase::Resolve, ident, target));
+    }
+
+    fn resolved(&self, ident: &PackageIdent, target: PackageTarget) {
+        self.event(&ProgressEvent::new(EventKind::Resolved, Phase::Resolve, ident, target));
+    }
+
+    fn downloading(&self, ident: &PackageIdent, target: PackageTarget) {
+        self.event(&ProgressEvent::new(EventKind::Downloading, Phase::Download, ident, target));
+    }
+
+    fn cache_hit(&self, ident: &PackageIdent, target: PackageTarget) {
+        self.event(&ProgressEvent::new(EventKind::CacheHit, Phase::Download, ident, target));
+    }
+
+    fn key_fetch(&self, ident: &PackageIdent, target: PackageTarget) {
+        self.event(&ProgressEvent::new(EventKind::KeyFetch, Phase::Download, ident, target));
+    }
+
+    fn verified(&self, ident: &PackageIdent, target: PackageTarget) {
+        self.event(&ProgressEvent::new(EventKind::Verified, Phase::Verify, ident, target));
+    }
+
+    fn failed(&self, phase: Phase, ident: &PackageIdent, target: PackageTarget, message: &str) {
+        self.event(&ProgressEvent::error(phase, ident, target, message.to_string()));
+    }
+}
+
+// So that a builder holding one can still be debug-printed
+impl fmt::Debug for dyn DownloadObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str("DownloadObserver") }
+}
+
+/// Tells `observer` about `event`, through the method for its kind.
+pub fn notify(observer: &dyn DownloadObserver, event: &ProgressEvent<'_>) {
+    let (ident, target) = (event.ident, event.target);
+    match event.event {
+        EventKind::Resolving => observer.resolving(ident, target),
+        EventKind::Resolved => observer.resolved(ident, target),
+        EventKind::Downloading => observer.downloading(ident, target),
+        EventKind::CacheHit => observer.cache_hit(ident, target),
+        EventKind::KeyFetch => observer.key_fetch(ident, target),
+        EventKind::Verified => observer.verified(ident, target),
+        EventKind::Error => {
+            let message = event.message.as_ref().map_or("", String::as_str);
+            observer.failed(event.phase, ident, target, message)
+        }
+    }
+}
+
+/// What `--progress-format ndjson` registers, to write every event to stderr.
+pub struct NdjsonEvents;
+
+impl DownloadObserver for NdjsonEvents {
+    fn event(&self, event: &ProgressEvent<'_>) { event.emit(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn event_json() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+
+        let cache_hit = ProgressEvent::new(EventKind::CacheHit, Phase::Download, &ident, target);
+        assert_eq!(serde_json::to_value(&cache_hit).unwrap(),
+                   json!({
+                       "event": "cache-hit",
+                       "phase": "download",
+                       "ident": "core/redis/4.0.14/20190319155852",
+                       "target": "x86_64-linux",
+                   }));
+
+        let error = ProgressEvent::error(Phase::Verify, &ident, target, String::from("bad"));
+        assert_eq!(serde_json::to_value(&error).unwrap()["message"],
+                   json!("bad"));
+    }
+}
@@ -0,0 +1,269 @@
+//! Spreads a download across Builders that serve the same packages, such as the replicas of an
+//! HA deployment: each request goes to the one that last answered, moving on to the next in
+//! order whenever that can't be reached. An answer, even a 404, is an answer, and is returned as
+//! it is.
+
+use std::{io::Write,
+          path::{Path,
+                 PathBuf},
+          sync::atomic::{AtomicUsize,
+                         Ordering}};
+
+use reqwest::StatusCode;
+
+use super::client::DownloadClient;
+use crate::{api_client::{self,
+                         DisplayProgress,
+                         Package},
+            hcore::{package::{PackageArchive,
+                              PackageIdent,
+                              PackageTarget},
+                    ChannelIdent}};
+
+pub struct FailoverClient {
+    /// Each Builder's URL and the client for it, in the order they're tried
+    builders: Vec<(String, Box<dyn DownloadClient>)>,
+    /// The index of the Builder that last answered
+    current:  AtomicUsize,
+}
+
+impl FailoverClient {
+    pub fn new(builders: Vec<(String, Box<dyn DownloadClient>)>) -> Self {
+        assert!(!builders.is_empty(), "A FailoverClient needs a Builder");
+        FailoverClient { builders,
+                         current: AtomicUsize::new(0) }
+    }
+
+    /// Makes the request with `request`, starting at the current Builder and going on until one
+    /// answers or every one has been tried.
+    fn call<T, F>(&self, mut request: F) -> api_client::Result<T>
+        where F: FnMut(&dyn DownloadClient) -> api_client::Result<T>
+    {
+        let first = self.current.load(Ordering::Relaxed);
+        let count = self.builders.len();
+        for tried in 0..count {
+            let index = (first + tried) % count;
+            let (ref url, ref client) = self.builders[index];
+            let result = request(client.as_ref());
+            match result {
+                Err(ref e) if tried + 1 < count && is_unreachable(e) => {
+                    warn!("Couldn't reach Builder at {}, so trying the next one: {}",
+                          url, e);
+                }
+                _ => {
+                    self.current.store(index, Ordering::Relaxed);
+                    return result;
+                }
+            }
+        }
+        unreachable!("Every Builder was tried without returning")
+    }
+}
+
+/// Whether `err` means the Builder couldn't be reached or isn't working, rather than that it
+/// answered. A 501 is the depot saying it doesn't support a platform, which any other would say
+/// too.
+fn is_unreachable(err: &api_client::Error) -> bool {
+    match *err {
+        api_client::Error::APIError(StatusCode::NOT_IMPLEMENTED, _) => false,
+        api_client::Error::APIError(status, _) => status.is_server_error(),
+        api_client::Error::ReqwestError(_) | api_client::Error::HabitatHttpClient(_) => true,
+        _ => false,
+    }
+}
+
+impl DownloadClient for FailoverClient {
+    fn fetch_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>,
+                     dst_path: &Path,
+                     progress: Option<Box<dyn DisplayProgress>>)
+                     -> api_client::Result<PackageArchive> {
+        // Only the first attempt gets to show its progress
+        let mut progress = progress;
+        self.call(|c| c.fetch_package(ident_and_target, token, dst_path, progress.take()))
+    }
+
+    // A Builder that stops partway through the artifact fails with an IO error, which isn't
+    // failed over, so that nothing's written to `out` twice
+    fn stream_package(&self,
+                      ident_and_target: (&PackageIdent, PackageTarget),
+                      token: Option<&str>,
+                      out: &mut dyn Write)
+                      -> api_client::Result<u64> {
+        self.call(|c| c.stream_package(ident_and_target, token, &mut *out))
+    }
+
+    fn check_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>)
+                     -> api_client::Result<()> {
+        self.call(|c| c.check_package(ident_and_target, token))
+    }
+
+    fn package_size(&self,
+                    ident_and_target: (&PackageIdent, PackageTarget),
+                    token: Option<&str>)
+                    -> api_client::Result<Option<u64>> {
+        self.call(|c| c.package_size(ident_and_target, token))
+    }
+
+    fn show_package_metadata(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> api_client::Result<Package> {
+        self.call(|c| c.show_package_metadata(ident_and_target, channel, token))
+    }
+
+    fn list_package_releases(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> api_client::Result<Vec<PackageIdent>> {
+        self.call(|c| c.list_package_releases(ident_and_target, channel, token))
+    }
+
+    fn list_origin_packages(&self,
+                            origin: &str,
+                            token: Option<&str>)
+                            -> api_client::Result<Vec<PackageIdent>> {
+        self.call(|c| c.list_origin_packages(origin, token))
+    }
+
+    fn list_channels(&self, origin: &str) -> api_client::Result<Option<Vec<String>>> {
+        self.call(|c| c.list_channels(origin))
+    }
+
+    fn fetch_origin_key(&self,
+                        origin: &str,
+                        revision: &str,
+                        token: Option<&str>,
+                        dst_path: &Path,
+                        progress: Option<Box<dyn DisplayProgress>>)
+                        -> api_client::Result<PathBuf> {
+        let mut progress = progress;
+        self.call(|c| c.fetch_origin_key(origin, revision, token, dst_path, progress.take()))
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        Some(&self.builders[self.current.load(Ordering::Relaxed)].0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{str::FromStr,
+              sync::Arc};
+
+    /// Answers every request with `status`, counting them.
+    struct Failing {
+        status: StatusCode,
+        calls:  Arc<AtomicUsize>,
+    }
+
+    impl Failing {
+        fn fail<T>(&self) -> api_client::Result<T> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Err(api_client::Error::APIError(self.status, String::new()))
+        }
+    }
+
+    impl DownloadClient for Failing {
+        fn fetch_package(&self,
+                         _: (&PackageIdent, PackageTarget),
+                         _: Option<&str>,
+                         _: &Path,
+                         _: Option<Box<dyn DisplayProgress>>)
+                         -> api_client::Result<PackageArchive> {
+            self.fail()
+        }
+
+        fn stream_package(&self,
+                          _: (&PackageIdent, PackageTarget),
+                          _: Option<&str>,
+                          _: &mut dyn Write)
+                          -> api_client::Result<u64> {
+            self.fail()
+        }
+
+        fn check_package(&self,
+                         _: (&PackageIdent, PackageTarget),
+                         _: Option<&str>)
+                         -> api_client::Result<()> {
+            self.fail()
+        }
+
+        fn package_size(&self,
+                        _: (&PackageIdent, PackageTarget),
+                        _: Option<&str>)
+                        -> api_client::Result<Option<u64>> {
+            self.fail()
+        }
+
+        fn show_package_metadata(&self,
+                                 _: (&PackageIdent, PackageTarget),
+                                 _: &ChannelIdent,
+                                 _: Option<&str>)
+                                 -> api_client::Result<Package> {
+            self.fail()
+        }
+
+        fn list_package_releases(&self,
+                                 _: (&PackageIdent, PackageTarget),
+                                 _: &ChannelIdent,
+                                 _: Option<&str>)
+                                 -> api_client::Result<Vec<PackageIdent>> {
+            self.fail()
+        }
+
+        fn list_origin_packages(&self,
+                                _: &str,
+                                _: Option<&str>)
+                                -> api_client::Result<Vec<PackageIdent>> {
+            self.fail()
+        }
+
+        fn fetch_origin_key(&self,
+                            _: &str,
+                            _: &str,
+                            _: Option<&str>,
+                            _: &Path,
+                            _: Option<Box<dyn DisplayProgress>>)
+                            -> api_client::Result<PathBuf> {
+            self.fail()
+        }
+    }
+
+    fn builder(url: &str,
+               status: StatusCode,
+               calls: &Arc<AtomicUsize>)
+               -> (String, Box<dyn DownloadClient>) {
+        (url.to_string(),
+         Box::new(Failing { status,
+                            calls: Arc::clone(calls) }))
+    }
+
+    #[test]
+    fn only_unreachable_builders_are_failed_over() {
+        let primary = Arc::new(AtomicUsize::new(0));
+        let secondary = Arc::new(AtomicUsize::new(0));
+        let client =
+            FailoverClient::new(vec![builder("https://a", StatusCode::BAD_GATEWAY, &primary),
+                                     builder("https://b", StatusCode::NOT_FOUND, &secondary)]);
+        let ident = PackageIdent::from_str("core/redis").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+
+        match client.check_package((&ident, target), None) {
+            Err(api_client::Error::APIError(StatusCode::NOT_FOUND, _)) => {}
+            other => panic!("Expected the secondary's 404, got {:?}", other),
+        }
+        assert_eq!(client.endpoint(), Some("https://b"));
+
+        // The secondary answered, so it's asked first from now on
+        assert!(client.check_package((&ident, target), None).is_err());
+        assert_eq!(primary.load(Ordering::Relaxed), 1);
+        assert_eq!(secondary.load(Ordering::Relaxed), 2);
+    }
+}
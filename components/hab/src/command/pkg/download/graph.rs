@@ -0,0 +1,127 @@
+//! The dependency graph written by `hab pkg download --graph`: what each requested package
+//! resolved to, and the dependencies that brought along. Builder only has the direct and
+//! transitive dependencies of each release, so that's all the graph has; in the DOT form the
+//! transitive ones are dashed.
+//!
+//! ```text
+//! digraph dependencies {
+//!     "core/redis/4.0.14/20190319155852" [label="core/redis/4.0.14/20190319155852\nx86_64-linux"];
+//!     "core/redis/4.0.14/20190319155852" -> "core/glibc/2.27/20190115002733";
+//!     "core/redis/4.0.14/20190319155852" -> "core/linux-headers/4.17.12/20190115002705" [style=dashed];
+//! }
+//! ```
+
+use std::{ffi::OsStr,
+          fs::File,
+          io::{BufWriter,
+               Write},
+          path::Path};
+
+use crate::{api_client::Package,
+            hcore::{package::{PackageIdent,
+                              PackageTarget},
+                    util::serde_string}};
+
+use crate::error::Result;
+
+#[derive(Debug, Serialize)]
+struct GraphNode<'a> {
+    /// The ident as it was requested
+    #[serde(with = "serde_string")]
+    ident:    &'a PackageIdent,
+    #[serde(with = "serde_string")]
+    resolved: &'a PackageIdent,
+    #[serde(with = "serde_string")]
+    target:   PackageTarget,
+    deps:     Vec<String>,
+    tdeps:    Vec<String>,
+}
+
+/// Writes the graph of every resolved package to `dest`, as JSON if it ends in `.json` and as
+/// Graphviz DOT otherwise.
+pub fn write_graph(dest: &Path, resolved: &[(PackageIdent, PackageTarget, Package)]) -> Result<()> {
+    let mut out = BufWriter::new(File::create(dest)?);
+    match dest.extension().and_then(OsStr::to_str) {
+        Some("json") => write_json(&mut out, resolved)?,
+        _ => write_dot(&mut out, resolved)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_json<W>(out: &mut W, resolved: &[(PackageIdent, PackageTarget, Package)]) -> Result<()>
+    where W: Write
+{
+    let nodes: Vec<GraphNode<'_>> = resolved.iter()
+                                            .map(|(ident, target, package)| {
+                                                GraphNode { ident,
+                                                            resolved: &package.ident,
+                                                            target: *target,
+                                                            deps: sorted(&package.deps),
+                                                            tdeps: sorted(&package.tdeps) }
+                                            })
+                                            .collect();
+    serde_json::to_writer_pretty(out, &nodes)?;
+    Ok(())
+}
+
+fn write_dot<W>(out: &mut W, resolved: &[(PackageIdent, PackageTarget, Package)]) -> Result<()>
+    where W: Write
+{
+    writeln!(out, "digraph dependencies {{")?;
+    for (_, target, package) in resolved {
+        writeln!(out,
+                 "    \"{}\" [label=\"{}\\n{}\"];",
+                 package.ident, package.ident, target)?;
+        for dep in sorted(&package.tdeps) {
+            let style = if package.deps.iter().any(|d| d.to_string() == dep) {
+                ""
+            } else {
+                " [style=dashed]"
+            };
+            writeln!(out, "    \"{}\" -> \"{}\"{};", package.ident, dep, style)?;
+        }
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn sorted(idents: &[PackageIdent]) -> Vec<String> {
+    let mut idents: Vec<String> = idents.iter().map(ToString::to_string).collect();
+    idents.sort();
+    idents.dedup();
+    idents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn transitive_deps_are_dashed() {
+        let ident = |ident: &str| PackageIdent::from_str(ident).unwrap();
+        let glibc = ident("core/glibc/2.27/20190115002733");
+        let headers = ident("core/linux-headers/4.17.12/20190115002705");
+        let package = Package { ident:       ident("core/redis/4.0.14/20190319155852"),
+                                checksum:    String::new(),
+                                manifest:    String::new(),
+                                deps:        vec![glibc.clone()],
+                                tdeps:       vec![headers, glibc],
+                                build_deps:  Vec::new(),
+                                build_tdeps: Vec::new(),
+                                exposes:     Vec::new(),
+                                config:      String::new(), };
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+
+        let mut dot = Vec::new();
+        write_dot(&mut dot, &[(ident("core/redis"), target, package)]).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        let edges: Vec<&str> = dot.lines().filter(|l| l.contains("->")).collect();
+        assert_eq!(edges,
+                   vec!["    \"core/redis/4.0.14/20190319155852\" -> \
+                         \"core/glibc/2.27/20190115002733\";",
+                        "    \"core/redis/4.0.14/20190319155852\" -> \
+                         \"core/linux-headers/4.17.12/20190115002705\" [style=dashed];"]);
+    }
+}
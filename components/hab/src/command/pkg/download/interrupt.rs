@@ -0,0 +1,70 @@
+//! Lets Ctrl-C, or a SIGTERM, stop a download at the next point where nothing is left
+//! half-done, instead of killing it outright. Only those two signals are trapped, and only while
+//! the `Trap` is held: dropping it puts back whatever handled them before, and every other
+//! signal does what it would have anyway.
+
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool,
+                        Ordering};
+
+#[cfg(windows)]
+use std::sync::Once;
+
+#[cfg(windows)]
+use crate::hcore::os::signals;
+
+#[cfg(unix)]
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(windows)]
+static INIT: Once = Once::new();
+
+/// The signals a download can be stopped with
+#[cfg(unix)]
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+pub struct Trap {
+    /// What handled each of `SIGNALS` before
+    #[cfg(unix)]
+    previous: Vec<(libc::c_int, libc::sighandler_t)>,
+}
+
+#[cfg(unix)]
+extern "C" fn handle_interrupt(_signal: libc::c_int) { INTERRUPTED.store(true, Ordering::SeqCst); }
+
+/// Traps Ctrl-C and SIGTERM until the returned `Trap` is dropped.
+#[cfg(unix)]
+pub fn trap() -> Trap {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let handler = handle_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    let previous = SIGNALS.iter()
+                          .map(|&signal| (signal, unsafe { libc::signal(signal, handler) }))
+                          .collect();
+    Trap { previous }
+}
+
+/// Traps Ctrl-C. The console handler can't be taken back again, so it stays for the rest of the
+/// process, which for `hab` ends with the download anyway.
+#[cfg(windows)]
+pub fn trap() -> Trap {
+    INIT.call_once(signals::init);
+    Trap {}
+}
+
+/// Whether Ctrl-C or a SIGTERM has arrived since the trap was set.
+#[cfg(unix)]
+pub fn interrupted() -> bool { INTERRUPTED.load(Ordering::SeqCst) }
+
+#[cfg(windows)]
+pub fn interrupted() -> bool { signals::check_for_shutdown() }
+
+#[cfg(unix)]
+impl Drop for Trap {
+    fn drop(&mut self) {
+        for &(signal, handler) in &self.previous {
+            unsafe {
+                libc::signal(signal, handler);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! What `hab pkg download --json` prints in place of its usual output: nothing on stdout until the
+//! end, when the whole report is printed as one JSON document along with its totals, and each
+//! warning or error as a line of JSON on stderr as it happens:
+//!
+//! ```json
+//! {"level":"warning","message":"Ignoring duplicate package ident core/redis"}
+//! ```
+
+use std::{fmt,
+          io::{self,
+               Write},
+          time::Duration};
+
+use termcolor::{ColorChoice,
+                WriteColor};
+
+use super::{report::DownloadReport,
+            ArtifactSource};
+use crate::{api_client::DisplayProgress,
+            error::Result};
+
+use habitat_common::ui::{ConsoleProgressBar,
+                         OutputStream,
+                         UIWriter,
+                         WriteStream};
+
+#[derive(Serialize)]
+struct Document<'a> {
+    #[serde(flatten)]
+    report:  &'a DownloadReport,
+    summary: Summary,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    artifacts:         usize,
+    /// Already in the download directory
+    cached:            usize,
+    from_local_cache:  usize,
+    downloaded:        usize,
+    skipped:           usize,
+    failed:            usize,
+    bytes_transferred: u64,
+    /// Of the artifacts, the fraction that didn't have to be fetched from Builder
+    cache_hit_ratio:   f64,
+    keys_downloaded:   usize,
+    elapsed_secs:      f64,
+}
+
+impl Summary {
+    fn new(report: &DownloadReport, elapsed: Duration) -> Self {
+        let cached = report.artifacts_from(ArtifactSource::Cache);
+        let from_local_cache = report.artifacts_from(ArtifactSource::LocalCache);
+        Summary { artifacts: report.artifacts.len(),
+                  cached,
+                  from_local_cache,
+                  downloaded: report.artifacts.len() - cached - from_local_cache,
+                  skipped: report.skipped.len(),
+                  failed: report.failed.len(),
+                  bytes_transferred: report.bytes_transferred(),
+                  cache_hit_ratio: report.cache_hit_ratio().unwrap_or(0.0),
+                  keys_downloaded: report.keys_downloaded,
+                  elapsed_secs: elapsed.as_secs_f64() }
+    }
+}
+
+/// Prints `report` and its totals to stdout as a single JSON document.
+pub fn print_document(report: &mut DownloadReport, elapsed: Duration) -> Result<()> {
+    report.sort();
+    let document = Document { summary: Summary::new(report, elapsed),
+                              report };
+    let raw = serde_json::to_string_pretty(&document)?;
+    let stdout = io::stdout();
+    writeln!(stdout.lock(), "{}", raw)?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Message {
+    level:   &'static str,
+    message: String,
+}
+
+/// A `UIWriter` that throws away everything but warnings and errors, which it writes to stderr
+/// as JSON.
+pub struct JsonUi {
+    out: OutputStream,
+    err: OutputStream,
+}
+
+impl Default for JsonUi {
+    fn default() -> Self {
+        JsonUi { out: sink(),
+                 err: sink(), }
+    }
+}
+
+fn sink() -> OutputStream {
+    OutputStream::new(WriteStream::from_write(|| Box::new(io::sink())),
+                      ColorChoice::Never,
+                      false)
+}
+
+fn emit(level: &'static str, message: String) -> io::Result<()> {
+    let line = serde_json::to_string(&Message { level, message })?;
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+    writeln!(stderr, "{}", line)
+}
+
+impl UIWriter for JsonUi {
+    type ProgressBar = ConsoleProgressBar;
+
+    fn out(&mut self) -> &mut dyn WriteColor { &mut self.out }
+
+    fn err(&mut self) -> &mut dyn WriteColor { &mut self.err }
+
+    fn is_out_a_terminal(&self) -> bool { false }
+
+    fn is_err_a_terminal(&self) -> bool { false }
+
+    fn progress(&self) -> Option<Box<dyn DisplayProgress>> { None }
+
+    fn warn<T>(&mut self, message: T) -> io::Result<()>
+        where T: fmt::Display
+    {
+        emit("warning", message.to_string())
+    }
+
+    fn fatal<T>(&mut self, message: T) -> io::Result<()>
+        where T: fmt::Display
+    {
+        emit("error", message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn the_document_has_the_report_and_its_totals() {
+        let mut report = DownloadReport::default();
+        report.keys_downloaded = 2;
+        let document = Document { summary: Summary::new(&report, Duration::from_secs(3)),
+                                  report:  &report, };
+
+        assert_eq!(serde_json::to_value(&document).unwrap(),
+                   json!({
+                       "resolved": [],
+                       "artifacts": [],
+                       "skipped": [],
+                       "failed": [],
+                       "keys_downloaded": 2,
+                       "bytes_saved": 0,
+                       "timings": {
+                           "resolve_secs": 0.0,
+                           "download_secs": 0.0
+                       },
+                       "summary": {
+                           "artifacts": 0,
+                           "cached": 0,
+                           "from_local_cache": 0,
+                           "downloaded": 0,
+                           "skipped": 0,
+                           "failed": 0,
+                           "bytes_transferred": 0,
+                           "cache_hit_ratio": 0.0,
+                           "keys_downloaded": 2,
+                           "elapsed_secs": 3.0
+                       }
+                   }));
+    }
+}
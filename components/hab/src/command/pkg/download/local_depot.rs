@@ -0,0 +1,242 @@
+//! A `DownloadClient` that answers from a directory instead of Builder, for resolving and
+//! downloading on a host that can't reach one at all. The directory is an export laid out like a
+//! download directory, in either layout: the artifacts in `artifacts/` and public keys in
+//! `keys/`, or all of them side by side. Everything about a package comes from its artifact, so
+//! there are no channels; every artifact is in all of them. It can be given as a `file://` URL
+//! in place of Builder's.
+
+use std::{fs::{self,
+               File},
+          io::{self,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+use reqwest::StatusCode;
+use url::Url;
+
+use super::{client::DownloadClient,
+            prune::files_with_extension};
+use crate::{api_client::{self,
+                         DisplayProgress,
+                         Package},
+            error::Result,
+            hcore::{package::{Identifiable,
+                              PackageArchive,
+                              PackageIdent,
+                              PackageTarget},
+                    ChannelIdent}};
+
+struct Artifact {
+    ident:  PackageIdent,
+    target: PackageTarget,
+    path:   PathBuf,
+}
+
+pub struct LocalDepot {
+    /// The directory as it was given, for messages
+    name:      String,
+    artifacts: Vec<Artifact>,
+    keys_dir:  PathBuf,
+}
+
+impl LocalDepot {
+    /// Opens the export at `root`, reading the ident and target of every artifact in it.
+    pub fn open(root: &Path) -> Result<Self> {
+        let (artifacts_dir, keys_dir) = if root.join("artifacts").is_dir() {
+            (root.join("artifacts"), root.join("keys"))
+        } else {
+            (root.to_path_buf(), root.to_path_buf())
+        };
+        let mut artifacts = Vec::new();
+        for path in files_with_extension(&artifacts_dir, "hart")? {
+            let mut archive = PackageArchive::new(&path);
+            artifacts.push(Artifact { ident: archive.ident()?,
+                                      target: archive.target()?,
+                                      path });
+        }
+        debug!("Found {} artifacts in {}", artifacts.len(), root.display());
+        Ok(LocalDepot { name: root.display().to_string(),
+                        artifacts,
+                        keys_dir })
+    }
+
+    fn find(&self,
+            (ident, target): (&PackageIdent, PackageTarget))
+            -> api_client::Result<&Artifact> {
+        self.artifacts
+            .iter()
+            .find(|a| &a.ident == ident && a.target == target)
+            .ok_or_else(not_found)
+    }
+
+    /// The latest artifact for `target` that `ident` could mean.
+    fn latest(&self,
+              (ident, target): (&PackageIdent, PackageTarget))
+              -> api_client::Result<&Artifact> {
+        self.artifacts
+            .iter()
+            .filter(|a| a.target == target && a.ident.satisfies(ident))
+            .max_by(|a, b| a.ident.cmp(&b.ident))
+            .ok_or_else(not_found)
+    }
+}
+
+/// The directory a `file://` URL points at, if that's the kind of URL it is.
+pub fn path_from_url(url: &str) -> Option<PathBuf> {
+    Url::parse(url).ok()
+                   .filter(|url| url.scheme() == "file")
+                   .and_then(|url| url.to_file_path().ok())
+}
+
+fn not_found() -> api_client::Error {
+    api_client::Error::APIError(StatusCode::NOT_FOUND, String::new())
+}
+
+/// Copies `src` into the directory `dst_path`, unless it's already there.
+fn copy_into(src: &Path, dst_path: &Path) -> io::Result<PathBuf> {
+    let dest = dst_path.join(src.file_name().unwrap_or_else(|| src.as_os_str()));
+    if dest != src {
+        fs::copy(src, &dest)?;
+    }
+    Ok(dest)
+}
+
+impl DownloadClient for LocalDepot {
+    fn fetch_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     _token: Option<&str>,
+                     dst_path: &Path,
+                     _progress: Option<Box<dyn DisplayProgress>>)
+                     -> api_client::Result<PackageArchive> {
+        let artifact = self.find(ident_and_target)?;
+        let dest = copy_into(&artifact.path, dst_path)?;
+        Ok(PackageArchive::new(dest))
+    }
+
+    fn stream_package(&self,
+                      ident_and_target: (&PackageIdent, PackageTarget),
+                      _token: Option<&str>,
+                      out: &mut dyn Write)
+                      -> api_client::Result<u64> {
+        let artifact = self.find(ident_and_target)?;
+        Ok(io::copy(&mut File::open(&artifact.path)?, out)?)
+    }
+
+    fn check_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     _token: Option<&str>)
+                     -> api_client::Result<()> {
+        self.find(ident_and_target).map(|_| ())
+    }
+
+    fn package_size(&self,
+                    ident_and_target: (&PackageIdent, PackageTarget),
+                    _token: Option<&str>)
+                    -> api_client::Result<Option<u64>> {
+        let artifact = self.find(ident_and_target)?;
+        let metadata = fs::metadata(&artifact.path)?;
+        Ok(Some(metadata.len()))
+    }
+
+    fn show_package_metadata(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             _channel: &ChannelIdent,
+                             _token: Option<&str>)
+                             -> api_client::Result<Package> {
+        let artifact = self.latest(ident_and_target)?;
+        let mut archive = PackageArchive::new(&artifact.path);
+        let exposes = archive.exposes()?;
+        Ok(Package { ident:       artifact.ident.clone(),
+                     checksum:    archive.checksum()?,
+                     manifest:    archive.manifest()?,
+                     deps:        archive.deps()?,
+                     tdeps:       archive.tdeps()?,
+                     build_deps:  archive.build_deps()?,
+                     build_tdeps: archive.build_tdeps()?,
+                     exposes:     exposes.into_iter().map(u32::from).collect(),
+                     config:      archive.config()?.unwrap_or_default(), })
+    }
+
+    fn list_package_releases(&self,
+                             (ident, target): (&PackageIdent, PackageTarget),
+                             _channel: &ChannelIdent,
+                             _token: Option<&str>)
+                             -> api_client::Result<Vec<PackageIdent>> {
+        Ok(self.artifacts
+               .iter()
+               .filter(|a| a.target == target && a.ident.satisfies(ident))
+               .map(|a| a.ident.clone())
+               .collect())
+    }
+
+    fn list_origin_packages(&self,
+                            origin: &str,
+                            _token: Option<&str>)
+                            -> api_client::Result<Vec<PackageIdent>> {
+        let mut idents: Vec<PackageIdent> = self.artifacts
+                                                .iter()
+                                                .filter(|a| a.ident.origin() == origin)
+                                                .map(|a| a.ident.clone())
+                                                .collect();
+        idents.sort();
+        idents.dedup();
+        Ok(idents)
+    }
+
+    fn fetch_origin_key(&self,
+                        origin: &str,
+                        revision: &str,
+                        _token: Option<&str>,
+                        dst_path: &Path,
+                        _progress: Option<Box<dyn DisplayProgress>>)
+                        -> api_client::Result<PathBuf> {
+        let key = self.keys_dir.join(format!("{}-{}.pub", origin, revision));
+        if !key.is_file() {
+            return Err(not_found());
+        }
+        Ok(copy_into(&key, dst_path)?)
+    }
+
+    fn endpoint(&self) -> Option<&str> { Some(&self.name) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ffi::OsStr,
+              str::FromStr};
+    use tempfile::TempDir;
+
+    #[test]
+    fn keys_come_from_the_export() {
+        let export = TempDir::new().unwrap();
+        let keys = export.path().join("keys");
+        fs::create_dir_all(export.path().join("artifacts")).unwrap();
+        fs::create_dir_all(&keys).unwrap();
+        fs::write(keys.join("core-20180119235000.pub"), "SIG-PUB-1").unwrap();
+        let depot = LocalDepot::open(export.path()).unwrap();
+
+        let dest = TempDir::new().unwrap();
+        let key = depot.fetch_origin_key("core", "20180119235000", None, dest.path(), None)
+                       .unwrap();
+        assert_eq!(key.file_name(), Some(OsStr::new("core-20180119235000.pub")));
+        assert!(key.is_file());
+
+        let ident = PackageIdent::from_str("core/redis").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        match depot.check_package((&ident, target), None) {
+            Err(api_client::Error::APIError(StatusCode::NOT_FOUND, _)) => {}
+            other => panic!("Expected a 404, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn file_urls_are_depot_exports() {
+        let export = TempDir::new().unwrap();
+        let url = Url::from_file_path(export.path()).unwrap();
+        assert_eq!(path_from_url(url.as_str()),
+                   Some(export.path().to_path_buf()));
+        assert_eq!(path_from_url("https://bldr.habitat.sh"), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! The lockfile written by `hab pkg download --lockfile`.
+//!
+//! It records what every requested ident resolved to on Builder, along with the transitive
+//! dependencies of that release, so that a later download can fetch exactly the same artifacts.
+//! Entries and their dependencies are sorted so the file diffs cleanly under version control.
+//!
+//! ```toml
+//! [[package]]
+//! ident = "core/redis"
+//! resolved = "core/redis/4.0.14/20190319155852"
+//! target = "x86_64-linux"
+//! tdeps = [
+//!     "core/glibc/2.27/20190115002733",
+//!     "core/linux-headers/4.17.12/20190115002705",
+//! ]
+//! ```
+
+use std::{collections::HashSet,
+          fs::File,
+          io::Write,
+          path::Path};
+
+use crate::{api_client::Package,
+            hcore::{config::ConfigFile,
+                    package::{PackageIdent,
+                              PackageTarget},
+                    util::serde_string}};
+
+use crate::error::{Error,
+                   Result};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Lockfile {
+    #[serde(rename = "package", default)]
+    pub packages: Vec<LockedPackage>,
+}
+
+impl ConfigFile for Lockfile {
+    type Error = Error;
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LockedPackage {
+    /// The ident as it was requested
+    #[serde(with = "serde_string")]
+    pub ident:    PackageIdent,
+    /// The fully qualified ident it resolved to
+    #[serde(with = "serde_string")]
+    pub resolved: PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target:   PackageTarget,
+    #[serde(default)]
+    pub tdeps:    Vec<LockedIdent>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct LockedIdent(#[serde(with = "serde_string")] pub PackageIdent);
+
+impl Lockfile {
+    /// Builds a lockfile from each requested ident, the target it was resolved for, and the
+    /// package it resolved to.
+    pub fn new(resolved: &[(PackageIdent, PackageTarget, Package)]) -> Self {
+        let mut packages: Vec<LockedPackage> =
+            resolved.iter()
+                    .map(|(ident, target, package)| {
+                        let mut tdeps: Vec<LockedIdent> =
+                            package.tdeps.iter().cloned().map(LockedIdent).collect();
+                        tdeps.sort_by_key(|dep| dep.0.to_string());
+                        tdeps.dedup();
+                        LockedPackage { ident: ident.clone(),
+                                        resolved: package.ident.clone(),
+                                        target: *target,
+                                        tdeps }
+                    })
+                    .collect();
+        // `PackageIdent`'s ordering ignores the origin, so sort on the string form
+        packages.sort_by_key(|p| (p.ident.to_string(), p.target.to_string()));
+        packages.dedup_by(|a, b| a.ident == b.ident && a.target == b.target);
+        Lockfile { packages }
+    }
+
+    /// Every package the lockfile pins, whether requested directly or as a dependency. Leaving
+    /// out the `tdeps` gives just the packages that were requested.
+    pub fn expanded_idents(&self, tdeps: bool) -> HashSet<(PackageIdent, PackageTarget)> {
+        let mut expanded_idents = HashSet::new();
+        for package in &self.packages {
+            if tdeps {
+                for dep in &package.tdeps {
+                    expanded_idents.insert((dep.0.clone(), package.target));
+                }
+            }
+            expanded_idents.insert((package.resolved.clone(), package.target));
+        }
+        expanded_idents
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        let raw = toml::ser::to_string_pretty(self)?;
+        debug!("Lockfile toml:\n---\n{}\n---", &raw);
+        let mut file = File::create(path)?;
+        file.write_all(raw.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn package(ident: &str, tdeps: &[&str]) -> Package {
+        Package { ident:       PackageIdent::from_str(ident).unwrap(),
+                  checksum:    String::new(),
+                  manifest:    String::new(),
+                  deps:        Vec::new(),
+                  tdeps:       tdeps.iter()
+                                    .map(|d| PackageIdent::from_str(d).unwrap())
+                                    .collect(),
+                  build_deps:  Vec::new(),
+                  build_tdeps: Vec::new(),
+                  exposes:     Vec::new(),
+                  config:      String::new(), }
+    }
+
+    #[test]
+    fn lockfile_is_sorted_and_round_trips() {
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let resolved = vec![(PackageIdent::from_str("core/redis").unwrap(),
+                             target,
+                             package("core/redis/4.0.14/20190319155852",
+                                     &["core/glibc/2.27/20190115002733",
+                                       "acme/glibc/2.27/20190115002733"])),
+                            (PackageIdent::from_str("acme/zlib").unwrap(),
+                             target,
+                             package("acme/zlib/1.2.11/20190115003728", &[]))];
+
+        let lockfile = Lockfile::new(&resolved);
+        let raw = toml::ser::to_string_pretty(&lockfile).unwrap();
+        let parsed = Lockfile::from_raw(&raw).unwrap();
+
+        let idents: Vec<String> = parsed.packages
+                                        .iter()
+                                        .map(|p| p.ident.to_string())
+                                        .collect();
+        assert_eq!(idents, vec!["acme/zlib", "core/redis"]);
+        assert_eq!(parsed.packages[1].resolved.to_string(),
+                   "core/redis/4.0.14/20190319155852");
+        assert_eq!(parsed.packages[1].target, target);
+        let tdeps: Vec<String> = parsed.packages[1].tdeps
+                                                   .iter()
+                                                   .map(|d| d.0.to_string())
+                                                   .collect();
+        assert_eq!(tdeps,
+                   vec!["acme/glibc/2.27/20190115002733",
+                        "core/glibc/2.27/20190115002733"]);
+
+        assert_eq!(parsed.expanded_idents(true).len(), 4);
+        assert_eq!(parsed.expanded_idents(false).len(), 2);
+    }
+}
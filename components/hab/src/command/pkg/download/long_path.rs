@@ -0,0 +1,98 @@
+//! Keeps what goes in a deeply nested download directory within reach on Windows, where a path
+//! is limited to `MAX_PATH` characters unless it has the extended-length `\\?\` prefix. An
+//! artifact's path is the download directory, a channel with `--layout by-channel`, `artifacts`,
+//! and a file name that can be a hundred characters by itself, so a download directory that's
+//! long to begin with is given the prefix. Prefixed paths aren't normalized by Windows, so the
+//! prefix is only added to a path without any `.` or `..` in it. Everywhere else paths are used
+//! as they are.
+
+use std::path::{Path,
+                PathBuf};
+
+#[cfg(windows)]
+use std::{env,
+          path::{Component,
+                 Prefix}};
+
+/// Windows' limit on the length of a path without the prefix
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Room for what goes under the download directory: a channel, `artifacts`, and a long
+/// artifact's file name along with a sidecar's extension
+#[cfg(windows)]
+const HEADROOM: usize = 160;
+
+/// `download_path`, with the extended-length prefix if what goes under it could be too long
+/// for Windows otherwise.
+#[cfg(windows)]
+pub fn for_download(download_path: &Path) -> PathBuf {
+    if download_path.as_os_str().len() + HEADROOM < MAX_PATH {
+        return download_path.to_path_buf();
+    }
+    match extended_length(download_path) {
+        Some(path) => {
+            debug!("Using {} for download path {}, since it's long",
+                   path.display(),
+                   download_path.display());
+            path
+        }
+        None => download_path.to_path_buf(),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn for_download(download_path: &Path) -> PathBuf { download_path.to_path_buf() }
+
+/// `path` with the extended-length prefix, if it can be given one. A relative path is taken to
+/// be relative to the current directory.
+#[cfg(windows)]
+fn extended_length(path: &Path) -> Option<PathBuf> {
+    let path = env::current_dir().ok()?.join(path);
+    let mut components = path.components();
+    let mut prefixed = match components.next()? {
+        Component::Prefix(prefix) => {
+            match prefix.kind() {
+                Prefix::Disk(drive) => format!(r"\\?\{}:", drive as char),
+                Prefix::UNC(server, share) => {
+                    format!(r"\\?\UNC\{}\{}",
+                            server.to_string_lossy(),
+                            share.to_string_lossy())
+                }
+                // Already prefixed, or a device
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    for component in components {
+        match component {
+            Component::RootDir => {}
+            Component::Normal(name) => {
+                prefixed.push('\\');
+                prefixed.push_str(name.to_str()?);
+            }
+            _ => return None,
+        }
+    }
+    Some(PathBuf::from(prefixed))
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_download_paths_are_prefixed() {
+        let deep = PathBuf::from(format!(r"C:\mirror\{}", "nested\\".repeat(20)));
+        assert_eq!(for_download(&deep),
+                   PathBuf::from(format!(r"\\?\C:\mirror\{}", "nested\\".repeat(19) + "nested")));
+        assert_eq!(for_download(Path::new(r"C:\mirror")),
+                   PathBuf::from(r"C:\mirror"));
+
+        let unc = PathBuf::from(format!(r"\\server\share\{}", "nested\\".repeat(20)));
+        assert!(for_download(&unc).starts_with(r"\\?\UNC\server\share"));
+        let unnormalized = PathBuf::from(format!(r"C:\mirror\..\{}", "nested\\".repeat(20)));
+        assert_eq!(for_download(&unnormalized), unnormalized);
+    }
+}
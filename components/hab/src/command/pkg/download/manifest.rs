@@ -0,0 +1,112 @@
+//! The manifest read by `hab pkg download --manifest`, for downloads whose packages don't all come
+//! from the same channel, or aren't all for the same target. Whatever an entry leaves out comes
+//! from the command line.
+//!
+//! ```toml
+//! [[package]]
+//! ident = "core/redis"
+//!
+//! [[package]]
+//! ident = "acme/app"
+//! channel = "release-20191014"
+//! target = "x86_64-windows"
+//! ```
+
+use std::fmt;
+
+use crate::hcore::{config::ConfigFile,
+                   package::{PackageIdent,
+                             PackageTarget},
+                   util::serde_string,
+                   ChannelIdent};
+
+use crate::error::Error;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    #[serde(rename = "package", default)]
+    pub packages: Vec<DownloadSource>,
+}
+
+impl ConfigFile for Manifest {
+    type Error = Error;
+}
+
+/// A package ident to resolve, along with where to resolve it.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+pub struct DownloadSource {
+    #[serde(with = "serde_string")]
+    pub ident:         PackageIdent,
+    /// The only channel to look in, instead of `--channel` and any fallback channels
+    #[serde(default)]
+    pub channel:       Option<ChannelIdent>,
+    #[serde(default)]
+    pub target:        Option<PackageTarget>,
+    /// Whether this came from expanding an `origin/*` wildcard, rather than being asked for by
+    /// name
+    #[serde(skip)]
+    pub from_wildcard: bool,
+}
+
+impl DownloadSource {
+    /// Whether this stands for every package in its origin, as `origin/*` does.
+    pub fn is_wildcard(&self) -> bool { self.ident.name == "*" && self.ident.version.is_none() }
+}
+
+impl From<PackageIdent> for DownloadSource {
+    fn from(ident: PackageIdent) -> Self {
+        DownloadSource { ident,
+                         channel: None,
+                         target: None,
+                         from_wildcard: false }
+    }
+}
+
+impl fmt::Display for DownloadSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ident)?;
+        if let Some(ref channel) = self.channel {
+            write!(f, " from the '{}' channel", channel)?;
+        }
+        if let Some(target) = self.target {
+            write!(f, " for {}", target)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn entries_only_override_what_they_give() {
+        let raw = r#"
+            [[package]]
+            ident = "core/redis"
+
+            [[package]]
+            ident = "acme/app"
+            channel = "release-20191014"
+            target = "x86_64-windows"
+        "#;
+
+        let manifest = Manifest::from_raw(raw).unwrap();
+
+        let app = PackageIdent::from_str("acme/app").unwrap();
+        assert_eq!(manifest.packages,
+                   vec![DownloadSource::from(PackageIdent::from_str("core/redis").unwrap()),
+                        DownloadSource { channel: Some(ChannelIdent::from("release-20191014")),
+                                         target: Some(PackageTarget::from_str("x86_64-windows")
+                                                          .unwrap()),
+                                         ..DownloadSource::from(app) }]);
+    }
+
+    #[test]
+    fn only_origin_star_is_a_wildcard() {
+        let source = |ident: &str| DownloadSource::from(PackageIdent::from_str(ident).unwrap());
+        assert!(source("core/*").is_wildcard());
+        assert!(!source("core/redis").is_wildcard());
+    }
+}
@@ -0,0 +1,76 @@
+//! What `hab pkg download --with-metadata` writes next to each artifact, for tools that index a
+//! mirror without reading every artifact. The channel is the one the package was found in, or for
+//! a dependency the channel of the package that needed it, and is left out when the artifact
+//! didn't come from resolving a channel, such as from a lockfile:
+//!
+//! ```json
+//! {
+//!   "ident": "core/redis/4.0.14/20190319155852",
+//!   "target": "x86_64-linux",
+//!   "channel": "stable",
+//!   "signer": "core-20180119235000",
+//!   "checksum": "e1a1e6b1d7cbb4c2cbb8d3b7c4e51bba9da2b46e9ab6c41b35b7e6b61bd1c2d7"
+//! }
+//! ```
+
+use std::path::{Path,
+                PathBuf};
+
+use crate::{error::Result,
+            hcore::{fs::atomic_write,
+                    package::{PackageIdent,
+                              PackageTarget},
+                    util::serde_string,
+                    ChannelIdent}};
+
+#[derive(Debug, Serialize)]
+pub struct ArtifactMetadata<'a> {
+    #[serde(with = "serde_string")]
+    pub ident:    &'a PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target:   PackageTarget,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel:  Option<&'a ChannelIdent>,
+    pub signer:   &'a str,
+    pub checksum: String,
+}
+
+/// Where the metadata for the artifact at `artifact` is kept.
+pub fn sidecar_path(artifact: &Path) -> PathBuf {
+    let mut sidecar = artifact.as_os_str().to_owned();
+    sidecar.push(".json");
+    PathBuf::from(sidecar)
+}
+
+/// Writes `metadata` next to the artifact at `artifact`.
+pub fn write(artifact: &Path, metadata: &ArtifactMetadata<'_>) -> Result<()> {
+    let json = serde_json::to_string_pretty(metadata)?;
+    atomic_write(&sidecar_path(artifact), json)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn artifacts_without_a_channel_leave_it_out() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let metadata = ArtifactMetadata { ident: &ident,
+                                          target,
+                                          channel: None,
+                                          signer: "core-20180119235000",
+                                          checksum: String::from("e1a1e6b1") };
+
+        assert_eq!(serde_json::to_value(&metadata).unwrap(),
+                   json!({
+                       "ident": "core/redis/4.0.14/20190319155852",
+                       "target": "x86_64-linux",
+                       "signer": "core-20180119235000",
+                       "checksum": "e1a1e6b1"
+                   }));
+    }
+}
@@ -0,0 +1,74 @@
+//! The single bar `hab pkg download --overall-progress` shows for the whole download, in place of
+//! one for each artifact:
+//!
+//! ```text
+//! artifact 47/200 1.20 GB / 3.40 GB [=========>-------------------] 35.29 % 12.05 MB/s 2m
+//! ```
+//!
+//! The bytes are those of the artifacts fetched from Builder, so the bar counts them when the
+//! size of every artifact to be fetched is known, and counts artifacts otherwise. Before that,
+//! while the package identifiers are resolved, a bar counts those:
+//!
+//! ```text
+//! ident 12/40 [=======>------------------------------] 30.00 % 4.20/s 6s
+//! ```
+
+use std::io::Stdout;
+
+use pbr::{ProgressBar,
+          Units};
+
+pub struct OverallProgress {
+    bar:   ProgressBar<Stdout>,
+    /// What's being counted, for the message
+    what:  &'static str,
+    total: usize,
+    /// The bytes expected from Builder, if the bar is counting them
+    bytes: Option<u64>,
+}
+
+impl OverallProgress {
+    /// For a download of `artifacts` artifacts, which are expected to need `bytes` from Builder
+    /// if that's known.
+    pub fn new(artifacts: usize, bytes: Option<u64>) -> Self {
+        Self::counting("artifact", artifacts, bytes)
+    }
+
+    /// For resolving `idents` package identifiers.
+    pub fn resolving(idents: usize) -> Self { Self::counting("ident", idents, None) }
+
+    fn counting(what: &'static str, total: usize, bytes: Option<u64>) -> Self {
+        // A bar with nothing to count can't say how far along it is
+        let bytes = bytes.filter(|&b| b > 0);
+        let mut bar = match bytes {
+            Some(bytes) => {
+                let mut bar = ProgressBar::new(bytes);
+                bar.set_units(Units::Bytes);
+                bar
+            }
+            None => ProgressBar::new(total as u64),
+        };
+        bar.show_tick = true;
+        let mut progress = OverallProgress { bar,
+                                             what,
+                                             total,
+                                             bytes };
+        progress.update(0, 0);
+        progress
+    }
+
+    /// Moves the bar on to `done` of what it's counting dealt with, and `transferred` bytes
+    /// fetched.
+    pub fn update(&mut self, done: usize, transferred: u64) {
+        self.bar
+            .message(&format!("{} {}/{} ", self.what, done, self.total));
+        match self.bytes {
+            // Builder's sizes are only what it expects, and a download that had to start over
+            // fetches some bytes twice, so this can go past them
+            Some(bytes) => self.bar.set(transferred.min(bytes)),
+            None => self.bar.set(done as u64),
+        };
+    }
+
+    pub fn finish(&mut self) { self.bar.finish_println(""); }
+}
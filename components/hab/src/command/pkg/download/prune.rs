@@ -0,0 +1,93 @@
+//! Finds the files in a download directory that the current resolution no longer wants, so that
+//! running a sync again after packages are demoted doesn't keep their artifacts around forever.
+
+use std::{collections::HashSet,
+          ffi::OsStr,
+          fs,
+          path::{Path,
+                 PathBuf}};
+
+use crate::error::Result;
+
+/// The files directly in `dir` with the given extension, sorted. A `dir` that doesn't exist yet
+/// has none.
+pub fn files_with_extension(dir: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().and_then(OsStr::to_str) == Some(extension) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where the file called `name` is among `dirs`, if it's in any of them.
+pub fn find_file(dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The artifacts in `artifacts_dir` that aren't among the `wanted` paths. Anything that isn't a
+/// `.hart`, such as a partial download, is left alone.
+pub fn stale_artifacts(artifacts_dir: &Path, wanted: &HashSet<PathBuf>) -> Result<Vec<PathBuf>> {
+    Ok(files_with_extension(artifacts_dir, "hart")?.into_iter()
+                                                   .filter(|path| !wanted.contains(path))
+                                                   .collect())
+}
+
+/// The public keys in `keys_dir` that none of the given `signers` need.
+pub fn stale_keys(keys_dir: &Path, signers: &HashSet<String>) -> Result<Vec<PathBuf>> {
+    Ok(files_with_extension(keys_dir, "pub")?.into_iter()
+                                             .filter(|path| {
+                                                 path.file_stem()
+                                                     .and_then(OsStr::to_str)
+                                                     .map_or(true, |stem| !signers.contains(stem))
+                                             })
+                                             .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn only_unwanted_harts_are_stale() {
+        let dir = TempDir::new().unwrap();
+        let wanted = dir.path()
+                        .join("core-redis-3.0.1-20190101000000-x86_64-linux.hart");
+        let demoted = dir.path()
+                         .join("core-redis-3.0.0-20180101000000-x86_64-linux.hart");
+        let partial = dir.path()
+                         .join("core-redis-3.0.2-20190201000000-x86_64-linux.hart.part");
+        for path in &[&wanted, &demoted, &partial] {
+            fs::write(path, "").unwrap();
+        }
+
+        let stale = stale_artifacts(dir.path(), &[wanted].iter().cloned().collect()).unwrap();
+
+        assert_eq!(stale, vec![demoted]);
+    }
+
+    #[test]
+    fn keys_without_a_signer_are_stale() {
+        let dir = TempDir::new().unwrap();
+        let used = dir.path().join("core-20180119235000.pub");
+        let orphaned = dir.path().join("core-20160810182414.pub");
+        fs::write(&used, "").unwrap();
+        fs::write(&orphaned, "").unwrap();
+
+        let signers = [String::from("core-20180119235000")].iter()
+                                                           .cloned()
+                                                           .collect();
+        let stale = stale_keys(dir.path(), &signers).unwrap();
+
+        assert_eq!(stale, vec![orphaned]);
+    }
+}
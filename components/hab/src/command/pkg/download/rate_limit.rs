@@ -0,0 +1,212 @@
+//! Paces the requests a download makes to Builder's API, for a Builder that limits how many it
+//! takes a second. Only the requests that resolve packages and fetch keys are paced; artifacts
+//! are left to `--max-bandwidth`, as there are few of those requests and it's their size that
+//! counts.
+
+use std::{cmp,
+          io::Write,
+          path::{Path,
+                 PathBuf},
+          sync::Mutex,
+          thread,
+          time::{Duration,
+                 Instant}};
+
+use super::client::DownloadClient;
+use crate::{api_client::{DisplayProgress,
+                         Package,
+                         Result},
+            hcore::{package::{PackageArchive,
+                              PackageIdent,
+                              PackageTarget},
+                    ChannelIdent}};
+
+pub struct RateLimitedClient {
+    inner:    Box<dyn DownloadClient>,
+    interval: Duration,
+    /// When the next request can be made
+    next:     Mutex<Instant>,
+}
+
+impl RateLimitedClient {
+    /// Makes no more than `requests_per_sec` API requests a second through `inner`, between
+    /// all the workers sharing it.
+    pub fn new(inner: Box<dyn DownloadClient>, requests_per_sec: u32) -> Self {
+        RateLimitedClient { inner,
+                            interval: Duration::from_secs(1) / cmp::max(requests_per_sec, 1),
+                            next: Mutex::new(Instant::now()) }
+    }
+
+    /// Blocks until it's this request's turn. Each request takes the next free slot, so ones
+    /// made at the same time go out one interval apart.
+    fn wait(&self) {
+        let wait = {
+            let mut next = self.next.lock().expect("Rate limit lock is poisoned");
+            let now = Instant::now();
+            let slot = cmp::max(*next, now);
+            *next = slot + self.interval;
+            slot - now
+        };
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+impl DownloadClient for RateLimitedClient {
+    fn fetch_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>,
+                     dst_path: &Path,
+                     progress: Option<Box<dyn DisplayProgress>>)
+                     -> Result<PackageArchive> {
+        self.inner
+            .fetch_package(ident_and_target, token, dst_path, progress)
+    }
+
+    fn stream_package(&self,
+                      ident_and_target: (&PackageIdent, PackageTarget),
+                      token: Option<&str>,
+                      out: &mut dyn Write)
+                      -> Result<u64> {
+        self.inner.stream_package(ident_and_target, token, out)
+    }
+
+    fn check_package(&self,
+                     ident_and_target: (&PackageIdent, PackageTarget),
+                     token: Option<&str>)
+                     -> Result<()> {
+        self.wait();
+        self.inner.check_package(ident_and_target, token)
+    }
+
+    fn package_size(&self,
+                    ident_and_target: (&PackageIdent, PackageTarget),
+                    token: Option<&str>)
+                    -> Result<Option<u64>> {
+        self.wait();
+        self.inner.package_size(ident_and_target, token)
+    }
+
+    fn show_package_metadata(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Package> {
+        self.wait();
+        self.inner
+            .show_package_metadata(ident_and_target, channel, token)
+    }
+
+    fn list_package_releases(&self,
+                             ident_and_target: (&PackageIdent, PackageTarget),
+                             channel: &ChannelIdent,
+                             token: Option<&str>)
+                             -> Result<Vec<PackageIdent>> {
+        self.wait();
+        self.inner
+            .list_package_releases(ident_and_target, channel, token)
+    }
+
+    fn list_origin_packages(&self, origin: &str, token: Option<&str>) -> Result<Vec<PackageIdent>> {
+        self.wait();
+        self.inner.list_origin_packages(origin, token)
+    }
+
+    fn list_channels(&self, origin: &str) -> Result<Option<Vec<String>>> {
+        self.wait();
+        self.inner.list_channels(origin)
+    }
+
+    fn fetch_origin_key(&self,
+                        origin: &str,
+                        revision: &str,
+                        token: Option<&str>,
+                        dst_path: &Path,
+                        progress: Option<Box<dyn DisplayProgress>>)
+                        -> Result<PathBuf> {
+        self.wait();
+        self.inner
+            .fetch_origin_key(origin, revision, token, dst_path, progress)
+    }
+
+    fn endpoint(&self) -> Option<&str> { self.inner.endpoint() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Empty;
+
+    impl DownloadClient for Empty {
+        fn fetch_package(&self,
+                         _: (&PackageIdent, PackageTarget),
+                         _: Option<&str>,
+                         _: &Path,
+                         _: Option<Box<dyn DisplayProgress>>)
+                         -> Result<PackageArchive> {
+            unimplemented!()
+        }
+
+        fn stream_package(&self,
+                          _: (&PackageIdent, PackageTarget),
+                          _: Option<&str>,
+                          _: &mut dyn Write)
+                          -> Result<u64> {
+            unimplemented!()
+        }
+
+        fn check_package(&self, _: (&PackageIdent, PackageTarget), _: Option<&str>) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn package_size(&self,
+                        _: (&PackageIdent, PackageTarget),
+                        _: Option<&str>)
+                        -> Result<Option<u64>> {
+            unimplemented!()
+        }
+
+        fn show_package_metadata(&self,
+                                 _: (&PackageIdent, PackageTarget),
+                                 _: &ChannelIdent,
+                                 _: Option<&str>)
+                                 -> Result<Package> {
+            unimplemented!()
+        }
+
+        fn list_package_releases(&self,
+                                 _: (&PackageIdent, PackageTarget),
+                                 _: &ChannelIdent,
+                                 _: Option<&str>)
+                                 -> Result<Vec<PackageIdent>> {
+            unimplemented!()
+        }
+
+        fn list_origin_packages(&self, _: &str, _: Option<&str>) -> Result<Vec<PackageIdent>> {
+            Ok(Vec::new())
+        }
+
+        fn fetch_origin_key(&self,
+                            _: &str,
+                            _: &str,
+                            _: Option<&str>,
+                            _: &Path,
+                            _: Option<Box<dyn DisplayProgress>>)
+                            -> Result<PathBuf> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn requests_go_out_an_interval_apart() {
+        let client = RateLimitedClient::new(Box::new(Empty), 20);
+        let started = Instant::now();
+        for _ in 0..5 {
+            client.list_origin_packages("core", None).unwrap();
+        }
+        // The first goes out straight away, and each after it 50ms after the one before
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+}
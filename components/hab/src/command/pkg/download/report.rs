@@ -0,0 +1,173 @@
+//! The machine-readable summary written by `hab pkg download --report-json`.
+
+use std::{fs::File,
+          io::Write,
+          path::Path};
+
+use super::ArtifactSource;
+use crate::hcore::{package::{PackageIdent,
+                             PackageTarget},
+                   util::serde_string};
+
+use crate::error::Result;
+
+#[derive(Debug, Default, Serialize)]
+pub struct DownloadReport {
+    /// What each package ident asked for resolved to. A frozen download doesn't resolve
+    /// anything, so has none.
+    pub resolved:        Vec<ResolvedIdent>,
+    pub artifacts:       Vec<ArtifactReport>,
+    /// Artifacts there was nothing to download for, because the depot doesn't support their
+    /// platform
+    pub skipped:         Vec<SkippedArtifact>,
+    /// Artifacts that couldn't be downloaded, with `--continue-on-error`
+    pub failed:          Vec<FailedArtifact>,
+    /// How many public keys were fetched from Builder, as opposed to already being on disk
+    pub keys_downloaded: usize,
+    /// The size of the artifacts that were already in the download directory or the local
+    /// cache, and so weren't fetched from Builder
+    pub bytes_saved:     u64,
+    pub timings:         PhaseTimings,
+}
+
+/// How long each phase of the download took, in seconds.
+#[derive(Debug, Default, Serialize)]
+pub struct PhaseTimings {
+    /// Resolving the package idents, and working out what's left to download
+    pub resolve_secs:   f64,
+    /// Downloading the artifacts. This includes fetching keys and verifying, unless those are
+    /// phases of their own
+    pub download_secs:  f64,
+    /// Fetching the signers' keys, as a phase of its own with `--verify-jobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_fetch_secs: Option<f64>,
+    /// Verifying the artifacts, as a phase of its own with `--verify-jobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_secs:    Option<f64>,
+}
+
+/// A package ident the download was asked for, and the release it resolved to.
+#[derive(Debug, Serialize)]
+pub struct ResolvedIdent {
+    /// As it was given, on the command line or in a manifest
+    #[serde(with = "serde_string")]
+    pub requested: PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target:    PackageTarget,
+    #[serde(with = "serde_string")]
+    pub resolved:  PackageIdent,
+}
+
+/// What happened to one artifact in the download's dependency closure.
+#[derive(Debug, Serialize)]
+pub struct ArtifactReport {
+    #[serde(with = "serde_string")]
+    pub ident:             PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target:            PackageTarget,
+    /// The artifact's file name in the download directory
+    pub artifact:          String,
+    pub source:            ArtifactSource,
+    /// The name and revision of the origin key that signed the artifact, such as
+    /// `core-20180119235000`
+    pub signer:            String,
+    /// Zero for artifacts that were already downloaded
+    pub bytes_transferred: u64,
+    /// Whether the artifact's signature was checked against the signer's key
+    pub verified:          bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedArtifact {
+    #[serde(with = "serde_string")]
+    pub ident:  PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target: PackageTarget,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedArtifact {
+    #[serde(with = "serde_string")]
+    pub ident:  PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target: PackageTarget,
+    /// What went wrong, as it was reported
+    pub error:  String,
+}
+
+impl DownloadReport {
+    /// Writes the report as JSON. Artifacts are sorted by ident, so the document doesn't depend on
+    /// the order the downloads happened to finish in.
+    pub fn write_to(&mut self, path: &Path) -> Result<()> {
+        self.sort();
+        let raw = serde_json::to_string_pretty(self)?;
+        let mut file = File::create(path)?;
+        file.write_all(raw.as_bytes())?;
+        Ok(())
+    }
+
+    /// Sorts everything in the report by ident.
+    pub fn sort(&mut self) {
+        // `PackageIdent`'s ordering ignores the origin, so sort on the string form
+        self.resolved
+            .sort_by_key(|r| (r.requested.to_string(), r.target.to_string()));
+        self.artifacts
+            .sort_by_key(|a| (a.ident.to_string(), a.target.to_string()));
+        self.skipped
+            .sort_by_key(|s| (s.ident.to_string(), s.target.to_string()));
+        self.failed
+            .sort_by_key(|f| (f.ident.to_string(), f.target.to_string()));
+    }
+
+    /// The bytes fetched from Builder for all of the artifacts together.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.artifacts.iter().map(|a| a.bytes_transferred).sum()
+    }
+
+    /// How many of the artifacts came from `source`.
+    pub fn artifacts_from(&self, source: ArtifactSource) -> usize {
+        self.artifacts.iter().filter(|a| a.source == source).count()
+    }
+
+    /// The fraction of the artifacts that didn't have to be fetched from Builder, or none if
+    /// there weren't any artifacts.
+    pub fn cache_hit_ratio(&self) -> Option<f64> {
+        if self.artifacts.is_empty() {
+            return None;
+        }
+        let hits = self.artifacts_from(ArtifactSource::Cache)
+                   + self.artifacts_from(ArtifactSource::LocalCache);
+        Some(hits as f64 / self.artifacts.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn artifact_report_json() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let report = ArtifactReport { artifact: ident.archive_name_with_target(target).unwrap(),
+                                      ident,
+                                      target,
+                                      source: ArtifactSource::Builder,
+                                      signer: String::from("core-20180119235000"),
+                                      bytes_transferred: 1024,
+                                      verified: true };
+
+        assert_eq!(serde_json::to_value(&report).unwrap(),
+                   json!({
+                       "ident": "core/redis/4.0.14/20190319155852",
+                       "target": "x86_64-linux",
+                       "artifact": "core-redis-4.0.14-20190319155852-x86_64-linux.hart",
+                       "source": "builder",
+                       "signer": "core-20180119235000",
+                       "bytes_transferred": 1024,
+                       "verified": true
+                   }));
+    }
+}
@@ -0,0 +1,180 @@
+//! The software bill of materials written by `hab pkg download --sbom`: a CycloneDX document
+//! with a component for every artifact in the download, and the licenses its plan declared. The
+//! licenses come from each artifact's manifest, as they're whatever the plan's `pkg_license` said
+//! rather than checked SPDX ids. A package without one is marked as such, for someone to follow up
+//! on:
+//!
+//! ```json
+//! {
+//!   "type": "application",
+//!   "bom-ref": "core/tree/1.7.0/20180609045201/x86_64-linux",
+//!   "group": "core",
+//!   "name": "tree",
+//!   "version": "1.7.0",
+//!   "licenses": [],
+//!   "properties": [
+//!     { "name": "habitat:release", "value": "20180609045201" },
+//!     { "name": "habitat:target", "value": "x86_64-linux" },
+//!     { "name": "habitat:license", "value": "missing" }
+//!   ]
+//! }
+//! ```
+
+use std::{fs::File,
+          io::{self,
+               BufWriter,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+use super::{prune::find_file,
+            report::DownloadReport};
+use crate::{error::Result,
+            hcore::package::{PackageArchive,
+                             PackageIdent,
+                             PackageTarget}};
+
+/// The line of a manifest that has the licenses, separated by spaces
+const LICENSE_LINE: &str = "* __License__:";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Bom {
+    bom_format:   &'static str,
+    spec_version: &'static str,
+    version:      u32,
+    components:   Vec<Component>,
+}
+
+#[derive(Debug, Serialize)]
+struct Component {
+    #[serde(rename = "type")]
+    kind:       &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref:    String,
+    group:      String,
+    name:       String,
+    version:    String,
+    licenses:   Vec<License>,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Serialize)]
+struct License {
+    license: LicenseName,
+}
+
+#[derive(Debug, Serialize)]
+struct LicenseName {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Property {
+    name:  &'static str,
+    value: String,
+}
+
+impl Component {
+    fn new(ident: &PackageIdent, target: PackageTarget, licenses: Vec<String>) -> Self {
+        let mut properties = vec![Property { name:  "habitat:release",
+                                             value: ident.release.clone().unwrap_or_default(), },
+                                  Property { name:  "habitat:target",
+                                             value: target.to_string(), }];
+        if licenses.is_empty() {
+            properties.push(Property { name:  "habitat:license",
+                                       value: String::from("missing"), });
+        }
+        Component { kind: "application",
+                    bom_ref: format!("{}/{}", ident, target),
+                    group: ident.origin.clone(),
+                    name: ident.name.clone(),
+                    version: ident.version.clone().unwrap_or_default(),
+                    licenses: licenses.into_iter()
+                                      .map(|name| License { license: LicenseName { name }, })
+                                      .collect(),
+                    properties }
+    }
+}
+
+/// Writes the SBOM for every artifact in `report` to `dest`, reading their licenses from the
+/// artifacts in `artifacts_dirs`. Returns the artifacts that don't have a license.
+pub fn write_sbom(dest: &Path,
+                  artifacts_dirs: &[PathBuf],
+                  report: &DownloadReport)
+                  -> Result<Vec<String>> {
+    let mut artifacts: Vec<_> = report.artifacts.iter().collect();
+    artifacts.sort_by_key(|a| (a.ident.to_string(), a.target.to_string()));
+    let mut unlicensed = Vec::new();
+    let mut components = Vec::with_capacity(artifacts.len());
+    for artifact in artifacts {
+        let path = find_file(artifacts_dirs, &artifact.artifact).ok_or_else(|| {
+                       io::Error::new(io::ErrorKind::NotFound,
+                                      format!("{} isn't in the download directory",
+                                              artifact.artifact))
+                   })?;
+        let mut archive = PackageArchive::new(path);
+        let licenses = licenses(&archive.manifest()?);
+        if licenses.is_empty() {
+            unlicensed.push(format!("{} for {}", artifact.ident, artifact.target));
+        }
+        components.push(Component::new(&artifact.ident, artifact.target, licenses));
+    }
+
+    let bom = Bom { bom_format: "CycloneDX",
+                    spec_version: "1.4",
+                    version: 1,
+                    components };
+    let mut out = BufWriter::new(File::create(dest)?);
+    serde_json::to_writer_pretty(&mut out, &bom)?;
+    out.flush()?;
+    Ok(unlicensed)
+}
+
+/// The licenses a manifest lists, if any.
+fn licenses(manifest: &str) -> Vec<String> {
+    manifest.lines()
+            .find(|line| line.starts_with(LICENSE_LINE))
+            .map(|line| {
+                line[LICENSE_LINE.len()..].split_whitespace()
+                                          .map(String::from)
+                                          .collect()
+            })
+            .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn licenses_come_from_the_manifest() {
+        let manifest = "# core / tree\n\n* __Version__: 1.7.0\n* __License__: gplv2+ MIT \n";
+        assert_eq!(licenses(manifest), vec!["gplv2+", "MIT"]);
+        assert!(licenses("# core / tree\n").is_empty());
+    }
+
+    #[test]
+    fn packages_without_a_license_are_marked() {
+        let ident = PackageIdent::from_str("core/tree/1.7.0/20180609045201").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let component = Component::new(&ident, target, Vec::new());
+
+        assert_eq!(serde_json::to_value(&component).unwrap(),
+                   json!({
+                       "type": "application",
+                       "bom-ref": "core/tree/1.7.0/20180609045201/x86_64-linux",
+                       "group": "core",
+                       "name": "tree",
+                       "version": "1.7.0",
+                       "licenses": [],
+                       "properties": [
+                           { "name": "habitat:release", "value": "20180609045201" },
+                           { "name": "habitat:target", "value": "x86_64-linux" },
+                           { "name": "habitat:license", "value": "missing" }
+                       ]
+                   }));
+    }
+}
@@ -0,0 +1,135 @@
+//! What `hab pkg download` leaves in the download directory after every successful run, so that
+//! a later `--incremental` run only downloads what's new since: when the last sync was, and every
+//! artifact it got.
+//!
+//! ```toml
+//! version = 1
+//! last_sync = "2019-10-14T09:30:00Z"
+//!
+//! [[artifact]]
+//! ident = "core/redis/4.0.14/20190319155852"
+//! target = "x86_64-linux"
+//! ```
+//!
+//! The `version` is read before anything else, so that a file written in some other format is
+//! ignored rather than misread.
+
+use std::{collections::HashSet,
+          fs,
+          path::Path};
+
+use chrono::{DateTime,
+             Utc};
+
+use crate::hcore::{fs::atomic_write,
+                   package::{PackageIdent,
+                             PackageTarget},
+                   util::serde_string};
+
+use crate::error::Result;
+
+pub const SYNC_STATE: &str = "sync-state.toml";
+pub const SYNC_STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SyncState {
+    pub version:   u32,
+    /// When the run that wrote this finished
+    pub last_sync: DateTime<Utc>,
+    #[serde(rename = "artifact", default)]
+    pub artifacts: Vec<SyncedArtifact>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SyncedArtifact {
+    #[serde(with = "serde_string")]
+    pub ident:  PackageIdent,
+    #[serde(with = "serde_string")]
+    pub target: PackageTarget,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl SyncState {
+    /// Records `synced` as got now, sorted so the file diffs cleanly.
+    pub fn new(synced: &HashSet<(PackageIdent, PackageTarget)>) -> Self {
+        let mut artifacts = Vec::with_capacity(synced.len());
+        for (ident, target) in synced {
+            artifacts.push(SyncedArtifact { ident:  ident.clone(),
+                                            target: *target, });
+        }
+        // `PackageIdent`'s ordering ignores the origin, so sort on the string form
+        artifacts.sort_by_key(|a| (a.ident.to_string(), a.target.to_string()));
+        SyncState { version: SYNC_STATE_VERSION,
+                    last_sync: Utc::now(),
+                    artifacts }
+    }
+
+    /// The state in the download directory `root`, or `None` if there isn't one this version of
+    /// `hab` understands.
+    pub fn read(root: &Path) -> Result<Option<Self>> {
+        let path = root.join(SYNC_STATE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let raw = fs::read_to_string(&path)?;
+        let Version { version } = toml::from_str(&raw)?;
+        if version != SYNC_STATE_VERSION {
+            debug!("Ignoring {}, whose version {} isn't {}",
+                   path.display(),
+                   version,
+                   SYNC_STATE_VERSION);
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&raw)?))
+    }
+
+    /// Every artifact the last sync got.
+    pub fn synced(&self) -> HashSet<(PackageIdent, PackageTarget)> {
+        self.artifacts
+            .iter()
+            .map(|a| (a.ident.clone(), a.target))
+            .collect()
+    }
+
+    pub fn write_to(&self, root: &Path) -> Result<()> {
+        let raw = toml::ser::to_string_pretty(self)?;
+        atomic_write(&root.join(SYNC_STATE), raw)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    #[test]
+    fn state_round_trips() {
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let idents = ["core/redis/4.0.14/20190319155852",
+                      "core/glibc/2.27/20190115002733"];
+        let artifacts: HashSet<_> = idents.iter()
+                                          .map(|i| (PackageIdent::from_str(i).unwrap(), target))
+                                          .collect();
+
+        let dir = TempDir::new().unwrap();
+        SyncState::new(&artifacts).write_to(dir.path()).unwrap();
+        let state = SyncState::read(dir.path()).unwrap().unwrap();
+        assert_eq!(state.synced(), artifacts);
+    }
+
+    #[test]
+    fn other_versions_are_ignored() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(SYNC_STATE),
+                  "version = 2\nsynced = [\"core/redis\"]\n").unwrap();
+        assert!(SyncState::read(dir.path()).unwrap().is_none());
+        let missing = dir.path().join("missing");
+        assert!(SyncState::read(&missing).unwrap().is_none());
+    }
+}
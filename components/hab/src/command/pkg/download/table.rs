@@ -0,0 +1,216 @@
+//! The table `hab pkg download` ends with, of what happened to each artifact:
+//!
+//! ```text
+//! IDENT                             TARGET        STATUS  SIZE     SIGNER
+//! core/glibc/2.27/20190115002733    x86_64-linux  OK      12.4 MB  core-20180119235000
+//! core/redis/4.0.14/20190319155852  x86_64-linux  FAIL    -        -
+//! ```
+//!
+//! The idents are cut short to fit the terminal when they would make the table too wide for it.
+
+use std::io::{self,
+              Write};
+
+use termcolor::{Color,
+                ColorSpec,
+                WriteColor};
+
+use super::report::DownloadReport;
+
+const HEADINGS: [&str; 5] = ["IDENT", "TARGET", "STATUS", "SIZE", "SIGNER"];
+const IDENT: usize = 0;
+const STATUS: usize = 2;
+/// The spaces between columns
+const GAP: usize = 2;
+/// The narrowest the ident column is cut down to
+const MIN_IDENT_WIDTH: usize = 20;
+
+#[derive(Clone, Copy)]
+enum Outcome {
+    Ok,
+    Skip,
+    Fail,
+}
+
+impl Outcome {
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Ok => "OK",
+            Outcome::Skip => "SKIP",
+            Outcome::Fail => "FAIL",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Outcome::Ok => Color::Green,
+            Outcome::Skip => Color::Yellow,
+            Outcome::Fail => Color::Red,
+        }
+    }
+}
+
+struct Row {
+    cells:   [String; 5],
+    outcome: Option<Outcome>,
+}
+
+impl Row {
+    fn new(ident: String, target: String, outcome: Outcome, size: String, signer: String) -> Self {
+        Row { cells:   [ident, target, outcome.label().to_string(), size, signer],
+              outcome: Some(outcome), }
+    }
+}
+
+/// Writes a row for each of the artifacts in `report` to `out`, sorted by ident. `size` gives the
+/// size of an artifact from its file name. The table is fitted into `width` columns if that's
+/// given, and the statuses are in color if `color` is set.
+pub fn write_table<W, F>(out: &mut W,
+                         report: &DownloadReport,
+                         size: F,
+                         width: Option<usize>,
+                         color: bool)
+                         -> io::Result<()>
+    where W: WriteColor + ?Sized,
+          F: Fn(&str) -> Option<u64>
+{
+    let mut rows = Vec::new();
+    for artifact in &report.artifacts {
+        let size = size(&artifact.artifact).map_or_else(|| String::from("-"), human_size);
+        rows.push(Row::new(artifact.ident.to_string(),
+                           artifact.target.to_string(),
+                           Outcome::Ok,
+                           size,
+                           artifact.signer.clone()));
+    }
+    for skipped in &report.skipped {
+        rows.push(Row::new(skipped.ident.to_string(),
+                           skipped.target.to_string(),
+                           Outcome::Skip,
+                           String::from("-"),
+                           String::from("-")));
+    }
+    for failed in &report.failed {
+        rows.push(Row::new(failed.ident.to_string(),
+                           failed.target.to_string(),
+                           Outcome::Fail,
+                           String::from("-"),
+                           String::from("-")));
+    }
+    rows.sort_by(|a, b| a.cells[..2].cmp(&b.cells[..2]));
+    let headings = Row { cells:   [HEADINGS[0].to_string(),
+                                   HEADINGS[1].to_string(),
+                                   HEADINGS[2].to_string(),
+                                   HEADINGS[3].to_string(),
+                                   HEADINGS[4].to_string()],
+                         outcome: None, };
+
+    let mut widths = [0; 5];
+    for row in Some(&headings).into_iter().chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(&row.cells) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    if let Some(width) = width {
+        let total = widths.iter().sum::<usize>() + GAP * (widths.len() - 1);
+        if total > width {
+            let over = total - width;
+            widths[IDENT] = widths[IDENT].saturating_sub(over).max(MIN_IDENT_WIDTH);
+        }
+    }
+
+    for row in Some(&headings).into_iter().chain(&rows) {
+        write_row(out, row, &widths, color)?;
+    }
+    Ok(())
+}
+
+fn write_row<W>(out: &mut W, row: &Row, widths: &[usize; 5], color: bool) -> io::Result<()>
+    where W: WriteColor + ?Sized
+{
+    let last = row.cells.len() - 1;
+    for (i, cell) in row.cells.iter().enumerate() {
+        let text = fit(cell, widths[i]);
+        match row.outcome {
+            Some(outcome) if i == STATUS && color => {
+                out.set_color(ColorSpec::new().set_fg(Some(outcome.color())))?;
+                write!(out, "{}", text)?;
+                out.reset()?;
+            }
+            _ => write!(out, "{}", text)?,
+        }
+        if i < last {
+            let padding = widths[i] - text.chars().count() + GAP;
+            write!(out, "{:1$}", "", padding)?;
+        }
+    }
+    writeln!(out)
+}
+
+/// `text`, cut short with an ellipsis if it's wider than `width`.
+fn fit(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut fitted: String = text.chars().take(width.saturating_sub(3)).collect();
+    fitted.push_str("...");
+    fitted
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command::pkg::download::{ArtifactReport,
+                                         ArtifactSource,
+                                         FailedArtifact},
+                hcore::package::{PackageIdent,
+                                 PackageTarget}};
+    use std::str::FromStr;
+    use termcolor::NoColor;
+
+    #[test]
+    fn columns_line_up_within_the_width() {
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let glibc = PackageIdent::from_str("core/glibc/2.27/20190115002733").unwrap();
+        let redis = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let mut report = DownloadReport::default();
+        report.artifacts
+              .push(ArtifactReport { artifact: glibc.archive_name_with_target(target).unwrap(),
+                                     ident: glibc,
+                                     target,
+                                     source: ArtifactSource::Builder,
+                                     signer: String::from("core-20180119235000"),
+                                     bytes_transferred: 0,
+                                     verified: false });
+        report.failed.push(FailedArtifact { ident: redis,
+                                            target,
+                                            error: String::from("timed out") });
+
+        let mut out = NoColor::new(Vec::new());
+        write_table(&mut out, &report, |_| Some(13_002_342), Some(72), true).unwrap();
+        let table = String::from_utf8(out.into_inner()).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.len() <= 72));
+        let status = lines[0].find("STATUS").unwrap();
+        assert_eq!(&lines[1][status..status + 2], "OK");
+        assert_eq!(&lines[2][status..status + 4], "FAIL");
+        assert!(lines[1].contains("12.4 MB"));
+        assert!(lines[2].starts_with("core/redis/4.0.14...  x86_64-linux"));
+    }
+}
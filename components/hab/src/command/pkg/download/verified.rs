@@ -0,0 +1,84 @@
+//! What `hab pkg download` leaves next to each artifact it verifies, so that later runs can take
+//! the artifact as verified for as long as it's unchanged. The artifact's checksum is recorded
+//! along with when it was verified, and a file whose checksum no longer matches is verified
+//! again:
+//!
+//! ```toml
+//! checksum = "e1a1e6b1d7cbb4c2cbb8d3b7c4e51bba9da2b46e9ab6c41b35b7e6b61bd1c2d7"
+//! verified_at = "2019-10-14T09:30:00Z"
+//! ```
+
+use std::{fs,
+          path::{Path,
+                 PathBuf}};
+
+use chrono::{DateTime,
+             Utc};
+
+use crate::{error::Result,
+            hcore::{crypto::hash,
+                    fs::atomic_write}};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Verified {
+    checksum:    String,
+    verified_at: DateTime<Utc>,
+}
+
+/// Where the record for the artifact at `artifact` is kept.
+pub fn sidecar_path(artifact: &Path) -> PathBuf {
+    let mut sidecar = artifact.as_os_str().to_owned();
+    sidecar.push(".verified");
+    PathBuf::from(sidecar)
+}
+
+/// Records that the artifact at `artifact` verified, as it is now.
+pub fn record(artifact: &Path) -> Result<()> {
+    let verified = Verified { checksum:    hash::hash_file(artifact)?,
+                              verified_at: Utc::now(), };
+    atomic_write(&sidecar_path(artifact), toml::ser::to_string(&verified)?)?;
+    Ok(())
+}
+
+/// Whether the artifact at `artifact` verified the last time it was, and hasn't changed since.
+/// A record that can't be read, or that doesn't match, doesn't count.
+pub fn is_current(artifact: &Path) -> bool {
+    let raw = match fs::read_to_string(sidecar_path(artifact)) {
+        Ok(raw) => raw,
+        Err(_) => return false,
+    };
+    let verified: Verified = match toml::from_str(&raw) {
+        Ok(verified) => verified,
+        Err(e) => {
+            debug!("Ignoring the unreadable verification record for {}: {}",
+                   artifact.display(),
+                   e);
+            return false;
+        }
+    };
+    match hash::hash_file(artifact) {
+        Ok(checksum) => checksum == verified.checksum,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn a_changed_artifact_is_no_longer_verified() {
+        let dir = TempDir::new().unwrap();
+        let artifact = dir.path()
+                          .join("core-redis-4.0.14-20190319155852-x86_64-linux.hart");
+        fs::write(&artifact, "HART-1\ncore-20180119235000\n").unwrap();
+        assert!(!is_current(&artifact));
+
+        record(&artifact).unwrap();
+        assert!(is_current(&artifact));
+
+        fs::write(&artifact, "HART-1\nrogue-20190101000000\n").unwrap();
+        assert!(!is_current(&artifact));
+    }
+}
@@ -14,11 +14,32 @@ use crate::{api_client,
             protocol::net,
             sup_client::SrvClientError};
 use handlebars;
+use reqwest::StatusCode;
 use serde_yaml;
 use toml;
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// What `hab` exits with when a command fails, for failures that scripts may want to tell
+/// apart; say, to retry a network failure but not a missing package. Success is 0, and any other
+/// failure is 1.
+pub mod exit_code {
+    /// The package, or an artifact for it, isn't on Builder
+    pub const PACKAGE_NOT_FOUND: i32 = 2;
+    /// A file or directory couldn't be read or written
+    pub const PERMISSION_DENIED: i32 = 3;
+    /// Builder couldn't be reached, or failed to answer
+    pub const NETWORK: i32 = 4;
+    /// An artifact's signature couldn't be verified
+    pub const VERIFICATION: i32 = 5;
+    /// Some of the artifacts of a `hab pkg download --continue-on-error` couldn't be downloaded,
+    /// or aren't in the download directory afterwards
+    pub const PARTIAL_DOWNLOAD: i32 = 6;
+    /// The command was interrupted, by Ctrl-C say, and stopped once what it was in the middle of
+    /// was done
+    pub const INTERRUPTED: i32 = 130;
+}
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum Error {
@@ -31,13 +52,16 @@ pub enum Error {
     CannotRemoveDockerStudio,
     CannotRemoveFromChannel((String, String)),
     CannotRemovePackage(hcore::package::PackageIdent, usize),
+    ChannelNotFound(String /* channels */, String /* origin */),
     CommandNotFoundInPkg((String, String)),
     CryptoCLI(String),
     CtlClient(SrvClientError),
+    DeadlineExceeded(Vec<String> /* what's left */),
     DockerDaemonDown,
     DockerFileSharingNotEnabled,
     DockerImageNotFound(String),
     DockerNetworkDown(String),
+    DownloadsFailed(Vec<String> /* artifacts */),
     EnvJoinPathsError(env::JoinPathsError),
     ExecCommandNotFound(PathBuf),
     FFINulError(ffi::NulError),
@@ -46,6 +70,10 @@ pub enum Error {
     HabitatCore(hcore::Error),
     // Boxed due to clippy::large_enum_variant
     HandlebarsRenderError(Box<handlebars::TemplateRenderError>),
+    IncompleteDownload(Vec<String> /* artifacts */),
+    InsufficientSpace(PathBuf, u64 /* needed */, u64 /* available */),
+    Interrupted(Vec<String> /* what's left */),
+    InvalidIdents(Vec<String> /* what's wrong with each */),
     IO(io::Error),
     JobGroupPromoteOrDemote(api_client::Error, bool /* promote */),
     JobGroupCancel(api_client::Error),
@@ -63,12 +91,62 @@ pub enum Error {
     ScheduleStatus(api_client::Error),
     SubcommandNotSupported(String),
     UnsupportedExportFormat(String),
+    UntrustedSigner(String /* artifact */, String /* signer */),
     TomlDeserializeError(toml::de::Error),
     TomlSerializeError(toml::ser::Error),
     Utf8Error(String),
+    VerificationFailed(Vec<String> /* artifacts */),
+    // The ident and target asked for, and the target of the artifact that came back
+    WrongArtifactTarget(hcore::package::PackageIdent,
+                        hcore::package::PackageTarget,
+                        hcore::package::PackageTarget),
     YamlError(serde_yaml::Error),
 }
 
+impl Error {
+    /// The process exit code for this error. See `exit_code`.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::APIClient(ref e) | Error::HabitatCommon(common::Error::APIClient(ref e)) => {
+                api_client_exit_code(e)
+            }
+            Error::HabitatCommon(common::Error::PackageNotFound(_))
+            | Error::HabitatCore(hcore::Error::PackageNotFound(_))
+            | Error::ChannelNotFound(..) => exit_code::PACKAGE_NOT_FOUND,
+            Error::HabitatCommon(common::Error::PermissionFailed(_))
+            | Error::HabitatCore(hcore::Error::PermissionFailed(_)) => exit_code::PERMISSION_DENIED,
+            Error::IO(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                exit_code::PERMISSION_DENIED
+            }
+            Error::HabitatCommon(common::Error::DownloadFailed(_)) => exit_code::NETWORK,
+            Error::CryptoCLI(_)
+            | Error::HabitatCore(hcore::Error::CryptoError(_))
+            | Error::UntrustedSigner(..)
+            | Error::VerificationFailed(_)
+            | Error::WrongArtifactTarget(..) => exit_code::VERIFICATION,
+            Error::DownloadsFailed(_) | Error::IncompleteDownload(_) => exit_code::PARTIAL_DOWNLOAD,
+            Error::Interrupted(_) => exit_code::INTERRUPTED,
+            _ => 1,
+        }
+    }
+}
+
+fn api_client_exit_code(err: &api_client::Error) -> i32 {
+    match *err {
+        api_client::Error::APIError(StatusCode::NOT_FOUND, _) => exit_code::PACKAGE_NOT_FOUND,
+        api_client::Error::APIError(status, _)
+            if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS =>
+        {
+            exit_code::NETWORK
+        }
+        api_client::Error::ReqwestError(_)
+        | api_client::Error::HabitatHttpClient(_)
+        | api_client::Error::BadResponseBody(_)
+        | api_client::Error::IncompleteDownload(..) => exit_code::NETWORK,
+        _ => 1,
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let msg = match *self {
@@ -92,12 +170,20 @@ impl fmt::Display for Error {
                 format!("Can't remove package: {}. It is a dependency of {} packages",
                         p, c)
             }
+            Error::ChannelNotFound(ref channels, ref origin) => {
+                format!("Channel {} not found in origin {}", channels, origin)
+            }
             Error::CommandNotFoundInPkg((ref p, ref c)) => {
                 format!("`{}' was not found under any 'PATH' directories in the {} package",
                         c, p)
             }
             Error::CryptoCLI(ref e) => e.to_string(),
             Error::CtlClient(ref e) => e.to_string(),
+            Error::DeadlineExceeded(ref left) => {
+                format!("Ran out of time with {} left to do: {}",
+                        left.len(),
+                        left.join(", "))
+            }
             Error::DockerDaemonDown => {
                 "Can not connect to Docker. Is the Docker daemon running?".to_string()
             }
@@ -126,6 +212,11 @@ impl fmt::Display for Error {
                          HAB_DOCKER_STUDIO_IMAGE environment variable.",
                         e)
             }
+            Error::DownloadsFailed(ref failed) => {
+                format!("Couldn't download {} artifacts: {}",
+                        failed.len(),
+                        failed.join(", "))
+            }
             Error::EnvJoinPathsError(ref err) => format!("{}", err),
             Error::ExecCommandNotFound(ref c) => {
                 format!("`{}' was not found on the filesystem or in PATH",
@@ -136,6 +227,27 @@ impl fmt::Display for Error {
             Error::HabitatCommon(ref e) => e.to_string(),
             Error::HabitatCore(ref e) => e.to_string(),
             Error::HandlebarsRenderError(ref e) => e.to_string(),
+            Error::IncompleteDownload(ref missing) => {
+                format!("The download directory is missing {} of the artifacts resolved: {}",
+                        missing.len(),
+                        missing.join(", "))
+            }
+            Error::InsufficientSpace(ref path, needed, available) => {
+                format!("{} needs about {} bytes free, but only has {}",
+                        path.display(),
+                        needed,
+                        available)
+            }
+            Error::Interrupted(ref left) => {
+                format!("Interrupted with {} left to do: {}",
+                        left.len(),
+                        left.join(", "))
+            }
+            Error::InvalidIdents(ref problems) => {
+                format!("Found {} malformed package identifiers:\n    {}",
+                        problems.len(),
+                        problems.join("\n    "))
+            }
             Error::IO(ref err) => format!("{}", err),
             Error::JobGroupPromoteOrDemoteUnprocessable(true) => {
                 "Failed to promote job group, the build job is still in progress".to_string()
@@ -172,9 +284,22 @@ impl fmt::Display for Error {
                 format!("Subcommand `{}' not supported on this operating system", e)
             }
             Error::UnsupportedExportFormat(ref e) => format!("Unsupported export format: {}", e),
+            Error::UntrustedSigner(ref artifact, ref signer) => {
+                format!("{} is signed by {}, which isn't one of the trusted origins",
+                        artifact, signer)
+            }
             Error::TomlDeserializeError(ref e) => format!("Can't deserialize TOML: {}", e),
             Error::TomlSerializeError(ref e) => format!("Can't serialize TOML: {}", e),
             Error::Utf8Error(ref e) => format!("Error processing a string as UTF-8: {}", e),
+            Error::VerificationFailed(ref failed) => {
+                format!("{} artifacts failed verification: {}",
+                        failed.len(),
+                        failed.join(", "))
+            }
+            Error::WrongArtifactTarget(ref ident, wanted, got) => {
+                format!("Builder sent an artifact for {} when {} for {} was asked for",
+                        got, ident, wanted)
+            }
             Error::YamlError(ref e) => format!("{}", e),
         };
         write!(f, "{}", msg)
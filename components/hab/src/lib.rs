@@ -31,6 +31,7 @@ pub mod config;
 pub mod error;
 mod exec;
 pub mod license;
+pub mod logging;
 pub mod scaffolding;
 
 pub const PRODUCT: &str = "hab";
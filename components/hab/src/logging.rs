@@ -0,0 +1,145 @@
+//! Sets up `hab`'s logging, which goes to stderr as usual unless the `HAB_LOG_FORMAT`
+//! environment variable is `json`. Then each record is a line of JSON instead, for log
+//! aggregation to take apart rather than scrape:
+//!
+//! ```json
+//! {"timestamp":"2019-10-14T09:30:00.000Z","level":"DEBUG","module":"hab::command::pkg::download","message":"Verified /hab/cache/artifacts/core-redis-4.0.14-20190319155852-x86_64-linux.hart","ident":"core/redis/4.0.14/20190319155852","target":"x86_64-linux","phase":"download"}
+//! ```
+//!
+//! A record logged while a download is working on an artifact carries its ident, target and
+//! phase, from `with_artifact`, and one logged with `log_duration` how long that phase took.
+
+use std::{cell::RefCell,
+          env,
+          io::{self,
+               Write}};
+
+use chrono::{SecondsFormat,
+             Utc};
+use log::Record;
+use serde_json::{Map,
+                 Value};
+
+use crate::hcore::package::{PackageIdent,
+                            PackageTarget};
+
+pub const LOG_FORMAT_ENVVAR: &str = "HAB_LOG_FORMAT";
+
+/// What's being worked on, for the records logged in the meantime.
+#[derive(Clone, Default)]
+struct Context {
+    ident:         Option<String>,
+    target:        Option<String>,
+    phase:         Option<&'static str>,
+    duration_secs: Option<f64>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/// Starts logging, as JSON lines if `HAB_LOG_FORMAT` says so. The level is taken from
+/// `RUST_LOG` either way.
+pub fn init() {
+    match env::var(LOG_FORMAT_ENVVAR) {
+        Ok(ref format) if format == "json" => {
+            env_logger::Builder::from_default_env().format(|buf, record| write_json(buf, record))
+                                                   .init()
+        }
+        _ => env_logger::init(),
+    }
+}
+
+/// Runs `f` with the records it logs on this thread marked as being about `ident` for `target`
+/// in `phase`.
+pub fn with_artifact<F, T>(ident: &PackageIdent,
+                           target: PackageTarget,
+                           phase: &'static str,
+                           f: F)
+                           -> T
+    where F: FnOnce() -> T
+{
+    let context = Context { ident:         Some(ident.to_string()),
+                            target:        Some(target.to_string()),
+                            phase:         Some(phase),
+                            duration_secs: None, };
+    with_context(context, f)
+}
+
+/// Logs that `phase` took `secs` seconds.
+pub fn log_duration(phase: &'static str, secs: f64) {
+    let context = Context { phase: Some(phase),
+                            duration_secs: Some(secs),
+                            ..Context::default() };
+    with_context(context, || debug!("The {} phase took {:.3}s", phase, secs));
+}
+
+fn with_context<F, T>(context: Context, f: F) -> T
+    where F: FnOnce() -> T
+{
+    let outer = CONTEXT.with(|current| current.replace(context));
+    let result = f();
+    CONTEXT.with(|current| current.replace(outer));
+    result
+}
+
+fn to_json(record: &Record<'_>) -> Value {
+    let mut fields = Map::new();
+    fields.insert(String::from("timestamp"),
+                  Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+    fields.insert(String::from("level"),
+                  Value::from(record.level().to_string()));
+    fields.insert(String::from("module"), Value::from(record.target()));
+    fields.insert(String::from("message"),
+                  Value::from(record.args().to_string()));
+    CONTEXT.with(|context| {
+               let context = context.borrow();
+               if let Some(ref ident) = context.ident {
+                   fields.insert(String::from("ident"), Value::from(ident.as_str()));
+               }
+               if let Some(ref target) = context.target {
+                   fields.insert(String::from("target"), Value::from(target.as_str()));
+               }
+               if let Some(phase) = context.phase {
+                   fields.insert(String::from("phase"), Value::from(phase));
+               }
+               if let Some(secs) = context.duration_secs {
+                   fields.insert(String::from("duration_secs"), Value::from(secs));
+               }
+           });
+    Value::Object(fields)
+}
+
+fn write_json<W: Write>(out: &mut W, record: &Record<'_>) -> io::Result<()> {
+    writeln!(out, "{}", to_json(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use std::str::FromStr;
+
+    #[test]
+    fn records_about_an_artifact_say_which() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let line = with_artifact(&ident, target, "verify", || {
+            let mut line = Vec::new();
+            write_json(&mut line,
+                       &Record::builder().args(format_args!("Verified it"))
+                                         .level(Level::Debug)
+                                         .target("hab::command::pkg::download")
+                                         .build()).unwrap();
+            line
+        });
+        let json: Value = serde_json::from_slice(&line).unwrap();
+
+        assert_eq!(json["message"], "Verified it");
+        assert_eq!(json["level"], "DEBUG");
+        assert_eq!(json["ident"], "core/redis/4.0.14/20190319155852");
+        assert_eq!(json["target"], "x86_64-linux");
+        assert_eq!(json["phase"], "verify");
+        assert!(json.get("duration_secs").is_none());
+    }
+}
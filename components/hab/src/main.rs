@@ -7,15 +7,17 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+use chrono::{DateTime,
+             Utc};
 use clap::{ArgMatches,
            Shell};
-use env_logger;
 use futures::prelude::*;
 use glob::glob_with;
 use hab::{cli::{self,
                 parse_optional_arg},
           command::{self,
-                    pkg::list::ListingType},
+                    pkg::{download::DownloadBuilder,
+                          list::ListingType}},
           config::{self,
                    Config},
           error::{Error,
@@ -28,7 +30,8 @@ use hab::{cli::{self,
           ORIGIN_ENVVAR,
           PRODUCT,
           VERSION};
-use habitat_api_client::BuildOnUpload;
+use habitat_api_client::{BuildOnUpload,
+                         ClientIdentity};
 use habitat_common::{self as common,
                      cli::{cache_key_path_from_matches,
                            FS_ROOT},
@@ -72,8 +75,10 @@ use habitat_sup_protocol::{self as sup_proto,
                            types::*};
 use pbr;
 use std::{env,
-          ffi::OsString,
-          fs::File,
+          ffi::{OsStr,
+                OsString},
+          fs::{self,
+               File},
           io::{self,
                prelude::*,
                Read},
@@ -83,10 +88,12 @@ use std::{env,
           process,
           result,
           str::FromStr,
-          thread};
+          thread,
+          time::Duration};
 use tabwriter::TabWriter;
 use termcolor::{self,
                 Color,
+                ColorChoice,
                 ColorSpec};
 
 /// Makes the --org CLI param optional when this env var is set
@@ -107,12 +114,12 @@ lazy_static! {
 }
 
 fn main() {
-    env_logger::init();
+    hab::logging::init();
     let mut ui = UI::default_with_env();
     let flags = FeatureFlag::from_env(&mut ui);
     if let Err(e) = start(&mut ui, flags) {
-        ui.fatal(e).unwrap();
-        std::process::exit(1)
+        ui.fatal(&e).unwrap();
+        std::process::exit(e.exit_code())
     }
 }
 
@@ -539,30 +546,179 @@ fn sub_pkg_dependencies(m: &ArgMatches<'_>) -> Result<()> {
 }
 
 fn sub_pkg_download(ui: &mut UI, m: &ArgMatches<'_>, _feature_flags: FeatureFlag) -> Result<()> {
-    let token = maybe_auth_token(&m);
+    if m.is_present("JSON") {
+        // Errors have to be JSON too, so they can't be left for `main` to print
+        let mut ui = command::pkg::download::JsonUi::default();
+        if let Err(e) = pkg_download(&mut ui, m) {
+            ui.fatal(&e)?;
+            process::exit(e.exit_code());
+        }
+        return Ok(());
+    }
+    if let Some(coloring) = coloring_from_matches(m) {
+        *ui = UI::default_with_coloring(coloring);
+    }
+    pkg_download(ui, m)
+}
+
+fn pkg_download<U>(ui: &mut U, m: &ArgMatches<'_>) -> Result<()>
+    where U: UIWriter
+{
+    let token = match m.value_of("TOKEN_FILE") {
+        Some(token_file) => Some(token_from_file(token_file)?),
+        None => maybe_auth_token(&m),
+    };
     let url = bldr_url_from_matches(&m)?;
     let download_dir = download_dir_from_matches(m);
-    let channel = channel_from_matches_or_default(m);
-
-    let mut install_sources = idents_from_matches(m)?;
-    let mut install_sources_from_file = idents_from_file_matches(m)?;
-    install_sources_from_file.append(&mut install_sources);
+    if m.is_present("VERIFY_ONLY") {
+        init();
+        return command::pkg::download::verify_only(ui,
+                                                   download_dir.as_ref(),
+                                                   layout_from_matches(m));
+    }
+    let mut channels = vec![channel_from_matches_or_default(m)];
+    channels.extend(m.values_of("FALLBACK_CHANNEL")
+                     .unwrap_or_default()
+                     .map(ChannelIdent::from));
+    let (ident_files, manifests) = input_files_from_matches(m)?;
+    let execution_strategy = if m.is_present("DRYRUN") {
+        command::pkg::ExecutionStrategy::DryRun
+    } else {
+        command::pkg::ExecutionStrategy::Run
+    };
 
-    let target = target_from_matches(m)?;
-    let verify = verify_from_matches(m);
+    let mut download =
+        DownloadBuilder::new(&url, PRODUCT, VERSION)
+            .channels(channels)
+            .no_deps(m.is_present("NO_DEPS"))
+            .build_deps(m.is_present("INCLUDE_BUILD_DEPS"))
+            .keep_versions(keep_versions_from_matches(m))
+            .layout(layout_from_matches(m))
+            .verify(verify_from_matches(m))
+            .require_signatures(m.is_present("REQUIRE_SIGNATURES"))
+            .download_keys(!m.is_present("NO_KEYS"))
+            .force(m.is_present("FORCE"))
+            .revalidate(m.is_present("REVALIDATE"))
+            .by_checksum(m.is_present("BY_CHECKSUM"))
+            .use_local_cache(!m.is_present("NO_LOCAL_CACHE"))
+            .fail_on_unsupported(m.is_present("FAIL_ON_UNSUPPORTED"))
+            .warn_on_wrong_target(m.is_present("WARN_ON_WRONG_TARGET"))
+            .continue_on_error(m.is_present("CONTINUE_ON_ERROR"))
+            .check_space(!m.is_present("SKIP_SPACE_CHECK"))
+            .precheck(m.is_present("PRECHECK"))
+            .resolve_jobs(resolve_jobs_from_matches(m))
+            .concurrent_downloads(concurrent_downloads_from_matches(m))
+            .verify_all(m.is_present("VERIFY_ALL"))
+            .reverify(m.is_present("REVERIFY"))
+            .remove_if_unverified(m.is_present("REMOVE_IF_UNVERIFIED"))
+            .clean_on_failure(m.is_present("CLEAN_ON_FAILURE"))
+            .incremental(m.is_present("INCREMENTAL"))
+            .with_metadata(m.is_present("WITH_METADATA"))
+            .retry_policy(retry_policy_from_matches(m))
+            .request_timeout(request_timeout_from_matches(m))
+            .quiet(m.is_present("QUIET"))
+            .progress_format(progress_format_from_matches(m))
+            .overall_progress(m.is_present("OVERALL_PROGRESS"))
+            .json(m.is_present("JSON"))
+            .stdout(m.is_present("STDOUT"))
+            .execution_strategy(execution_strategy);
+    for ident in idents_from_matches(m)? {
+        download = download.ident(ident);
+    }
+    for ident_file in &ident_files {
+        download = download.ident_file(ident_file);
+    }
+    for manifest in &manifests {
+        download = download.manifest(manifest);
+    }
+    for fallback_url in m.values_of("FALLBACK_BLDR_URL").unwrap_or_default() {
+        download = download.fallback_url(fallback_url);
+    }
+    for ident in excludes_from_matches(m) {
+        download = download.exclude(ident);
+    }
+    for ident in exclude_deps_of_from_matches(m) {
+        download = download.exclude_deps_of(ident);
+    }
+    for origin in m.values_of("TRUSTED_ORIGIN").unwrap_or_default() {
+        download = download.trusted_origin(origin);
+    }
+    if let Some(target) = m.value_of("PKG_TARGET") {
+        download = download.target(PackageTarget::from_str(target)?);
+    }
+    if let Some(source) = m.value_of("SOURCE") {
+        download = download.source(Path::new(source));
+    }
+    if let Some(proxy) = m.value_of("PROXY") {
+        download = download.proxy(proxy);
+    }
+    if let Some(client_identity) = client_identity_from_matches(m) {
+        download = download.client_identity(client_identity);
+    }
+    if let Some(ca_bundle) = m.value_of("CA_BUNDLE") {
+        download = download.ca_bundle(Path::new(ca_bundle));
+    }
+    if let Some(user_agent) = m.value_of("USER_AGENT") {
+        download = download.user_agent(user_agent);
+    }
+    if let Some(filter_url) = m.value_of("FILTER_BLDR_URL") {
+        download = download.filter_url(filter_url);
+    }
+    if let Some(max_depth) = max_depth_from_matches(m) {
+        download = download.max_depth(max_depth);
+    }
+    if let Some(since) = since_from_matches(m) {
+        download = download.since(since);
+    }
+    if let Some(download_dir) = download_dir {
+        download = download.download_path(download_dir);
+    }
+    if let Some(ref token) = token {
+        download = download.token(token);
+    }
+    if let Some(keys_from) = m.value_of("KEYS_FROM") {
+        download = download.keys_from(Path::new(keys_from));
+    }
+    if let Some(jobs) = m.value_of("VERIFY_JOBS") {
+        // validated by clap
+        download = download.verify_jobs(jobs.parse().expect("valid VERIFY_JOBS"));
+    }
+    if let Some(bytes_per_sec) = max_bandwidth_from_matches(m) {
+        download = download.max_bandwidth(bytes_per_sec);
+    }
+    if let Some(rate) = m.value_of("API_RATE_LIMIT") {
+        // validated by clap
+        download = download.api_rate_limit(rate.parse().expect("valid API_RATE_LIMIT"));
+    }
+    if let Some(lockfile) = lockfile_from_matches(m) {
+        download = download.lockfile(lockfile);
+    }
+    if let Some(graph) = m.value_of("GRAPH") {
+        download = download.graph(Path::new(graph));
+    }
+    if let Some(report_path) = m.value_of("REPORT_JSON") {
+        download = download.report_path(Path::new(report_path));
+    }
+    if let Some(sbom) = m.value_of("SBOM") {
+        download = download.sbom(Path::new(sbom));
+    }
+    if let Some(checksums) = checksums_from_matches(m) {
+        download = download.checksums(checksums);
+    }
+    if let Some(bundle) = m.value_of("BUNDLE") {
+        download = download.bundle(Path::new(bundle));
+    }
+    if let Some(prune) = prune_from_matches(m) {
+        download = download.prune(prune);
+    }
+    if let Some(secs) = m.value_of("DEADLINE") {
+        // validated by clap
+        download = download.deadline(Duration::from_secs(secs.parse().expect("valid DEADLINE")));
+    }
 
     init();
 
-    command::pkg::download::start(ui,
-                                  &url,
-                                  &channel,
-                                  PRODUCT,
-                                  VERSION,
-                                  install_sources_from_file,
-                                  target,
-                                  download_dir.as_ref(),
-                                  token.as_ref().map(String::as_str),
-                                  verify)?;
+    command::pkg::download::start(ui, download)?;
     Ok(())
 }
 
@@ -1518,6 +1674,26 @@ fn ctl_secret_key(config: &Config) -> Result<String> {
     }
 }
 
+/// Reads an auth token from `path`, or stdin if it's `-`. Only the first line is the token, so a
+/// trailing newline doesn't matter.
+fn token_from_file(path: &str) -> Result<String> {
+    let contents = if path == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents).map(|_| contents)
+    } else {
+        fs::read_to_string(path)
+    };
+    let contents = contents.map_err(|e| {
+                               Error::ArgumentError(format!("Couldn't read the auth token from \
+                                                             {}: {}",
+                                                            path, e))
+                           })?;
+    match contents.lines().next().map(str::trim) {
+        Some(token) if !token.is_empty() => Ok(token.to_string()),
+        _ => Err(Error::ArgumentError(format!("No auth token in {}", path))),
+    }
+}
+
 /// Check to see if an auth token exists and convert it to a string slice if it does. Unlike
 /// auth_token_param_or_env, it's ok for no auth token to be present here. This is useful for
 /// commands that can optionally take an auth token for operating on private packages.
@@ -1663,17 +1839,33 @@ fn idents_from_matches(matches: &ArgMatches<'_>) -> Result<Vec<PackageIdent>> {
     }
 }
 
-fn idents_from_file_matches(matches: &ArgMatches<'_>) -> Result<Vec<PackageIdent>> {
-    let mut sources: Vec<PackageIdent> = Vec::new();
-
-    if let Some(files) = matches.values_of("PKG_IDENT_FILE") {
-        for filename in files {
-            let mut packages_from_file =
-                habitat_common::cli::file_into_idents(&filename.to_string())?;
-            sources.append(&mut packages_from_file);
+/// The `--file` ident files, and the `--manifest` manifests. A directory given with `--file`
+/// stands for the `.txt` ident files and `.toml` manifests in it.
+fn input_files_from_matches(matches: &ArgMatches<'_>) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut ident_files = Vec::new();
+    let mut manifests: Vec<PathBuf> = matches.values_of("MANIFEST")
+                                             .unwrap_or_default()
+                                             .map(PathBuf::from)
+                                             .collect();
+    for path in matches.values_of("PKG_IDENT_FILE").unwrap_or_default() {
+        let path = PathBuf::from(path);
+        if !path.is_dir() {
+            ident_files.push(path);
+            continue;
+        }
+        let mut entries = fs::read_dir(&path)?.map(|entry| entry.map(|e| e.path()))
+                                              .collect::<io::Result<Vec<_>>>()?;
+        // So that the idents come in the same order every time
+        entries.sort();
+        for entry in entries.into_iter().filter(|e| e.is_file()) {
+            match entry.extension().and_then(OsStr::to_str) {
+                Some("txt") => ident_files.push(entry),
+                Some("toml") => manifests.push(entry),
+                _ => debug!("Ignoring {} in {}", entry.display(), path.display()),
+            }
         }
     }
-    Ok(sources)
+    Ok((ident_files, manifests))
 }
 
 fn verify_from_matches(matches: &ArgMatches<'_>) -> bool { matches.is_present("VERIFY") }
@@ -1682,6 +1874,127 @@ fn download_dir_from_matches(matches: &ArgMatches<'_>) -> Option<PathBuf> {
     matches.value_of("DOWNLOAD_DIRECTORY").map(PathBuf::from)
 }
 
+fn keep_versions_from_matches(matches: &ArgMatches<'_>) -> usize {
+    matches.value_of("KEEP_VERSIONS")
+           .map_or(1, |n| n.parse().expect("valid KEEP_VERSIONS")) // validated by clap
+}
+
+fn max_depth_from_matches(matches: &ArgMatches<'_>) -> Option<usize> {
+    matches.value_of("MAX_DEPTH")
+           .map(|n| n.parse().expect("valid MAX_DEPTH")) // validated by clap
+}
+
+fn resolve_jobs_from_matches(matches: &ArgMatches<'_>) -> usize {
+    matches.value_of("RESOLVE_JOBS")
+           .map(|jobs| jobs.parse().expect("valid RESOLVE_JOBS")) // validated by clap
+           .unwrap_or(command::pkg::download::DEFAULT_RESOLVE_JOBS)
+}
+
+fn concurrent_downloads_from_matches(matches: &ArgMatches<'_>) -> usize {
+    matches.value_of("CONCURRENT_DOWNLOADS")
+           .map(|jobs| jobs.parse().expect("valid CONCURRENT_DOWNLOADS")) // validated by clap
+           .unwrap_or(command::pkg::download::DEFAULT_CONCURRENT_DOWNLOADS)
+}
+
+/// The bandwidth limit in bytes per second, from one given in KB/s.
+fn max_bandwidth_from_matches(matches: &ArgMatches<'_>) -> Option<u64> {
+    // validated by clap
+    matches.value_of("MAX_BANDWIDTH")
+           .map(|kbps| {
+               kbps.parse::<u64>()
+                   .ok()
+                   .and_then(|kbps| kbps.checked_mul(1024))
+                   .expect("valid MAX_BANDWIDTH")
+           })
+}
+
+fn request_timeout_from_matches(matches: &ArgMatches<'_>) -> Duration {
+    // validated by clap
+    matches.value_of("REQUEST_TIMEOUT")
+           .map(|secs| Duration::from_secs(secs.parse().expect("valid REQUEST_TIMEOUT")))
+           .unwrap_or(command::pkg::download::DEFAULT_REQUEST_TIMEOUT)
+}
+
+fn client_identity_from_matches(matches: &ArgMatches<'_>) -> Option<ClientIdentity> {
+    let password = matches.value_of("CLIENT_CERT_PASSWORD").unwrap_or_default();
+    matches.value_of("CLIENT_CERT").map(|path| {
+                                       ClientIdentity { pkcs12:   PathBuf::from(path),
+                                                        password: password.to_string(), }
+                                   })
+}
+
+fn lockfile_from_matches<'a>(matches: &'a ArgMatches<'_>)
+                             -> Option<command::pkg::download::LockfileMode<'a>> {
+    matches.value_of("LOCKFILE").map(|path| {
+                                    if matches.is_present("FROZEN") {
+                                        command::pkg::download::LockfileMode::Frozen(Path::new(path))
+                                    } else {
+                                        command::pkg::download::LockfileMode::Write(Path::new(path))
+                                    }
+                                })
+}
+
+fn checksums_from_matches(matches: &ArgMatches<'_>)
+                          -> Option<command::pkg::download::ChecksumScope> {
+    if matches.is_present("CHECKSUM_KEYS") {
+        Some(command::pkg::download::ChecksumScope::ArtifactsAndKeys)
+    } else if matches.is_present("CHECKSUMS") {
+        Some(command::pkg::download::ChecksumScope::Artifacts)
+    } else {
+        None
+    }
+}
+
+fn prune_from_matches(matches: &ArgMatches<'_>) -> Option<command::pkg::download::PrunePolicy> {
+    let dry_run = matches.is_present("PRUNE_DRY_RUN");
+    if matches.is_present("PRUNE") || dry_run {
+        Some(command::pkg::download::PrunePolicy { keys: matches.is_present("PRUNE_KEYS"),
+                                                   dry_run })
+    } else {
+        None
+    }
+}
+
+fn retry_policy_from_matches(matches: &ArgMatches<'_>) -> command::pkg::download::RetryPolicy {
+    let mut policy = command::pkg::download::RetryPolicy::default();
+    if let Some(retries) = matches.value_of("RETRIES") {
+        policy.retries = retries.parse().expect("valid RETRIES"); // validated by clap
+    }
+    if let Some(ms) = matches.value_of("RETRY_WAIT_MS") {
+        let ms = ms.parse().expect("valid RETRY_WAIT_MS"); // validated by clap
+        policy.wait = Duration::from_millis(ms);
+    }
+    if let Some("exponential") = matches.value_of("RETRY_BACKOFF") {
+        policy.backoff = command::pkg::download::RetryBackoff::Exponential;
+    }
+    policy
+}
+
+fn coloring_from_matches(matches: &ArgMatches<'_>) -> Option<ColorChoice> {
+    match matches.value_of("COLOR") {
+        Some("always") => Some(ColorChoice::Always),
+        Some("never") => Some(ColorChoice::Never),
+        // The UI already colors automatically
+        _ => None,
+    }
+}
+
+fn layout_from_matches(matches: &ArgMatches<'_>) -> command::pkg::download::DownloadLayout {
+    match matches.value_of("LAYOUT") {
+        Some("flat") => command::pkg::download::DownloadLayout::Flat,
+        Some("by-channel") => command::pkg::download::DownloadLayout::ByChannel,
+        _ => command::pkg::download::DownloadLayout::Nested,
+    }
+}
+
+fn progress_format_from_matches(matches: &ArgMatches<'_>)
+                                -> command::pkg::download::ProgressFormat {
+    match matches.value_of("PROGRESS_FORMAT") {
+        Some("ndjson") => command::pkg::download::ProgressFormat::Ndjson,
+        _ => command::pkg::download::ProgressFormat::Human,
+    }
+}
+
 fn excludes_from_matches(matches: &ArgMatches<'_>) -> Vec<PackageIdent> {
     matches
         .values_of("EXCLUDE")
@@ -1690,6 +2003,21 @@ fn excludes_from_matches(matches: &ArgMatches<'_>) -> Vec<PackageIdent> {
         .collect()
 }
 
+fn exclude_deps_of_from_matches(matches: &ArgMatches<'_>) -> Vec<PackageIdent> {
+    matches
+        .values_of("EXCLUDE_DEPS_OF")
+        .unwrap_or_default()
+        .map(|i| PackageIdent::from_str(i).unwrap()) // unwrap safe as we've validated the input
+        .collect()
+}
+
+fn since_from_matches(matches: &ArgMatches<'_>) -> Option<DateTime<Utc>> {
+    matches.value_of("SINCE").map(|since| {
+                                 DateTime::parse_from_rfc3339(since).expect("valid SINCE")
+                                                                    .with_timezone(&Utc)
+                             })
+}
+
 fn handle_ctl_reply(reply: &SrvMessage) -> result::Result<(), SrvClientError> {
     let mut progress_bar = pbr::ProgressBar::<io::Stdout>::new(0);
     progress_bar.set_units(pbr::Units::Bytes);
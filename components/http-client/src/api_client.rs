@@ -1,5 +1,5 @@
-use std::{fs,
-          iter::FromIterator,
+use std::{fmt,
+          fs,
           path::{Path,
                  PathBuf},
           str::FromStr,
@@ -10,6 +10,7 @@ use reqwest::{header::{HeaderMap,
                        CONNECTION,
                        USER_AGENT},
               Certificate,
+              Identity,
               IntoUrl,
               Proxy,
               RequestBuilder};
@@ -45,6 +46,46 @@ pub struct ApiClient {
     inner: reqwest::Client,
 }
 
+/// Settings for the HTTP client underlying an `ApiClient`, for when the defaults won't do.
+#[derive(Clone, Debug, Default)]
+pub struct ApiClientOptions {
+    /// A proxy to send every request through, instead of any the environment configures
+    pub proxy:           Option<String>,
+    /// A certificate for the client to authenticate to the server with
+    pub client_identity: Option<ClientIdentity>,
+    /// A PEM file of CA certificates to trust besides the usual ones, for a server whose
+    /// certificate is signed by a private CA
+    pub ca_bundle:       Option<PathBuf>,
+    /// The User-Agent to send instead of the one made from the product and version, for telling
+    /// this client's requests apart from any other's
+    pub user_agent:      Option<String>,
+    /// Keep connections open between requests, rather than closing each one when its response
+    /// is done. Only worth it for a client that makes many requests; see `with_options`.
+    pub keep_alive:      bool,
+    /// The most idle connections to keep open to the server, when keeping them alive
+    pub max_idle:        Option<usize>,
+    /// How long a request can take before it fails, instead of `HAB_CLIENT_SOCKET_TIMEOUT` or
+    /// its default
+    pub timeout:         Option<Duration>,
+}
+
+/// A client certificate and its private key, in a PKCS #12 archive. The TLS implementation we
+/// use can't read PEM ones, which `openssl pkcs12 -export` will convert.
+#[derive(Clone)]
+pub struct ClientIdentity {
+    pub pkcs12:   PathBuf,
+    pub password: String,
+}
+
+// Keeps the password out of debug logs
+impl fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientIdentity")
+         .field("pkcs12", &self.pkcs12)
+         .finish()
+    }
+}
+
 impl ApiClient {
     /// Creates and returns a new `ApiClient` instance.
     ///
@@ -63,19 +104,27 @@ impl ApiClient {
                   fs_root_path: Option<&Path>)
                   -> Result<Self>
         where T: IntoUrl
+    {
+        Self::with_options(endpoint,
+                           product,
+                           version,
+                           fs_root_path,
+                           &ApiClientOptions::default())
+    }
+
+    /// Like `new`, but with `options` overriding the defaults.
+    pub fn with_options<T>(endpoint: T,
+                           product: &str,
+                           version: &str,
+                           fs_root_path: Option<&Path>,
+                           options: &ApiClientOptions)
+                           -> Result<Self>
+        where T: IntoUrl
     {
         let endpoint = endpoint.into_url().map_err(Error::ReqwestError)?;
 
-        let timeout_in_secs = match env::var("HAB_CLIENT_SOCKET_TIMEOUT") {
-            Ok(t) => {
-                match t.parse::<u64>() {
-                    Ok(n) => n,
-                    Err(_) => CLIENT_SOCKET_RW_TIMEOUT_SEC,
-                }
-            }
-            Err(_) => CLIENT_SOCKET_RW_TIMEOUT_SEC,
-        };
-        debug!("Client socket timeout: {} secs", timeout_in_secs);
+        let timeout = options.timeout.unwrap_or_else(timeout_from_env);
+        debug!("Client socket timeout: {:?}", timeout);
 
         let skip_cert_verify = env::var("HAB_SSL_CERT_VERIFY_NONE").is_ok();
         debug!("Skip cert verification: {}", skip_cert_verify);
@@ -87,26 +136,60 @@ impl ApiClient {
         // closed until the process exits. Until the process exits, these connections
         // remain in CLOSE_WAIT. Since this ApiClient is created fresh from CLI
         // commands, we are not taking advantage of keep-alive anyways so setting
-        // the Connection header to close should not have adverse effects.
-        let headers = HeaderMap::from_iter(vec![
-            (USER_AGENT, user_agent(product, version)?),
-            (
-                CONNECTION,
-                HeaderValue::from_str("close").expect("Valid Connection header"),
-            ),
-        ].into_iter());
-
-        let mut client = reqwest::Client::builder().proxy(proxy_for(&endpoint)?)
+        // the Connection header to close should not have adverse effects. A client that's made
+        // once and used for many requests can opt back in to keep-alive.
+        let mut headers = HeaderMap::new();
+        let user_agent = match options.user_agent {
+            Some(ref ua) => {
+                debug!("User-Agent: {}", ua);
+                HeaderValue::from_str(ua).map_err(|_| Error::InvalidUserAgent(ua.clone()))?
+            }
+            None => user_agent(product, version)?,
+        };
+        headers.insert(USER_AGENT, user_agent);
+        if !options.keep_alive {
+            headers.insert(CONNECTION,
+                           HeaderValue::from_str("close").expect("Valid Connection header"));
+        }
+
+        let proxy = match options.proxy {
+            Some(ref proxy_url) => {
+                debug!("Setting proxy for all requests to {}", proxy_url);
+                Proxy::all(proxy_url.as_str())?
+            }
+            None => proxy_for(&endpoint)?,
+        };
+
+        let mut client = reqwest::Client::builder().proxy(proxy)
                                                    .default_headers(headers)
-                                                   .timeout(Duration::from_secs(timeout_in_secs))
+                                                   .timeout(timeout)
                                                    .danger_accept_invalid_certs(skip_cert_verify);
 
+        if let (true, Some(max_idle)) = (options.keep_alive, options.max_idle) {
+            debug!("Keeping up to {} idle connections to {}",
+                   max_idle, endpoint);
+            client = client.max_idle_per_host(max_idle);
+        }
+
+        if let Some(ref identity) = options.client_identity {
+            debug!("Using client certificate {}", identity.pkcs12.display());
+            let der = fs::read(&identity.pkcs12)?;
+            client = client.identity(Identity::from_pkcs12_der(&der, &identity.password)?);
+        }
+
         client =
             certificates(fs_root_path)?.into_iter()
                                        .fold(client, |client, cert| {
                                            client.add_root_certificate(cert)
                                        });
 
+        if let Some(ref ca_bundle) = options.ca_bundle {
+            debug!("Trusting the CA certificates in {}", ca_bundle.display());
+            for cert in certs_from_bundle(ca_bundle)? {
+                client = client.add_root_certificate(cert);
+            }
+        }
+
         Ok(ApiClient { inner: client.build()?,
                        endpoint })
     }
@@ -206,6 +289,20 @@ impl ApiClient {
     }
 }
 
+/// The request timeout set by `HAB_CLIENT_SOCKET_TIMEOUT`, or the default one.
+fn timeout_from_env() -> Duration {
+    let timeout_in_secs = match env::var("HAB_CLIENT_SOCKET_TIMEOUT") {
+        Ok(t) => {
+            match t.parse::<u64>() {
+                Ok(n) => n,
+                Err(_) => CLIENT_SOCKET_RW_TIMEOUT_SEC,
+            }
+        }
+        Err(_) => CLIENT_SOCKET_RW_TIMEOUT_SEC,
+    };
+    Duration::from_secs(timeout_in_secs)
+}
+
 fn proxy_for(url: &Url) -> reqwest::Result<Proxy> {
     trace!("Checking proxy for url: {:?}", url);
 
@@ -356,6 +453,28 @@ fn process_cert_file(certificates: &mut Vec<Certificate>, file_path: &Path) {
     }
 }
 
+/// Unlike the files `certificates` reads, a CA bundle is asked for by name, so it's an error for
+/// it not to hold a certificate. `Certificate::from_pem` only reads the first one in a file, so
+/// each is handed to it separately.
+fn certs_from_bundle(file_path: &Path) -> Result<Vec<Certificate>> {
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let buf = fs::read(file_path)?;
+    let pem = String::from_utf8_lossy(&buf);
+    let certs = pem.split(END)
+                   .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+                   .map(|block| {
+                       Certificate::from_pem(format!("{}{}\n", block.trim_start(), END).as_bytes())
+                           .map_err(Error::ReqwestError)
+                   })
+                   .collect::<Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        Ok(vec![Certificate::from_der(&buf).map_err(Error::ReqwestError)?])
+    } else {
+        Ok(certs)
+    }
+}
+
 fn cert_from_file(file_path: &Path) -> Result<Certificate> {
     let buf = fs::read(file_path)?;
 
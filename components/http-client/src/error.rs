@@ -16,6 +16,7 @@ pub enum Error {
     HabitatCore(hab_core::Error),
     ReqwestError(reqwest::Error),
     IO(io::Error),
+    InvalidUserAgent(String),
     Json(serde_json::Error),
     UrlParseError(url::ParseError),
 }
@@ -26,6 +27,7 @@ impl fmt::Display for Error {
             Error::HabitatCore(ref e) => format!("{}", e),
             Error::ReqwestError(ref err) => format!("{}", err),
             Error::IO(ref e) => format!("{}", e),
+            Error::InvalidUserAgent(ref ua) => format!("'{}' isn't a valid User-Agent header", ua),
             Error::Json(ref e) => format!("{}", e),
             Error::UrlParseError(ref e) => format!("{}", e),
         };
@@ -4,6 +4,8 @@ extern crate log;
 mod api_client;
 mod error;
 
-pub use crate::{api_client::ApiClient,
+pub use crate::{api_client::{ApiClient,
+                             ApiClientOptions,
+                             ClientIdentity},
                 error::{Error,
                         Result}};